@@ -57,6 +57,7 @@ func main() {
 		config.REDIS_TOPIC_CANDLESTICK_EXT,
 		config.REDIS_TOPIC_CMC_GLOBAL_METRICS,
 		config.REDIS_TOPIC_FUNDING_RATE_PARSED,
+		config.REDIS_TOPIC_BOOK_STATS,
 	)
 
 	// Consume messages.
@@ -179,6 +180,40 @@ func handleMessage(msg *redis.Message, writeAPI api.WriteAPI) {
 			)
 			writeAPI.WritePoint(p)
 		}
+	case config.REDIS_TOPIC_BOOK_STATS:
+		{
+			book_stats := make(map[string]interface{})
+			err := json.Unmarshal([]byte(msg.Payload), &book_stats)
+			if err != nil {
+				log.Println(err)
+				return
+			}
+			pair := book_stats["pair"].(string)
+			arr := strings.Split(pair, "/")
+
+			// same tags as candlestick_ext
+			tags := map[string]string{
+				"exchange":    book_stats["exchange"].(string),
+				"market_type": book_stats["market_type"].(string),
+				"symbol":      book_stats["symbol"].(string),
+				"pair":        book_stats["pair"].(string),
+				"base":        arr[0],
+				"quote":       arr[1],
+				"bar_size":    strconv.Itoa(int(book_stats["bar_size"].(float64))),
+			}
+			delete(book_stats, "exchange")
+			delete(book_stats, "market_type")
+			delete(book_stats, "symbol")
+			delete(book_stats, "pair")
+			delete(book_stats, "bar_size")
+
+			p := influxdb2.NewPoint("book_stats",
+				tags,
+				book_stats,
+				utils.FromUnixMilli(int64(book_stats["timestamp"].(float64))),
+			)
+			writeAPI.WritePoint(p)
+		}
 	default:
 		log.Fatalf("Unknown channel %s", msg.Channel)
 	}
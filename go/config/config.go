@@ -10,3 +10,4 @@ const REDIS_TOPIC_ETH_GAS_PRICE = REDIS_TOPIC_PREFIX + "eth_gas_price"
 const REDIS_COINSIGNAL_TOPIC_PREFIX = "coinsignal:"
 const REDIS_TOPIC_CANDLESTICK_EXT = REDIS_COINSIGNAL_TOPIC_PREFIX + "candlestick_ext"
 const REDIS_TOPIC_FUNDING_RATE_PARSED = REDIS_COINSIGNAL_TOPIC_PREFIX + "funding_rate"
+const REDIS_TOPIC_BOOK_STATS = REDIS_COINSIGNAL_TOPIC_PREFIX + "book_stats"
@@ -23,6 +23,54 @@ const apps = [
     instances: 1,
     restart_delay: 5000, // 5 seconds
   },
+  {
+    name: "indicator_engine",
+    script: "indicator_engine",
+    exec_interpreter: "none",
+    exec_mode: "fork",
+    instances: 1,
+    restart_delay: 5000, // 5 seconds
+  },
+  {
+    name: "basis_calculator",
+    script: "basis_calculator",
+    exec_interpreter: "none",
+    exec_mode: "fork",
+    instances: 1,
+    restart_delay: 5000, // 5 seconds
+  },
+  {
+    name: "funding_aggregator",
+    script: "funding_aggregator",
+    exec_interpreter: "none",
+    exec_mode: "fork",
+    instances: 1,
+    restart_delay: 5000, // 5 seconds
+  },
+  {
+    name: "correlation_matrix",
+    script: "correlation_matrix",
+    exec_interpreter: "none",
+    exec_mode: "fork",
+    instances: 1,
+    restart_delay: 5000, // 5 seconds
+  },
+  {
+    name: "whale_detector",
+    script: "whale_detector",
+    exec_interpreter: "none",
+    exec_mode: "fork",
+    instances: 1,
+    restart_delay: 5000, // 5 seconds
+  },
+  {
+    name: "book_aggregator",
+    script: "book_aggregator",
+    exec_interpreter: "none",
+    exec_mode: "fork",
+    instances: 1,
+    restart_delay: 5000, // 5 seconds
+  },
   {
     name: "data_shipper",
     script: "data_shipper",
@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+
+use crate::median;
+
+// Scales MAD to be consistent with the standard deviation of a normal distribution
+const MAD_SCALE: f64 = 0.6745;
+// A baseline whose spread is below this fraction of its median is flat
+const SCALE_EPSILON: f64 = 1e-9;
+
+pub struct AnomalyScore {
+    pub value: f64,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub z_score: Option<f64>, // (value - mean) / std_dev, None if the baseline is flat
+    pub median: f64,
+    pub mad: f64,                    // median absolute deviation
+    pub robust_z_score: Option<f64>, // 0.6745 * (value - median) / mad, None if the baseline is flat
+}
+
+/// Scores each value against an adaptive baseline of the previous `window` values,
+/// with both the z-score and the robust z-score based on median absolute deviation.
+///
+/// A score is skipped if its baseline is flat, e.g., zero volume or one trade per bar,
+/// since any deviation from a flat baseline would be infinitely anomalous.
+pub struct AnomalyDetector {
+    window: usize,
+    baseline: VecDeque<f64>,
+}
+
+impl AnomalyDetector {
+    pub fn new(window: usize) -> Self {
+        assert!(window > 1);
+        AnomalyDetector {
+            window,
+            baseline: VecDeque::with_capacity(window + 1),
+        }
+    }
+
+    /// Score a value then add it to the baseline, returns None until the baseline is full
+    /// or if both scores are skipped.
+    pub fn update(&mut self, value: f64) -> Option<AnomalyScore> {
+        if !value.is_finite() {
+            return None;
+        }
+        let score = self.score(value);
+        self.baseline.push_back(value);
+        if self.baseline.len() > self.window {
+            self.baseline.pop_front();
+        }
+        score
+    }
+
+    fn score(&self, value: f64) -> Option<AnomalyScore> {
+        if self.baseline.len() < self.window {
+            return None;
+        }
+        let n = self.baseline.len() as f64;
+        let mean = self.baseline.iter().sum::<f64>() / n;
+        let variance = self
+            .baseline
+            .iter()
+            .map(|x| (x - mean) * (x - mean))
+            .sum::<f64>()
+            / (n - 1.0);
+        let std_dev = variance.sqrt();
+
+        let values: Vec<f64> = self.baseline.iter().copied().collect();
+        let median = median(&values);
+        let deviations: Vec<f64> = values.iter().map(|x| (x - median).abs()).collect();
+        let mad = crate::median(&deviations);
+
+        let z_score = ratio(value - mean, std_dev, median);
+        let robust_z_score = ratio(MAD_SCALE * (value - median), mad, median);
+        if z_score.is_none() && robust_z_score.is_none() {
+            return None;
+        }
+        Some(AnomalyScore {
+            value,
+            mean,
+            std_dev,
+            z_score,
+            median,
+            mad,
+            robust_z_score,
+        })
+    }
+}
+
+// None if the scale is negligible relative to the median
+fn ratio(deviation: f64, scale: f64, median: f64) -> Option<f64> {
+    if scale <= SCALE_EPSILON * median.abs() {
+        None
+    } else {
+        Some(deviation / scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warmed_up(baseline: &[f64]) -> AnomalyDetector {
+        let mut detector = AnomalyDetector::new(baseline.len());
+        for value in baseline {
+            assert!(detector.update(*value).is_none());
+        }
+        detector
+    }
+
+    #[test]
+    fn z_score_and_mad() {
+        let mut detector = warmed_up(&[1.0, 2.0, 3.0, 4.0, 100.0]);
+        let score = detector.update(10.0).unwrap();
+        assert_eq!(score.mean, 22.0);
+        // sample standard deviation
+        assert!((score.std_dev - 1902.5f64.sqrt()).abs() < 1e-9);
+        assert!((score.z_score.unwrap() - (10.0 - 22.0) / 1902.5f64.sqrt()).abs() < 1e-9);
+        // the outlier 100 inflates std_dev but not MAD
+        assert_eq!(score.median, 3.0);
+        assert_eq!(score.mad, 1.0);
+        assert!((score.robust_z_score.unwrap() - 0.6745 * 7.0).abs() < 1e-9);
+
+        // 1.0 has left the baseline
+        let score = detector.update(3.0).unwrap();
+        assert_eq!(score.median, 4.0);
+    }
+
+    #[test]
+    fn flat_baseline_is_not_scored() {
+        let mut detector = warmed_up(&[0.0; 5]);
+        assert!(detector.update(1.0).is_none());
+
+        let mut detector = warmed_up(&[1.0; 5]);
+        assert!(detector.update(2.0).is_none());
+
+        // MAD is zero if most values are equal, the z-score is still valid
+        let mut detector = warmed_up(&[1.0, 1.0, 1.0, 1.0, 2.0]);
+        let score = detector.update(3.0).unwrap();
+        assert_eq!(score.mad, 0.0);
+        assert!(score.robust_z_score.is_none());
+        assert!(score.z_score.unwrap() > 0.0);
+    }
+
+    #[test]
+    fn non_finite_values_are_ignored() {
+        let mut detector = warmed_up(&[1.0, 2.0, 3.0]);
+        assert!(detector.update(f64::NAN).is_none());
+        assert!(detector.update(f64::INFINITY).is_none());
+        assert_eq!(detector.update(2.0).unwrap().mean, 2.0);
+    }
+}
@@ -0,0 +1,76 @@
+/// Average true range with Wilder's smoothing.
+pub struct Atr {
+    period: usize,
+    prev_close: Option<f64>,
+    count: usize,
+    value: f64,
+}
+
+impl Atr {
+    pub fn new(period: usize) -> Self {
+        assert!(period > 0);
+        Atr {
+            period,
+            prev_close: None,
+            count: 0,
+            value: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, high: f64, low: f64, close: f64) -> Option<f64> {
+        let true_range = if let Some(prev_close) = self.prev_close {
+            (high - low)
+                .max((high - prev_close).abs())
+                .max((low - prev_close).abs())
+        } else {
+            high - low
+        };
+        self.prev_close = Some(close);
+
+        self.count += 1;
+        let period = self.period as f64;
+        if self.count <= self.period {
+            // The first ATR is the simple average of true ranges
+            self.value += true_range / period;
+        } else {
+            self.value = (self.value * (period - 1.0) + true_range) / period;
+        }
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        if self.count < self.period {
+            None
+        } else {
+            Some(self.value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::*;
+
+    #[test]
+    fn atr_of_default_period() {
+        let mut atr = Atr::new(14);
+        let actual: Vec<Option<f64>> = (0..CLOSES.len())
+            .map(|i| atr.update(HIGHS[i], LOWS[i], CLOSES[i]))
+            .collect();
+        let expected = [
+            3.0314, 3.0649, 3.0881, 3.0597, 3.0733, 3.0730, 3.0857, 3.0796, 3.0203, 3.0189, 3.0711,
+            2.9946, 2.9771, 3.0145, 2.9956, 3.0116, 3.0793, 3.0851, 3.1333, 3.2166, 3.2190, 3.2477,
+            3.2964, 3.2517, 3.2230, 3.2428, 3.2076,
+        ];
+        assert_series(&actual, 13, &expected, 0.0001);
+    }
+
+    #[test]
+    fn true_range_includes_gaps() {
+        let mut atr = Atr::new(2);
+        assert_eq!(atr.update(11.0, 9.0, 10.0), None);
+        // the gap from the previous close 10 to the low 12 counts
+        assert_eq!(atr.update(13.0, 12.0, 12.5), Some(2.5));
+    }
+}
@@ -0,0 +1,101 @@
+use crate::Sma;
+
+pub struct BollingerBandsOutput {
+    pub upper: f64,
+    pub middle: f64,
+    pub lower: f64,
+}
+
+/// Bollinger bands, the middle band is the SMA and the others are `k` population standard deviations away.
+pub struct BollingerBands {
+    sma: Sma,
+    k: f64,
+}
+
+impl BollingerBands {
+    pub fn new(period: usize, k: f64) -> Self {
+        BollingerBands {
+            sma: Sma::new(period),
+            k,
+        }
+    }
+
+    pub fn update(&mut self, close: f64) -> Option<BollingerBandsOutput> {
+        self.sma.update(close);
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<BollingerBandsOutput> {
+        let middle = self.sma.value()?;
+        let window = self.sma.window();
+        let variance = window
+            .iter()
+            .map(|x| (x - middle) * (x - middle))
+            .sum::<f64>()
+            / window.len() as f64;
+        let std_dev = variance.sqrt();
+        Some(BollingerBandsOutput {
+            upper: middle + self.k * std_dev,
+            middle,
+            lower: middle - self.k * std_dev,
+        })
+    }
+}
+
+impl Default for BollingerBands {
+    fn default() -> Self {
+        BollingerBands::new(20, 2.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::*;
+
+    #[test]
+    fn bollinger_bands_of_default_periods() {
+        let mut bands = BollingerBands::default();
+        let outputs: Vec<Option<BollingerBandsOutput>> =
+            EMA_CLOSES.iter().map(|x| bands.update(*x)).collect();
+        let expected = [
+            (24.1261, 22.7155, 21.3049),
+            (24.2661, 22.7930, 21.3199),
+            (24.3939, 22.8770, 21.3601),
+            (24.4617, 22.9555, 21.4493),
+            (24.4714, 23.0065, 21.5416),
+            (24.4676, 23.0525, 21.6374),
+            (24.4665, 23.1125, 21.7585),
+            (24.4438, 23.1350, 21.8262),
+            (24.4371, 23.1685, 21.8999),
+            (24.4234, 23.1765, 21.9296),
+            (24.4355, 23.1705, 21.9055),
+        ];
+        let uppers: Vec<Option<f64>> = outputs
+            .iter()
+            .map(|x| x.as_ref().map(|x| x.upper))
+            .collect();
+        let middles: Vec<Option<f64>> = outputs
+            .iter()
+            .map(|x| x.as_ref().map(|x| x.middle))
+            .collect();
+        let lowers: Vec<Option<f64>> = outputs
+            .iter()
+            .map(|x| x.as_ref().map(|x| x.lower))
+            .collect();
+        let column =
+            |f: fn(&(f64, f64, f64)) -> f64| -> Vec<f64> { expected.iter().map(f).collect() };
+        assert_series(&uppers, 19, &column(|x| x.0), 0.0001);
+        assert_series(&middles, 19, &column(|x| x.1), 0.0001);
+        assert_series(&lowers, 19, &column(|x| x.2), 0.0001);
+    }
+
+    #[test]
+    fn bands_collapse_on_flat_series() {
+        let mut bands = BollingerBands::new(3, 2.0);
+        assert!(bands.update(5.0).is_none());
+        assert!(bands.update(5.0).is_none());
+        let output = bands.update(5.0).unwrap();
+        assert_eq!((output.upper, output.middle, output.lower), (5.0, 5.0, 5.0));
+    }
+}
@@ -0,0 +1,57 @@
+/// Exponential moving average, seeded with the simple average of the first `period` values.
+pub struct Ema {
+    period: usize,
+    alpha: f64,
+    count: usize,
+    sum: f64, // sum of the first period values
+    value: Option<f64>,
+}
+
+impl Ema {
+    pub fn new(period: usize) -> Self {
+        assert!(period > 0);
+        Ema {
+            period,
+            alpha: 2.0 / (period as f64 + 1.0),
+            count: 0,
+            sum: 0.0,
+            value: None,
+        }
+    }
+
+    pub fn update(&mut self, value: f64) -> Option<f64> {
+        if let Some(prev) = self.value {
+            self.value = Some(prev + self.alpha * (value - prev));
+        } else {
+            self.count += 1;
+            self.sum += value;
+            if self.count == self.period {
+                self.value = Some(self.sum / self.period as f64);
+            }
+        }
+        self.value
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::*;
+
+    #[test]
+    fn ema_of_stockcharts_example() {
+        let mut ema = Ema::new(10);
+        assert_eq!(ema.value(), None);
+        let actual: Vec<Option<f64>> = EMA_CLOSES.iter().map(|x| ema.update(*x)).collect();
+        // seeded with the 10-day SMA 22.22
+        let expected = [
+            22.22, 22.21, 22.24, 22.27, 22.33, 22.52, 22.80, 22.97, 23.13, 23.28, 23.34, 23.43,
+            23.51, 23.53, 23.47, 23.40, 23.39, 23.26, 23.23, 23.08, 22.92,
+        ];
+        assert_series(&actual, 9, &expected, 0.01);
+    }
+}
@@ -0,0 +1,29 @@
+//! Technical indicators which are updated incrementally, one bar at a time.
+//!
+//! Every indicator returns `None` until it has seen enough bars to warm up.
+
+mod anomaly;
+mod atr;
+mod bollinger;
+mod ema;
+mod macd;
+mod obv;
+mod order_flow;
+mod rsi;
+mod sma;
+mod stats;
+#[cfg(test)]
+mod test_data;
+mod volatility;
+
+pub use anomaly::{AnomalyDetector, AnomalyScore};
+pub use atr::Atr;
+pub use bollinger::{BollingerBands, BollingerBandsOutput};
+pub use ema::Ema;
+pub use macd::{Macd, MacdOutput};
+pub use obv::Obv;
+pub use order_flow::{imbalance, Cvd};
+pub use rsi::Rsi;
+pub use sma::Sma;
+pub use stats::median;
+pub use volatility::{Volatility, VolatilityOutput};
@@ -0,0 +1,78 @@
+use crate::Ema;
+
+pub struct MacdOutput {
+    pub macd: f64,
+    pub signal: f64,
+    pub histogram: f64,
+}
+
+/// Moving average convergence divergence.
+pub struct Macd {
+    fast: Ema,
+    slow: Ema,
+    signal: Ema,
+    value: Option<MacdOutput>,
+}
+
+impl Macd {
+    pub fn new(fast_period: usize, slow_period: usize, signal_period: usize) -> Self {
+        assert!(fast_period < slow_period);
+        Macd {
+            fast: Ema::new(fast_period),
+            slow: Ema::new(slow_period),
+            signal: Ema::new(signal_period),
+            value: None,
+        }
+    }
+
+    pub fn update(&mut self, close: f64) -> Option<&MacdOutput> {
+        let fast = self.fast.update(close);
+        let slow = self.slow.update(close);
+        if let (Some(fast), Some(slow)) = (fast, slow) {
+            let macd = fast - slow;
+            if let Some(signal) = self.signal.update(macd) {
+                self.value = Some(MacdOutput {
+                    macd,
+                    signal,
+                    histogram: macd - signal,
+                });
+            }
+        }
+        self.value.as_ref()
+    }
+
+    pub fn value(&self) -> Option<&MacdOutput> {
+        self.value.as_ref()
+    }
+}
+
+impl Default for Macd {
+    fn default() -> Self {
+        Macd::new(12, 26, 9)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::*;
+
+    #[test]
+    fn macd_of_default_periods() {
+        let mut macd = Macd::default();
+        let outputs: Vec<Option<(f64, f64, f64)>> = CLOSES
+            .iter()
+            .map(|x| macd.update(*x).map(|x| (x.macd, x.signal, x.histogram)))
+            .collect();
+        // EMA(12) - EMA(26) starts at the 26th bar, its EMA(9) at the 34th bar
+        let expected_macd = [-0.1620, 0.6261, 1.3905, 2.1067, 2.7515, 3.3043, 3.7484];
+        let expected_signal = [-2.8002, -2.1149, -1.4138, -0.7097, -0.0175, 0.6469, 1.2672];
+        let macds: Vec<Option<f64>> = outputs.iter().map(|x| x.map(|x| x.0)).collect();
+        let signals: Vec<Option<f64>> = outputs.iter().map(|x| x.map(|x| x.1)).collect();
+        assert_series(&macds, 33, &expected_macd, 0.0001);
+        assert_series(&signals, 33, &expected_signal, 0.0001);
+        for (macd, signal, histogram) in outputs.iter().flatten() {
+            assert!((histogram - (macd - signal)).abs() < 1e-12);
+        }
+    }
+}
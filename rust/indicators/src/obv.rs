@@ -0,0 +1,66 @@
+/// On-balance volume, starting from zero.
+pub struct Obv {
+    prev_close: Option<f64>,
+    value: f64,
+}
+
+impl Obv {
+    pub fn new() -> Self {
+        Obv {
+            prev_close: None,
+            value: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, close: f64, volume: f64) -> Option<f64> {
+        if let Some(prev_close) = self.prev_close {
+            if close > prev_close {
+                self.value += volume;
+            } else if close < prev_close {
+                self.value -= volume;
+            }
+        }
+        self.prev_close = Some(close);
+        Some(self.value)
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        self.prev_close.map(|_| self.value)
+    }
+}
+
+impl Default for Obv {
+    fn default() -> Self {
+        Obv::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obv_adds_volume_of_up_bars() {
+        let mut obv = Obv::new();
+        assert_eq!(obv.value(), None);
+        let bars = [
+            (10.0, 100.0),
+            (10.5, 200.0),
+            (10.2, 50.0),
+            (10.2, 70.0),
+            (11.0, 30.0),
+        ];
+        let actual: Vec<Option<f64>> = bars.iter().map(|(c, v)| obv.update(*c, *v)).collect();
+        assert_eq!(
+            actual,
+            vec![
+                Some(0.0),
+                Some(200.0),
+                Some(150.0),
+                Some(150.0),
+                Some(180.0)
+            ]
+        );
+        assert_eq!(obv.value(), Some(180.0));
+    }
+}
@@ -0,0 +1,70 @@
+/// Cumulative volume delta, i.e., the running sum of buy volume minus sell volume.
+pub struct Cvd {
+    value: f64,
+}
+
+impl Cvd {
+    /// Continue from a previously saved value.
+    pub fn new(initial: f64) -> Self {
+        Cvd { value: initial }
+    }
+
+    pub fn update(&mut self, volume_buy: f64, volume_sell: f64) -> f64 {
+        self.value += volume_buy - volume_sell;
+        self.value
+    }
+
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+}
+
+impl Default for Cvd {
+    fn default() -> Self {
+        Cvd::new(0.0)
+    }
+}
+
+/// (buy - sell) / (buy + sell), from -1 (all sells) to 1 (all buys), None if both are zero.
+pub fn imbalance(buy: f64, sell: f64) -> Option<f64> {
+    let total = buy + sell;
+    if total > 0.0 {
+        Some((buy - sell) / total)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cvd_accumulates_delta() {
+        let mut cvd = Cvd::default();
+        assert_eq!(cvd.value(), 0.0);
+        let bars = [(3.0, 1.0), (0.5, 2.0), (1.0, 1.0), (0.0, 4.0)];
+        let actual: Vec<f64> = bars.iter().map(|(b, s)| cvd.update(*b, *s)).collect();
+        // Buying pressure is positive, selling pressure is negative
+        assert_eq!(actual, vec![2.0, 0.5, 0.5, -3.5]);
+        assert_eq!(cvd.value(), -3.5);
+
+        // A restored CVD continues from its saved value
+        let mut cvd = Cvd::new(-3.5);
+        assert_eq!(cvd.update(1.5, 0.0), -2.0);
+    }
+
+    #[test]
+    fn imbalance_is_bounded() {
+        assert_eq!(imbalance(1.0, 0.0), Some(1.0));
+        assert_eq!(imbalance(0.0, 2.0), Some(-1.0));
+        assert_eq!(imbalance(3.0, 1.0), Some(0.5));
+        assert_eq!(imbalance(1.0, 3.0), Some(-0.5));
+        assert_eq!(imbalance(2.0, 2.0), Some(0.0));
+        assert_eq!(imbalance(0.0, 0.0), None);
+        for (buy, sell) in [(1e-12, 1e12), (1e12, 1e-12), (7.0, 0.3)] {
+            let value = imbalance(buy, sell).unwrap();
+            assert!((-1.0..=1.0).contains(&value), "{}", value);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+/// Relative strength index with Wilder's smoothing.
+pub struct Rsi {
+    period: usize,
+    prev_close: Option<f64>,
+    count: usize,
+    avg_gain: f64,
+    avg_loss: f64,
+}
+
+impl Rsi {
+    pub fn new(period: usize) -> Self {
+        assert!(period > 0);
+        Rsi {
+            period,
+            prev_close: None,
+            count: 0,
+            avg_gain: 0.0,
+            avg_loss: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, close: f64) -> Option<f64> {
+        let prev_close = self.prev_close.replace(close)?;
+        let change = close - prev_close;
+        let (gain, loss) = if change > 0.0 {
+            (change, 0.0)
+        } else {
+            (0.0, -change)
+        };
+
+        self.count += 1;
+        if self.count <= self.period {
+            // The first averages are simple averages
+            self.avg_gain += gain / self.period as f64;
+            self.avg_loss += loss / self.period as f64;
+        } else {
+            let period = self.period as f64;
+            self.avg_gain = (self.avg_gain * (period - 1.0) + gain) / period;
+            self.avg_loss = (self.avg_loss * (period - 1.0) + loss) / period;
+        }
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        if self.count < self.period {
+            None
+        } else if self.avg_loss == 0.0 {
+            Some(if self.avg_gain == 0.0 { 50.0 } else { 100.0 })
+        } else {
+            let rs = self.avg_gain / self.avg_loss;
+            Some(100.0 - 100.0 / (1.0 + rs))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::*;
+
+    #[test]
+    fn rsi_of_stockcharts_example() {
+        let mut rsi = Rsi::new(14);
+        let actual: Vec<Option<f64>> = RSI_CLOSES.iter().map(|x| rsi.update(*x)).collect();
+        // StockCharts rounds the average gain and loss to 2 decimals, e.g., its first RSI is 70.53,
+        // these are calculated without rounding
+        let expected = [
+            70.46, 66.25, 66.48, 69.35, 66.29, 57.92, 62.88, 63.21, 56.01, 62.34, 54.67, 50.39,
+            40.02, 41.49, 41.90, 45.50, 37.32, 33.09, 37.79,
+        ];
+        // 14 changes are needed, i.e., 15 closes
+        assert_series(&actual, 14, &expected, 0.01);
+    }
+
+    #[test]
+    fn rsi_of_flat_and_rising_series() {
+        let mut rsi = Rsi::new(3);
+        for _ in 0..3 {
+            assert_eq!(rsi.update(10.0), None);
+        }
+        assert_eq!(rsi.update(10.0), Some(50.0));
+
+        let mut rsi = Rsi::new(3);
+        for i in 0..3 {
+            rsi.update(i as f64);
+        }
+        assert_eq!(rsi.update(3.0), Some(100.0));
+    }
+}
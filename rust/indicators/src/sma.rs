@@ -0,0 +1,60 @@
+use std::collections::VecDeque;
+
+/// Simple moving average.
+pub struct Sma {
+    period: usize,
+    window: VecDeque<f64>,
+    sum: f64,
+}
+
+impl Sma {
+    pub fn new(period: usize) -> Self {
+        assert!(period > 0);
+        Sma {
+            period,
+            window: VecDeque::with_capacity(period + 1),
+            sum: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, value: f64) -> Option<f64> {
+        self.window.push_back(value);
+        self.sum += value;
+        if self.window.len() > self.period {
+            self.sum -= self.window.pop_front().unwrap();
+        }
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        if self.window.len() == self.period {
+            Some(self.sum / self.period as f64)
+        } else {
+            None
+        }
+    }
+
+    /// Values in the window, from the oldest to the latest.
+    pub(crate) fn window(&self) -> &VecDeque<f64> {
+        &self.window
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::*;
+
+    #[test]
+    fn sma_of_stockcharts_example() {
+        let mut sma = Sma::new(10);
+        assert_eq!(sma.value(), None);
+        let actual: Vec<Option<f64>> = EMA_CLOSES.iter().map(|x| sma.update(*x)).collect();
+        let expected = [
+            22.22, 22.21, 22.23, 22.26, 22.30, 22.42, 22.61, 22.77, 22.91, 23.08, 23.21, 23.38,
+            23.52, 23.65, 23.71, 23.68, 23.61, 23.51, 23.43, 23.28, 23.13,
+        ];
+        assert_series(&actual, 9, &expected, 0.01);
+        assert_eq!(sma.window().len(), 10);
+    }
+}
@@ -0,0 +1,59 @@
+//! Price series shared by the tests of indicators, expected values in the tests are calculated
+//! from the textbook definitions without intermediate rounding.
+
+// Closes of the 10-day SMA and EMA example of StockCharts
+pub const EMA_CLOSES: [f64; 30] = [
+    22.27, 22.19, 22.08, 22.17, 22.18, 22.13, 22.23, 22.43, 22.24, 22.29, 22.15, 22.39, 22.38,
+    22.61, 23.36, 24.05, 23.75, 23.83, 23.95, 23.63, 23.82, 23.87, 23.65, 23.19, 23.10, 23.33,
+    22.68, 23.10, 22.40, 22.17,
+];
+
+// Closes of the 14-day RSI example of StockCharts
+pub const RSI_CLOSES: [f64; 33] = [
+    44.34, 44.09, 44.15, 43.61, 44.33, 44.83, 45.10, 45.42, 45.84, 46.08, 45.89, 46.03, 45.61,
+    46.28, 46.28, 46.00, 46.03, 46.41, 46.22, 45.64, 46.21, 46.25, 45.71, 46.45, 45.78, 45.35,
+    44.03, 44.18, 44.22, 44.57, 43.42, 42.66, 43.13,
+];
+
+// 100 + 10 * sin(i / 5) + 0.3 * i, rounded to cents
+pub const CLOSES: [f64; 40] = [
+    100.0, 102.29, 104.49, 106.55, 108.37, 109.91, 111.12, 111.95, 112.4, 112.44, 112.09, 111.38,
+    110.35, 109.06, 107.55, 105.91, 104.22, 102.54, 100.97, 99.58, 98.43, 97.58, 97.08, 96.96,
+    97.24, 97.91, 98.97, 100.37, 102.09, 104.05, 106.21, 108.47, 110.77, 113.02, 115.14, 117.07,
+    118.74, 120.09, 121.08, 121.69,
+];
+
+// close + 1 + (i % 3) * 0.5
+pub const HIGHS: [f64; 40] = [
+    101.0, 103.79, 106.49, 107.55, 109.87, 111.91, 112.12, 113.45, 114.4, 113.44, 113.59, 113.38,
+    111.35, 110.56, 109.55, 106.91, 105.72, 104.54, 101.97, 101.08, 100.43, 98.58, 98.58, 98.96,
+    98.24, 99.41, 100.97, 101.37, 103.59, 106.05, 107.21, 109.97, 112.77, 114.02, 116.64, 119.07,
+    119.74, 121.59, 123.08, 122.69,
+];
+
+// close - 1 - (i % 4) * 0.25
+pub const LOWS: [f64; 40] = [
+    99.0, 101.04, 102.99, 104.8, 107.37, 108.66, 109.62, 110.2, 111.4, 111.19, 110.59, 109.63,
+    109.35, 107.81, 106.05, 104.16, 103.22, 101.29, 99.47, 97.83, 97.43, 96.33, 95.58, 95.21,
+    96.24, 96.66, 97.47, 98.62, 101.09, 102.8, 104.71, 106.72, 109.77, 111.77, 113.64, 115.32,
+    117.74, 118.84, 119.58, 119.94,
+];
+
+/// Check that the first `warm_up` outputs are None and the others are within `tolerance` of `expected`.
+pub fn assert_series(actual: &[Option<f64>], warm_up: usize, expected: &[f64], tolerance: f64) {
+    assert_eq!(actual.len(), warm_up + expected.len());
+    for (i, value) in actual[..warm_up].iter().enumerate() {
+        assert!(value.is_none(), "{}: expected None, got {:?}", i, value);
+    }
+    for (i, (value, expected)) in actual[warm_up..].iter().zip(expected.iter()).enumerate() {
+        let value =
+            value.unwrap_or_else(|| panic!("{}: expected {}, got None", warm_up + i, expected));
+        assert!(
+            (value - expected).abs() <= tolerance,
+            "{}: expected {}, got {}",
+            warm_up + i,
+            expected,
+            value
+        );
+    }
+}
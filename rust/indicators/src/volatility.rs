@@ -0,0 +1,144 @@
+use crate::Sma;
+
+pub struct VolatilityOutput {
+    pub close_to_close: Option<f64>,
+    pub parkinson: Option<f64>,
+    pub garman_klass: Option<f64>,
+    pub rogers_satchell: Option<f64>,
+}
+
+/// Annualized volatility estimators over the latest `lookback` bars.
+///
+/// `periods_per_year` is the number of bars in a year, e.g., 365 for daily bars,
+/// since crypto markets trade around the clock.
+pub struct Volatility {
+    lookback: usize,
+    periods_per_year: f64,
+    prev_close: Option<f64>,
+    returns: Sma,         // ln(C / prev C)
+    squared_returns: Sma, // ln(C / prev C)^2
+    parkinson: Sma,       // ln(H / L)^2 / (4 ln 2)
+    garman_klass: Sma,    // 0.5 ln(H / L)^2 - (2 ln 2 - 1) ln(C / O)^2
+    rogers_satchell: Sma, // ln(H / C) ln(H / O) + ln(L / C) ln(L / O)
+}
+
+impl Volatility {
+    pub fn new(lookback: usize, periods_per_year: f64) -> Self {
+        assert!(lookback > 1);
+        Volatility {
+            lookback,
+            periods_per_year,
+            prev_close: None,
+            returns: Sma::new(lookback),
+            squared_returns: Sma::new(lookback),
+            parkinson: Sma::new(lookback),
+            garman_klass: Sma::new(lookback),
+            rogers_satchell: Sma::new(lookback),
+        }
+    }
+
+    pub fn update(&mut self, open: f64, high: f64, low: f64, close: f64) -> VolatilityOutput {
+        if let Some(prev_close) = self.prev_close.replace(close) {
+            let r = (close / prev_close).ln();
+            self.returns.update(r);
+            self.squared_returns.update(r * r);
+        }
+
+        let hl = (high / low).ln();
+        let co = (close / open).ln();
+        let ln2 = std::f64::consts::LN_2;
+        self.parkinson.update(hl * hl / (4.0 * ln2));
+        self.garman_klass
+            .update(0.5 * hl * hl - (2.0 * ln2 - 1.0) * co * co);
+        self.rogers_satchell.update(
+            (high / close).ln() * (high / open).ln() + (low / close).ln() * (low / open).ln(),
+        );
+        self.value()
+    }
+
+    pub fn value(&self) -> VolatilityOutput {
+        // sample variance of returns
+        let close_to_close = match (self.returns.value(), self.squared_returns.value()) {
+            (Some(mean), Some(mean_of_squares)) => {
+                let n = self.lookback as f64;
+                Some((mean_of_squares - mean * mean) * n / (n - 1.0))
+            }
+            _ => None,
+        };
+        VolatilityOutput {
+            close_to_close: close_to_close.map(|x| self.annualize(x)),
+            parkinson: self.parkinson.value().map(|x| self.annualize(x)),
+            garman_klass: self.garman_klass.value().map(|x| self.annualize(x)),
+            rogers_satchell: self.rogers_satchell.value().map(|x| self.annualize(x)),
+        }
+    }
+
+    // Convert a per-bar variance into annualized volatility
+    fn annualize(&self, variance: f64) -> f64 {
+        (variance.max(0.0) * self.periods_per_year).sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::*;
+
+    fn outputs(lookback: usize) -> Vec<VolatilityOutput> {
+        let mut volatility = Volatility::new(lookback, 365.0);
+        (0..CLOSES.len())
+            .map(|i| {
+                // each bar opens at the previous close
+                let open = if i == 0 { 100.0 } else { CLOSES[i - 1] };
+                volatility.update(open, HIGHS[i], LOWS[i], CLOSES[i])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn estimators_of_synthetic_series() {
+        let outputs = outputs(20);
+        let column = |f: fn(&VolatilityOutput) -> Option<f64>| -> Vec<Option<f64>> {
+            outputs.iter().map(f).collect()
+        };
+        // close-to-close needs 20 returns, i.e., 21 closes
+        let close_to_close = column(|x| x.close_to_close);
+        assert_eq!(close_to_close[..20].iter().flatten().count(), 0);
+        let parkinson = column(|x| x.parkinson);
+        let garman_klass = column(|x| x.garman_klass);
+        let rogers_satchell = column(|x| x.rogers_satchell);
+        for series in [&parkinson, &garman_klass, &rogers_satchell] {
+            assert_eq!(series[..19].iter().flatten().count(), 0);
+        }
+
+        let expected = [
+            (19, None, 0.309645, 0.327045, 0.372013),
+            (20, Some(0.270353), 0.31512, 0.33279, 0.376737),
+            (30, Some(0.247267), 0.32771, 0.354144, 0.389599),
+            (39, Some(0.195999), 0.315412, 0.331127, 0.37953),
+        ];
+        let close = |x: Option<f64>, y: f64| (x.unwrap() - y).abs() < 1e-6;
+        for (i, c2c, pk, gk, rs) in expected {
+            match c2c {
+                Some(c2c) => assert!(close(close_to_close[i], c2c), "{}", i),
+                None => assert!(close_to_close[i].is_none()),
+            }
+            assert!(close(parkinson[i], pk), "{}", i);
+            assert!(close(garman_klass[i], gk), "{}", i);
+            assert!(close(rogers_satchell[i], rs), "{}", i);
+        }
+    }
+
+    #[test]
+    fn flat_bars_have_zero_volatility() {
+        let mut volatility = Volatility::new(3, 365.0);
+        for _ in 0..4 {
+            volatility.update(10.0, 10.0, 10.0, 10.0);
+        }
+        let output = volatility.value();
+        assert_eq!(output.close_to_close, Some(0.0));
+        assert_eq!(output.parkinson, Some(0.0));
+        assert_eq!(output.garman_klass, Some(0.0));
+        assert_eq!(output.rogers_satchell, Some(0.0));
+    }
+}
@@ -0,0 +1,121 @@
+//! Throughput of msg_parser with different numbers of worker threads.
+//!
+//! Recorded carbonbot messages, one JSON `Message` per line, are read from
+//! the file in the MESSAGES_FILE environment variable, otherwise binance
+//! trade messages are generated.
+//!
+//! ```bash
+//! MESSAGES_FILE=/data/trade.binance.spot.json cargo bench --bench msg_parser
+//! ```
+use std::io::BufRead;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crypto_market_type::MarketType;
+use crypto_msg_type::MessageType;
+use transform::parser::{self, Message};
+use transform::worker_pool::WorkerPool;
+
+const NUM_GENERATED_MESSAGES: usize = 200000;
+const QUEUE_SIZE: usize = 4096;
+
+fn generate_messages() -> Vec<Message> {
+    let bases = [
+        "BTC", "ETH", "BNB", "XRP", "ADA", "SOL", "DOGE", "DOT", "LTC", "TRX", "LINK", "AVAX",
+        "ATOM", "ETC", "XLM", "BCH",
+    ];
+    (0..NUM_GENERATED_MESSAGES)
+        .map(|i| {
+            let symbol = format!("{}USDT", bases[i % bases.len()]);
+            let timestamp = 1616176861894 + i as u64;
+            let json = format!(
+                r#"{{"stream":"{}@trade","data":{{"e":"trade","E":{},"s":"{}","t":{},"p":"57928.65000000","q":"0.00150000","b":5461317845,"a":5461318138,"T":{},"m":true,"M":true}}}}"#,
+                symbol.to_lowercase(),
+                timestamp + 1,
+                symbol,
+                i,
+                timestamp
+            );
+            Message {
+                exchange: "binance".to_string(),
+                market_type: MarketType::Spot,
+                msg_type: MessageType::Trade,
+                received_at: timestamp + 2,
+                json,
+            }
+        })
+        .collect()
+}
+
+fn read_messages(path: &str) -> Vec<Message> {
+    let file = std::fs::File::open(path).unwrap();
+    std::io::BufReader::new(file)
+        .lines()
+        .map(|line| line.unwrap())
+        .filter_map(|line| serde_json::from_str::<Message>(&line).ok())
+        .collect()
+}
+
+fn run(num_workers: usize, lines: &[String]) -> f64 {
+    let parsed = Arc::new(AtomicUsize::new(0));
+    let start = Instant::now();
+    let pool = WorkerPool::new("parser", num_workers, QUEUE_SIZE, |_| {
+        let parsed = parsed.clone();
+        move |raw_msg: Message| {
+            if let Ok(msgs) = parser::parse(&raw_msg, raw_msg.received_at as i64) {
+                parsed.fetch_add(msgs.len(), Ordering::Relaxed);
+            }
+        }
+    });
+    // Deserialize on the dispatching thread like msg_parser does
+    for line in lines {
+        let raw_msg = serde_json::from_str::<Message>(line).unwrap();
+        pool.dispatch(&parser::shard_key(&raw_msg), raw_msg);
+    }
+    let blocked = pool.metrics().blocked();
+    pool.join();
+    let elapsed = start.elapsed().as_secs_f64();
+    assert!(parsed.load(Ordering::Relaxed) > 0, "No message was parsed");
+    println!(
+        "{} workers: {:.0} messages/s, {} blocked dispatches",
+        num_workers,
+        lines.len() as f64 / elapsed,
+        blocked
+    );
+    lines.len() as f64 / elapsed
+}
+
+fn main() {
+    let messages = if let Ok(path) = std::env::var("MESSAGES_FILE") {
+        read_messages(&path)
+    } else {
+        generate_messages()
+    };
+    let lines: Vec<String> = messages
+        .iter()
+        .map(|msg| serde_json::to_string(msg).unwrap())
+        .collect();
+    println!(
+        "{} messages, {} CPUs",
+        lines.len(),
+        std::thread::available_parallelism().map_or(1, |n| n.get())
+    );
+
+    // The dispatching thread is serial, so its throughput is the upper bound
+    let start = Instant::now();
+    for line in lines.iter() {
+        let raw_msg = serde_json::from_str::<Message>(line).unwrap();
+        std::hint::black_box(parser::shard_key(&raw_msg));
+    }
+    println!(
+        "dispatch only: {:.0} messages/s",
+        lines.len() as f64 / start.elapsed().as_secs_f64()
+    );
+
+    let baseline = run(1, &lines);
+    for num_workers in [2, 4, 8] {
+        let throughput = run(num_workers, &lines);
+        println!("  speedup: {:.2}x", throughput / baseline);
+    }
+}
@@ -0,0 +1,116 @@
+use log::*;
+use redis::Commands;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use transform::constants::{
+    REDIS_TOPIC_CANDLESTICK_EXT, REDIS_TOPIC_CURRENCY_PRICE, REDIS_TOPIC_FUNDING_RATE_PARSED,
+};
+use utils::{pubsub::create_bus, wait_redis};
+
+mod rule;
+mod webhook;
+
+use rule::{Config, RuleState, Source};
+use webhook::create_webhook_thread;
+
+const DEFAULT_ALERT_RULES: &str = "alerts.toml";
+const PRICE_POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+// Evaluate alert rules over candlesticks, funding rates and currency prices
+fn main() {
+    env_logger::init();
+    let redis_url = if std::env::var("REDIS_URL").is_err() {
+        info!(
+            "The REDIS_URL environment variable is empty, using redis://localhost:6379 by default"
+        );
+        "redis://localhost:6379"
+    } else {
+        let url = std::env::var("REDIS_URL").unwrap();
+        Box::leak(url.into_boxed_str())
+    };
+    wait_redis(redis_url);
+
+    let path = if let Ok(path) = std::env::var("ALERT_RULES") {
+        path
+    } else {
+        info!(
+            "The ALERT_RULES environment variable is empty, using {} by default",
+            DEFAULT_ALERT_RULES
+        );
+        DEFAULT_ALERT_RULES.to_string()
+    };
+    let config = Config::load(&path).unwrap_or_else(|err| panic!("{}", err));
+    let mut states: Vec<RuleState> = config.rules.iter().map(RuleState::new).collect();
+    let has_price_rules = config
+        .rules
+        .iter()
+        .any(|rule| rule.source == Source::CurrencyPrice);
+
+    let (tx, _) = create_webhook_thread(
+        config
+            .webhooks
+            .iter()
+            .map(|webhook| (webhook.name.clone(), webhook.url.clone()))
+            .collect(),
+    );
+
+    let mut evaluate = |source: Source, msg: &Value| {
+        let now = now_ms();
+        for (rule, state) in config.rules.iter().zip(states.iter_mut()) {
+            if rule.source != source {
+                continue;
+            }
+            if let Some(alert) = state.evaluate(rule, msg, now) {
+                warn!("Alert {} fired on {}", alert.rule, alert.series);
+                tx.send((alert, rule.webhooks.clone())).unwrap();
+            }
+        }
+    };
+
+    let mut price_conn = {
+        let client = redis::Client::open(redis_url).unwrap();
+        client.get_connection().unwrap()
+    };
+
+    let bus = create_bus(redis_url);
+    let mut subscriber =
+        bus.subscribe(&[REDIS_TOPIC_CANDLESTICK_EXT, REDIS_TOPIC_FUNDING_RATE_PARSED]);
+
+    let mut last_price_poll_time = 0;
+    loop {
+        // next_message() times out periodically so that prices are polled even if there is no message
+        if let Some(msg) = subscriber.next_message(Some(PRICE_POLL_INTERVAL)) {
+            let source = if msg.channel == REDIS_TOPIC_CANDLESTICK_EXT {
+                Source::Candlestick
+            } else {
+                Source::FundingRate
+            };
+            match serde_json::from_str::<Value>(&msg.payload) {
+                Ok(value) => evaluate(source, &value),
+                Err(err) => warn!("{}, {}", err, msg.payload),
+            }
+        }
+
+        let now = now_ms();
+        if has_price_rules && now - last_price_poll_time >= PRICE_POLL_INTERVAL.as_millis() as i64 {
+            if let Ok(prices) =
+                price_conn.hgetall::<&str, HashMap<String, f64>>(REDIS_TOPIC_CURRENCY_PRICE)
+            {
+                for (currency, price) in prices {
+                    let msg = serde_json::json!({ "currency": currency, "price": price });
+                    evaluate(Source::CurrencyPrice, &msg);
+                }
+            }
+            last_price_poll_time = now;
+        }
+    }
+}
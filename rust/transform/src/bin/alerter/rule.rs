@@ -0,0 +1,401 @@
+use indicators::Sma;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use transform::duration::parse_duration;
+
+const DEFAULT_AVERAGE_WINDOW: usize = 20;
+const DEFAULT_COOLDOWN: &str = "5m";
+
+/// Where the messages evaluated by a rule come from.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Candlestick,   // coinsignal:candlestick_ext
+    FundingRate,   // coinsignal:funding_rate
+    CurrencyPrice, // the coinsignal:currency_price hash
+}
+
+impl Source {
+    // Fields which identify a series
+    fn key_fields(&self) -> &'static [&'static str] {
+        match self {
+            Source::Candlestick => &["exchange", "market_type", "symbol", "pair", "bar_size"],
+            Source::FundingRate => &["exchange", "market_type", "symbol"],
+            Source::CurrencyPrice => &["currency"],
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+pub enum Op {
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = ">=")]
+    Ge,
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = "<=")]
+    Le,
+}
+
+impl Op {
+    fn compare(&self, value: f64, bound: f64) -> bool {
+        match self {
+            Op::Gt => value > bound,
+            Op::Ge => value >= bound,
+            Op::Lt => value < bound,
+            Op::Le => value <= bound,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Webhook {
+    pub name: String,
+    pub url: String,
+}
+
+/// A rule compares a numeric field of each message either with a static threshold,
+/// or with a multiple of the field's rolling average in the same series.
+#[derive(Deserialize)]
+pub struct Rule {
+    pub name: String,
+    pub source: Source,
+    // Only messages whose fields equal these values are evaluated, e.g., { pair = "BTC/USDT" }
+    #[serde(default)]
+    pub filters: BTreeMap<String, String>,
+    pub field: String,
+    pub op: Op,
+    pub threshold: Option<f64>,
+    pub average_multiple: Option<f64>,
+    #[serde(default = "default_average_window")]
+    pub average_window: usize, // number of previous messages in the rolling average
+    #[serde(default = "default_cooldown")]
+    pub cooldown: String, // a rule fires at most once per cooldown for each series
+    pub webhooks: Vec<String>,
+}
+
+fn default_average_window() -> usize {
+    DEFAULT_AVERAGE_WINDOW
+}
+
+fn default_cooldown() -> String {
+    DEFAULT_COOLDOWN.to_string()
+}
+
+/// The alert rules file, e.g.,
+///
+/// ```toml
+/// [[webhooks]]
+/// name = "ops"
+/// url = "http://localhost:8080/alerts"
+///
+/// [[rules]]
+/// name = "btc_funding_rate_high"
+/// source = "funding_rate"
+/// filters = { pair = "BTC/USDT" }
+/// field = "funding_rate"
+/// op = ">"
+/// threshold = 0.001
+/// cooldown = "1h"
+/// webhooks = ["ops"]
+///
+/// [[rules]]
+/// name = "volume_spike"
+/// source = "candlestick"
+/// filters = { bar_size = "300000" }
+/// field = "volume_usd"
+/// op = ">"
+/// average_multiple = 10
+/// webhooks = ["ops"]
+/// ```
+#[derive(Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+    pub rules: Vec<Rule>,
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        Self::parse(&text).map_err(|err| format!("{}: {}", path, err))
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let config = toml::from_str::<Config>(text).map_err(|err| err.to_string())?;
+        for rule in config.rules.iter() {
+            if rule.threshold.is_some() == rule.average_multiple.is_some() {
+                return Err(format!(
+                    "Rule {} should have exactly one of threshold and average_multiple",
+                    rule.name
+                ));
+            }
+            if rule.average_window == 0 {
+                return Err(format!("Rule {} has an empty average_window", rule.name));
+            }
+            if parse_duration(&rule.cooldown).is_none() {
+                return Err(format!(
+                    "Rule {} has an invalid cooldown {}",
+                    rule.name, rule.cooldown
+                ));
+            }
+            for webhook in rule.webhooks.iter() {
+                if !config.webhooks.iter().any(|x| &x.name == webhook) {
+                    return Err(format!(
+                        "Rule {} has an unknown webhook {}",
+                        rule.name, webhook
+                    ));
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// An alert delivered to webhooks.
+#[derive(Serialize)]
+pub struct Alert {
+    pub rule: String,
+    pub source: Source,
+    pub series: String,
+    pub field: String,
+    pub value: f64,
+    pub op: Op,
+    pub bound: f64, // the threshold, or the multiple of the rolling average
+    pub timestamp: i64,
+    pub message: Value,
+}
+
+// Rolling averages and cooldowns of one rule
+pub struct RuleState {
+    cooldown: i64,
+    averages: HashMap<String, Sma>, // series -> rolling average of the field
+    last_fired: HashMap<String, i64>, // series -> when the rule fired last time
+}
+
+impl RuleState {
+    pub fn new(rule: &Rule) -> Self {
+        RuleState {
+            cooldown: parse_duration(&rule.cooldown).unwrap(),
+            averages: HashMap::new(),
+            last_fired: HashMap::new(),
+        }
+    }
+
+    pub fn evaluate(&mut self, rule: &Rule, msg: &Value, now: i64) -> Option<Alert> {
+        for (field, expected) in rule.filters.iter() {
+            let matched = match msg.get(field) {
+                Some(Value::String(x)) => x == expected,
+                Some(x) => &x.to_string() == expected,
+                None => false,
+            };
+            if !matched {
+                return None;
+            }
+        }
+        let value = msg.get(&rule.field)?.as_f64()?;
+        if !value.is_finite() {
+            return None;
+        }
+
+        let series = rule
+            .source
+            .key_fields()
+            .iter()
+            .map(|field| match msg.get(*field) {
+                Some(Value::String(x)) => x.clone(),
+                Some(x) => x.to_string(),
+                None => String::new(),
+            })
+            .collect::<Vec<String>>()
+            .join("-");
+
+        let bound = if let Some(threshold) = rule.threshold {
+            threshold
+        } else {
+            let average = self
+                .averages
+                .entry(series.clone())
+                .or_insert_with(|| Sma::new(rule.average_window));
+            // compare with the average of previous values
+            let prev_average = average.value();
+            average.update(value);
+            prev_average? * rule.average_multiple.unwrap()
+        };
+        if !rule.op.compare(value, bound) {
+            return None;
+        }
+
+        if let Some(last_fired) = self.last_fired.get(&series) {
+            if now < last_fired + self.cooldown {
+                return None;
+            }
+        }
+        self.last_fired.insert(series.clone(), now);
+
+        Some(Alert {
+            rule: rule.name.clone(),
+            source: rule.source,
+            series,
+            field: rule.field.clone(),
+            value,
+            op: rule.op,
+            bound,
+            timestamp: now,
+            message: msg.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const CONFIG: &str = r#"
+[[webhooks]]
+name = "ops"
+url = "http://localhost:8080/alerts"
+
+[[rules]]
+name = "btc_funding_rate_high"
+source = "funding_rate"
+filters = { pair = "BTC/USDT" }
+field = "funding_rate"
+op = ">"
+threshold = 0.001
+cooldown = "1h"
+webhooks = ["ops"]
+
+[[rules]]
+name = "volume_spike"
+source = "candlestick"
+filters = { bar_size = "300000" }
+field = "volume_usd"
+op = ">"
+average_multiple = 10
+average_window = 3
+webhooks = ["ops"]
+"#;
+
+    // Replace the rules of CONFIG with one rule
+    fn parse_rule(rule: &str) -> Result<Config, String> {
+        let webhooks = CONFIG.split("[[rules]]").next().unwrap();
+        Config::parse(&format!("{}[[rules]]\n{}", webhooks, rule))
+    }
+
+    #[test]
+    fn parse_config() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert_eq!(config.webhooks.len(), 1);
+        assert_eq!(config.webhooks[0].url, "http://localhost:8080/alerts");
+        assert_eq!(config.rules.len(), 2);
+
+        let rule = &config.rules[0];
+        assert_eq!(rule.source, Source::FundingRate);
+        assert_eq!(rule.filters["pair"], "BTC/USDT");
+        assert_eq!(rule.threshold, Some(0.001));
+        assert_eq!(rule.average_multiple, None);
+        assert_eq!(rule.cooldown, "1h");
+        assert_eq!(rule.webhooks, vec!["ops".to_string()]);
+
+        let rule = &config.rules[1];
+        assert_eq!(rule.source, Source::Candlestick);
+        assert_eq!(rule.average_multiple, Some(10.0));
+        assert_eq!(rule.average_window, 3);
+        assert_eq!(rule.cooldown, DEFAULT_COOLDOWN);
+    }
+
+    #[test]
+    fn reject_invalid_rules() {
+        let base = r#"name = "r"
+source = "currency_price"
+field = "price"
+op = "<"
+webhooks = ["ops"]
+"#;
+        let valid = format!("{}threshold = 1", base);
+        assert!(parse_rule(&valid).is_ok());
+        // exactly one of threshold and average_multiple
+        assert!(parse_rule(base).is_err());
+        assert!(parse_rule(&format!("{}\naverage_multiple = 2", valid)).is_err());
+        assert!(parse_rule(&format!("{}average_multiple = 2\naverage_window = 0", base)).is_err());
+        assert!(parse_rule(&format!("{}\ncooldown = \"5x\"", valid)).is_err());
+        assert!(parse_rule(&valid.replace("[\"ops\"]", "[\"pager\"]")).is_err());
+        assert!(parse_rule(&valid.replace("\"<\"", "\"!=\"")).is_err());
+        assert!(parse_rule(&valid.replace("currency_price", "trade")).is_err());
+    }
+
+    #[test]
+    fn threshold_rule_fires_once_per_cooldown() {
+        let config = Config::parse(CONFIG).unwrap();
+        let rule = &config.rules[0];
+        let mut state = RuleState::new(rule);
+        let msg = |pair: &str, symbol: &str, funding_rate: f64| {
+            json!({
+                "exchange": "binance",
+                "market_type": "linear_swap",
+                "symbol": symbol,
+                "pair": pair,
+                "funding_rate": funding_rate,
+            })
+        };
+
+        assert!(state
+            .evaluate(rule, &msg("BTC/USDT", "BTCUSDT", 0.0005), 0)
+            .is_none());
+        let alert = state
+            .evaluate(rule, &msg("BTC/USDT", "BTCUSDT", 0.002), 1000)
+            .unwrap();
+        assert_eq!(alert.rule, "btc_funding_rate_high");
+        assert_eq!(alert.series, "binance-linear_swap-BTCUSDT");
+        assert_eq!(alert.value, 0.002);
+        assert_eq!(alert.bound, 0.001);
+        // filtered out
+        assert!(state
+            .evaluate(rule, &msg("ETH/USDT", "ETHUSDT", 0.01), 2000)
+            .is_none());
+
+        // the cooldown is per series
+        assert!(state
+            .evaluate(rule, &msg("BTC/USDT", "BTCUSDT", 0.002), 3600000)
+            .is_none());
+        assert!(state
+            .evaluate(rule, &msg("BTC/USDT", "BTCUSD_PERP", 0.002), 3600000)
+            .is_some());
+        assert!(state
+            .evaluate(rule, &msg("BTC/USDT", "BTCUSDT", 0.002), 3601000)
+            .is_some());
+    }
+
+    #[test]
+    fn average_rule_compares_with_previous_values() {
+        let config = Config::parse(CONFIG).unwrap();
+        let rule = &config.rules[1];
+        let mut state = RuleState::new(rule);
+        let msg = |bar_size: i64, volume_usd: f64| {
+            json!({
+                "exchange": "binance",
+                "market_type": "spot",
+                "symbol": "BTCUSDT",
+                "pair": "BTC/USDT",
+                "bar_size": bar_size,
+                "volume_usd": volume_usd,
+            })
+        };
+
+        // no alert until the average of 3 previous values is known
+        for (i, volume_usd) in [100.0, 200.0, 10000.0].iter().enumerate() {
+            assert!(state
+                .evaluate(rule, &msg(300000, *volume_usd), i as i64)
+                .is_none());
+        }
+        // the average of 100, 200 and 10000 is 3433.33
+        assert!(state.evaluate(rule, &msg(300000, 30000.0), 3).is_none());
+        let alert = state.evaluate(rule, &msg(300000, 1e6), 4).unwrap();
+        assert!((alert.bound - (200.0 + 10000.0 + 30000.0) / 3.0 * 10.0).abs() < 1e-6);
+        assert!(state.evaluate(rule, &msg(60000, 1e9), 5).is_none());
+    }
+}
@@ -0,0 +1,173 @@
+use log::*;
+use std::{
+    collections::HashMap,
+    sync::mpsc::{Receiver, Sender},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use super::rule::Alert;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_RETRIES: u32 = 3;
+
+/// Deliver alerts to webhooks in a background thread, so that slow webhooks never block rules.
+pub fn create_webhook_thread(
+    webhooks: HashMap<String, String>, // name -> url
+) -> (Sender<(Alert, Vec<String>)>, JoinHandle<()>) {
+    let (tx, rx) = std::sync::mpsc::channel::<(Alert, Vec<String>)>();
+    let handle = std::thread::Builder::new()
+        .name("webhook".to_string())
+        .spawn(move || run(webhooks, rx))
+        .unwrap();
+    (tx, handle)
+}
+
+fn run(webhooks: HashMap<String, String>, rx: Receiver<(Alert, Vec<String>)>) {
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    for (alert, names) in rx {
+        for name in names.iter() {
+            let url = &webhooks[name];
+            for retry in 0..=MAX_RETRIES {
+                match agent.post(url).send_json(&alert) {
+                    Ok(_) => break,
+                    Err(err) => {
+                        error!(
+                            "Failed to deliver alert {} to webhook {}: {}",
+                            alert.rule, name, err
+                        );
+                        // Client errors won't go away by retrying
+                        if matches!(err, ureq::Error::Status(status, _) if status < 500) {
+                            break;
+                        }
+                        if retry < MAX_RETRIES {
+                            std::thread::sleep(Duration::from_secs(1 << retry));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::{Op, Source};
+    use serde_json::{json, Value};
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::mpsc::channel,
+    };
+
+    // An HTTP server which replies with statuses in order, returns its URL and received requests
+    fn serve(statuses: Vec<u16>) -> (String, Receiver<(String, Value)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+        let (tx, rx) = channel();
+        std::thread::spawn(move || {
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse::<usize>().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                tx.send((
+                    request_line.trim().to_string(),
+                    serde_json::from_slice::<Value>(&body).unwrap(),
+                ))
+                .unwrap();
+
+                let mut stream = reader.into_inner();
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    fn alert() -> Alert {
+        Alert {
+            rule: "btc_funding_rate_high".to_string(),
+            source: Source::FundingRate,
+            series: "binance-linear_swap-BTCUSDT".to_string(),
+            field: "funding_rate".to_string(),
+            value: 0.002,
+            op: Op::Gt,
+            bound: 0.001,
+            timestamp: 1600000000000,
+            message: json!({ "pair": "BTC/USDT", "funding_rate": 0.002 }),
+        }
+    }
+
+    // Deliver one alert to the webhook named ops and wait until it is done
+    fn deliver(url: String) {
+        let (tx, rx) = channel();
+        tx.send((alert(), vec!["ops".to_string()])).unwrap();
+        drop(tx);
+        run(vec![("ops".to_string(), url)].into_iter().collect(), rx);
+    }
+
+    #[test]
+    fn post_alert_as_json() {
+        let (url, requests) = serve(vec![200]);
+        deliver(url);
+
+        let (request_line, body) = requests.recv().unwrap();
+        assert_eq!(request_line, "POST /alerts HTTP/1.1");
+        assert_eq!(
+            body,
+            json!({
+                "rule": "btc_funding_rate_high",
+                "source": "funding_rate",
+                "series": "binance-linear_swap-BTCUSDT",
+                "field": "funding_rate",
+                "value": 0.002,
+                "op": ">",
+                "bound": 0.001,
+                "timestamp": 1600000000000i64,
+                "message": { "pair": "BTC/USDT", "funding_rate": 0.002 },
+            })
+        );
+        assert!(requests.try_recv().is_err());
+    }
+
+    #[test]
+    fn retry_on_server_errors() {
+        let (url, requests) = serve(vec![500, 503, 200]);
+        deliver(url);
+
+        let bodies: Vec<Value> = requests.try_iter().map(|(_, body)| body).collect();
+        assert_eq!(bodies.len(), 3);
+        assert!(bodies
+            .iter()
+            .all(|body| body["rule"] == "btc_funding_rate_high"));
+    }
+
+    #[test]
+    fn no_retry_on_client_errors() {
+        let (url, requests) = serve(vec![400, 200]);
+        deliver(url);
+
+        assert_eq!(requests.try_iter().count(), 1);
+    }
+}
@@ -0,0 +1,607 @@
+use crypto_market_type::MarketType;
+use crypto_message::TradeMsg;
+use crypto_msg_type::MessageType;
+use log::*;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
+use std::io::{BufRead, BufReader, BufWriter, Lines, Read, Write};
+use std::path::{Path, PathBuf};
+
+use transform::candlestick::{candlestick_key, Candlestick};
+use transform::constants::REDIS_TOPIC_CANDLESTICK_EXT;
+use transform::duration::parse_bar_sizes;
+use transform::parser::{self, Message, ParsedMsg};
+use transform::price::PriceSource;
+use transform::rollup::Rollup;
+use utils::pubsub::{create_bus, PublishError, Publisher};
+
+const BETA: f64 = 0.9; // same as price_updater
+const DEFAULT_BAR_SIZES: &str = "5m";
+// Base bars are closed once trades are this far past their end, in milliseconds,
+// later trades of closed bars are dropped
+const ALLOWED_LATENESS: i64 = 60000;
+
+/// Prices replayed from trades in archive files, so that the output doesn't
+/// depend on live prices in Redis.
+#[derive(Default)]
+struct ReplayPrices {
+    prices: HashMap<String, f64>,
+}
+
+impl ReplayPrices {
+    fn seed_price(&mut self, currency: &str, price: f64) {
+        self.prices.entry(currency.to_string()).or_insert(price);
+    }
+
+    // The same EMA as price_updater
+    fn update_price(&mut self, currency: &str, new_price: f64) {
+        let price_ema = if let Some(prev_price) = self.prices.get(currency) {
+            *prev_price * BETA + (1.0 - BETA) * new_price
+        } else {
+            new_price
+        };
+        self.prices.insert(currency.to_string(), price_ema);
+    }
+}
+
+impl PriceSource for ReplayPrices {
+    fn get_price(&self, currency: &str) -> Option<f64> {
+        self.prices.get(currency).copied()
+    }
+}
+
+// Trades which update prices in price_updater
+fn usd_price(trade: &TradeMsg) -> Option<(&str, f64)> {
+    match trade.market_type {
+        MarketType::Spot | MarketType::InverseSwap | MarketType::LinearSwap => {
+            let (base, quote) = trade.pair.split_once('/')?;
+            if quote == "USD" || quote == "USDT" || quote == "USDC" || quote == "BUSD" {
+                Some((base, trade.price))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+// Bar sizes are read from the BAR_SIZES environment variable, e.g., BAR_SIZES=1m,5m,15m,1h,4h,1d
+fn get_bar_sizes() -> Vec<i64> {
+    let text = if let Ok(text) = std::env::var("BAR_SIZES") {
+        text
+    } else {
+        info!(
+            "The BAR_SIZES environment variable is empty, using {} by default",
+            DEFAULT_BAR_SIZES
+        );
+        DEFAULT_BAR_SIZES.to_string()
+    };
+    parse_bar_sizes(&text).unwrap_or_else(|err| panic!("{}", err))
+}
+
+// Files under the given paths, in ascending order so that the output is deterministic
+fn list_files(paths: &[String]) -> Vec<PathBuf> {
+    fn walk(path: &Path, files: &mut Vec<PathBuf>) {
+        if path.is_dir() {
+            for entry in std::fs::read_dir(path).unwrap() {
+                walk(&entry.unwrap().path(), files);
+            }
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+    let mut files = Vec::new();
+    for path in paths {
+        walk(Path::new(path), &mut files);
+    }
+    files.sort();
+    files.dedup();
+    files
+}
+
+fn open_file(path: &Path) -> Box<dyn BufRead> {
+    let file = std::fs::File::open(path)
+        .unwrap_or_else(|err| panic!("Failed to open {}, {}", path.display(), err));
+    let reader: Box<dyn Read> = match path.extension().and_then(|x| x.to_str()) {
+        Some("gz") => Box::new(flate2::read::MultiGzDecoder::new(file)),
+        Some("xz") => Box::new(xz2::read::XzDecoder::new_multi_decoder(file)),
+        _ => Box::new(file),
+    };
+    Box::new(BufReader::new(reader))
+}
+
+// Trades within [start, end) of a carbonbot file, in the order of the file
+struct TradeReader {
+    path: PathBuf,
+    lines: Option<Lines<Box<dyn BufRead>>>, // None after a read error
+    parsed: VecDeque<TradeMsg>,
+    start: i64,
+    end: i64,
+    errors: BTreeMap<&'static str, u64>,
+}
+
+impl TradeReader {
+    fn open(path: &Path, start: i64, end: i64) -> Self {
+        TradeReader {
+            path: path.to_path_buf(),
+            lines: Some(open_file(path).lines()),
+            parsed: VecDeque::new(),
+            start,
+            end,
+            errors: BTreeMap::new(),
+        }
+    }
+}
+
+impl Iterator for TradeReader {
+    type Item = TradeMsg;
+
+    fn next(&mut self) -> Option<TradeMsg> {
+        loop {
+            if let Some(trade) = self.parsed.pop_front() {
+                return Some(trade);
+            }
+            let line = match self.lines.as_mut()?.next()? {
+                Ok(line) => line,
+                Err(err) => {
+                    // Truncated files are common if carbonbot was killed
+                    warn!("{}, {}", self.path.display(), err);
+                    self.lines = None;
+                    return None;
+                }
+            };
+            let raw_msg = match serde_json::from_str::<Message>(&line) {
+                Ok(raw_msg) => raw_msg,
+                Err(err) => {
+                    *self
+                        .errors
+                        .entry(parser::ParseError::InvalidPayload(err.to_string()).kind())
+                        .or_default() += 1;
+                    continue;
+                }
+            };
+            if raw_msg.msg_type != MessageType::Trade {
+                continue;
+            }
+            match parser::parse(&raw_msg, raw_msg.received_at as i64) {
+                Ok(msgs) => {
+                    for msg in msgs {
+                        if let ParsedMsg::Trade(mut trade) = msg {
+                            if trade.timestamp >= self.start && trade.timestamp < self.end {
+                                trade.json = String::new(); // save memory
+                                self.parsed.push_back(trade);
+                            }
+                        }
+                    }
+                }
+                Err(err) => *self.errors.entry(err.kind()).or_default() += 1,
+            }
+        }
+    }
+}
+
+// The order in which trades are aggregated
+fn trade_order(trade: &TradeMsg) -> (i64, &str, u32, &str, &str) {
+    (
+        trade.timestamp,
+        &trade.exchange,
+        trade.market_type as u32,
+        &trade.symbol,
+        &trade.trade_id,
+    )
+}
+
+// The next trade of a file, trades with the same order stay in the order of files
+struct Head {
+    trade: TradeMsg,
+    file: usize,
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Self) -> Ordering {
+        trade_order(&self.trade)
+            .cmp(&trade_order(&other.trade))
+            .then(self.file.cmp(&other.file))
+    }
+}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+/// Result of the first pass over carbonbot files, which reads one file at a time.
+struct Scan {
+    first_times: Vec<Option<i64>>, // the earliest trade time of each file
+    first_prices: HashMap<String, (i64, f64)>, // currency -> the earliest USD price
+    trades: u64,
+}
+
+fn scan_files(files: &[PathBuf], start: i64, end: i64) -> Scan {
+    let mut scan = Scan {
+        first_times: Vec::new(),
+        first_prices: HashMap::new(),
+        trades: 0,
+    };
+    let mut errors: BTreeMap<&'static str, u64> = BTreeMap::new();
+    for path in files {
+        info!("Scanning {}", path.display());
+        let mut reader = TradeReader::open(path, start, end);
+        let mut first_time: Option<i64> = None;
+        for trade in reader.by_ref() {
+            scan.trades += 1;
+            first_time = Some(first_time.map_or(trade.timestamp, |x| x.min(trade.timestamp)));
+            if let Some((currency, price)) = usd_price(&trade) {
+                let first_price = scan
+                    .first_prices
+                    .entry(currency.to_string())
+                    .or_insert((trade.timestamp, price));
+                if trade.timestamp < first_price.0 {
+                    *first_price = (trade.timestamp, price);
+                }
+            }
+        }
+        for (kind, count) in reader.errors {
+            *errors.entry(kind).or_default() += count;
+        }
+        scan.first_times.push(first_time);
+    }
+    for (kind, count) in errors {
+        warn!("{} messages failed with {}", count, kind);
+    }
+    scan
+}
+
+/// Trades of all files merged in time order.
+///
+/// Trades within a file are assumed to be roughly in time order, and a file is only opened
+/// once the merge reaches its earliest trade, so only files overlapping in time are open.
+struct MergedTrades {
+    files: Vec<PathBuf>,
+    start: i64,
+    end: i64,
+    unopened: BinaryHeap<Reverse<(i64, usize)>>, // (the earliest trade time, file index)
+    readers: HashMap<usize, TradeReader>,
+    heads: BinaryHeap<Reverse<Head>>,
+}
+
+impl MergedTrades {
+    fn new(files: &[PathBuf], first_times: &[Option<i64>], start: i64, end: i64) -> Self {
+        let unopened = first_times
+            .iter()
+            .enumerate()
+            .filter_map(|(index, first_time)| first_time.map(|x| Reverse((x, index))))
+            .collect();
+        MergedTrades {
+            files: files.to_vec(),
+            start,
+            end,
+            unopened,
+            readers: HashMap::new(),
+            heads: BinaryHeap::new(),
+        }
+    }
+
+    fn advance(&mut self, file: usize) {
+        if let Some(trade) = self.readers.get_mut(&file).unwrap().next() {
+            self.heads.push(Reverse(Head { trade, file }));
+        } else {
+            self.readers.remove(&file);
+        }
+    }
+}
+
+impl Iterator for MergedTrades {
+    type Item = TradeMsg;
+
+    fn next(&mut self) -> Option<TradeMsg> {
+        // Open files which may contain the next trade
+        while let Some(Reverse((first_time, file))) = self.unopened.peek().copied() {
+            if let Some(Reverse(head)) = self.heads.peek() {
+                if head.trade.timestamp < first_time {
+                    break;
+                }
+            }
+            self.unopened.pop();
+            debug!("Opening {}", self.files[file].display());
+            let reader = TradeReader::open(&self.files[file], self.start, self.end);
+            self.readers.insert(file, reader);
+            self.advance(file);
+        }
+        let Reverse(Head { trade, file }) = self.heads.pop()?;
+        self.advance(file);
+        Some(trade)
+    }
+}
+
+// Close base bars which end at or before the watermark and roll them up,
+// bars are output in ascending order of bar end time, bar size and series
+fn flush<F>(
+    candlesticks: &mut HashMap<String, Candlestick>,
+    rollup: &mut Rollup,
+    watermark: i64,
+    start: i64,
+    end: i64,
+    output: &mut F,
+) -> usize
+where
+    F: FnMut(&Candlestick),
+{
+    let keys: Vec<String> = candlesticks
+        .iter()
+        .filter(|(_, candlestick)| candlestick.timestamp <= watermark)
+        .map(|(key, _)| key.clone())
+        .collect();
+    let mut finished: Vec<Candlestick> = keys
+        .iter()
+        .map(|key| {
+            let mut candlestick = candlesticks.remove(key).unwrap();
+            candlestick.finalize();
+            rollup.push(&candlestick);
+            candlestick
+        })
+        .collect();
+    finished.extend(rollup.flush(|_| watermark));
+    finished.sort_by_cached_key(|x| (x.timestamp, x.bar_size, x.key()));
+
+    let mut count = 0;
+    // Bars partially outside of the time range are incomplete
+    for candlestick in finished.iter().filter(|candlestick| {
+        candlestick.count > 0
+            && candlestick.timestamp - candlestick.bar_size >= start
+            && candlestick.timestamp <= end
+    }) {
+        output(candlestick);
+        count += 1;
+    }
+    count
+}
+
+// Aggregate trades in time order into candlesticks the same way as candlestick_builder,
+// i.e., the smallest bar size is built from trades and larger ones are rolled up from it.
+// Returns the number of candlesticks output.
+fn build_candlesticks<I, F>(
+    trades: I,
+    mut prices: ReplayPrices,
+    bar_sizes: &[i64],
+    start: i64,
+    end: i64,
+    mut output: F,
+) -> usize
+where
+    I: Iterator<Item = TradeMsg>,
+    F: FnMut(&Candlestick),
+{
+    let mut rollup = Rollup::new(bar_sizes);
+    let bar_size = rollup.base_bar_size();
+    let mut candlesticks: HashMap<String, Candlestick> = HashMap::new();
+    let mut watermark = i64::MIN; // base bars ending at or before the watermark are closed
+    let mut count = 0;
+    let mut late = 0;
+    for trade in trades {
+        if let Some((currency, price)) = usd_price(&trade) {
+            prices.update_price(currency, price);
+        }
+        let bar_time = (trade.timestamp / bar_size) * bar_size + bar_size;
+        if bar_time <= watermark {
+            late += 1;
+            continue;
+        }
+        candlesticks
+            .entry(candlestick_key(&trade, bar_time))
+            .or_insert_with(|| {
+                Candlestick::new(
+                    trade.exchange.clone(),
+                    trade.market_type,
+                    trade.symbol.clone(),
+                    trade.pair.clone(),
+                    bar_size,
+                    bar_time,
+                )
+            })
+            .append(&trade, &prices);
+
+        // Advance the watermark by whole base bars
+        let time = (trade.timestamp - ALLOWED_LATENESS) / bar_size * bar_size;
+        if time > watermark {
+            watermark = time;
+            count += flush(
+                &mut candlesticks,
+                &mut rollup,
+                watermark,
+                start,
+                end,
+                &mut output,
+            );
+        }
+    }
+    count += flush(
+        &mut candlesticks,
+        &mut rollup,
+        i64::MAX,
+        start,
+        end,
+        &mut output,
+    );
+    if late > 0 {
+        warn!(
+            "Dropped {} trades which are more than {}ms behind later trades",
+            late, ALLOWED_LATENESS
+        );
+    }
+    count
+}
+
+fn print_usage() {
+    eprintln!("Usage: backfill <start> <end> <output> <path>...");
+    eprintln!();
+    eprintln!("  start   Unix timestamp in milliseconds, inclusive");
+    eprintln!("  end     Unix timestamp in milliseconds, exclusive");
+    eprintln!("  output  A file of JSON lines, - for stdout, or redis to publish to coinsignal:candlestick_ext on the message bus");
+    eprintln!("  path    carbonbot files or directories, .gz and .xz files are decompressed");
+}
+
+// Rebuild candlesticks from carbonbot archive files
+fn main() {
+    env_logger::init();
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 5 {
+        print_usage();
+        std::process::exit(1);
+    }
+    let (start, end) = match (args[1].parse::<i64>(), args[2].parse::<i64>()) {
+        (Ok(start), Ok(end)) if start < end => (start, end),
+        _ => {
+            print_usage();
+            std::process::exit(1);
+        }
+    };
+    let output = args[3].as_str();
+    let bar_sizes = get_bar_sizes();
+
+    let files = list_files(&args[4..]);
+    let scan = scan_files(&files, start, end);
+    info!("Read {} trades from {} files", scan.trades, files.len());
+    // Prices before the first trade of a currency are its first price
+    let mut prices = ReplayPrices::default();
+    for (currency, (_, price)) in scan.first_prices.iter() {
+        prices.seed_price(currency, *price);
+    }
+    if prices.get_price("BTC").is_none() {
+        error!("No BTC price in trades, which is required to calculate volume_btc");
+        std::process::exit(1);
+    }
+    let trades = MergedTrades::new(&files, &scan.first_times, start, end);
+
+    let count = if output == "redis" {
+        let redis_url = if std::env::var("REDIS_URL").is_err() {
+            info!(
+                "The REDIS_URL environment variable is empty, using redis://localhost:6379 by default"
+            );
+            "redis://localhost:6379".to_string()
+        } else {
+            std::env::var("REDIS_URL").unwrap()
+        };
+        let bus = create_bus(&redis_url);
+        let mut publisher = Publisher::new(bus.as_ref());
+        build_candlesticks(trades, prices, &bar_sizes, start, end, |candlestick| {
+            if let Err(PublishError::Redis(err)) =
+                publisher.publish::<Candlestick>(REDIS_TOPIC_CANDLESTICK_EXT, candlestick)
+            {
+                error!("{}", err);
+            }
+        })
+    } else {
+        let mut writer: Box<dyn Write> = if output == "-" {
+            Box::new(BufWriter::new(std::io::stdout()))
+        } else {
+            Box::new(BufWriter::new(std::fs::File::create(output).unwrap()))
+        };
+        let count = build_candlesticks(trades, prices, &bar_sizes, start, end, |candlestick| {
+            writeln!(writer, "{}", serde_json::to_string(candlestick).unwrap()).unwrap();
+        });
+        writer.flush().unwrap();
+        count
+    };
+    info!("Built {} candlesticks", count);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto_message::TradeSide;
+
+    const MINUTE: i64 = 60000;
+    const HOUR: i64 = 60 * MINUTE;
+    const START: i64 = 1599998400000; // 2020-09-13T12:00:00Z, aligned to an hour
+
+    fn trade(timestamp: i64) -> TradeMsg {
+        TradeMsg {
+            exchange: "binance".to_string(),
+            market_type: MarketType::Spot,
+            msg_type: MessageType::Trade,
+            pair: "BTC/USDT".to_string(),
+            symbol: "BTCUSDT".to_string(),
+            timestamp,
+            side: TradeSide::Buy,
+            price: 10000.0 + (timestamp / 15000 % 11) as f64,
+            quantity_base: 0.5,
+            quantity_quote: 5000.0,
+            quantity_contract: None,
+            trade_id: timestamp.to_string(),
+            json: String::new(),
+        }
+    }
+
+    fn backfill(trades: Vec<TradeMsg>) -> Vec<Candlestick> {
+        let mut prices = ReplayPrices::default();
+        prices.seed_price("BTC", 10000.0);
+        let mut candlesticks = Vec::new();
+        let count = build_candlesticks(
+            trades.into_iter(),
+            prices,
+            &[5 * MINUTE, HOUR],
+            START,
+            START + HOUR,
+            |candlestick| candlesticks.push(candlestick.clone()),
+        );
+        assert_eq!(count, candlesticks.len());
+        candlesticks
+    }
+
+    #[test]
+    fn bars_are_output_in_time_order_within_range() {
+        // One trade every 15 seconds within [START - 10m, START + 70m)
+        let trades: Vec<TradeMsg> = (-40..280).map(|i| trade(START + i * 15000)).collect();
+        let candlesticks = backfill(trades);
+
+        // Bars partially outside of the time range are dropped
+        assert_eq!(candlesticks.len(), 13);
+        let timestamps: Vec<(i64, i64)> = candlesticks
+            .iter()
+            .map(|x| (x.timestamp, x.bar_size))
+            .collect();
+        let mut expected: Vec<(i64, i64)> = (1..=12)
+            .map(|i| (START + i * 5 * MINUTE, 5 * MINUTE))
+            .collect();
+        expected.push((START + HOUR, HOUR));
+        assert_eq!(timestamps, expected);
+
+        let bar_1h = candlesticks.last().unwrap();
+        assert_eq!(bar_1h.count, 240);
+        assert_eq!(bar_1h.timestamp_start, START);
+        assert_eq!(bar_1h.timestamp_end, START + HOUR - 15000);
+        assert_eq!(bar_1h.open, trade(START).price);
+        assert_eq!(bar_1h.close, trade(START + HOUR - 15000).price);
+    }
+
+    #[test]
+    fn trades_later_than_allowed_lateness_are_dropped() {
+        let mut trades: Vec<TradeMsg> = (0..240).map(|i| trade(START + i * 15000)).collect();
+        // Arrives after the trade at START + 20m, behind by less than ALLOWED_LATENESS
+        trades.insert(81, trade(START + 19 * MINUTE + 1));
+        // Arrives after the trade at START + 20m, its 5m bar has been closed
+        trades.insert(82, trade(START + 2 * MINUTE + 1));
+        let candlesticks = backfill(trades);
+
+        let count = |bar_size: i64, timestamp: i64| {
+            candlesticks
+                .iter()
+                .find(|x| x.bar_size == bar_size && x.timestamp == timestamp)
+                .unwrap()
+                .count
+        };
+        assert_eq!(count(5 * MINUTE, START + 5 * MINUTE), 20);
+        assert_eq!(count(5 * MINUTE, START + 20 * MINUTE), 21);
+        assert_eq!(count(HOUR, START + HOUR), 241);
+    }
+}
@@ -0,0 +1,411 @@
+use crypto_market_type::MarketType;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use transform::constants::{REDIS_TOPIC_BASIS, REDIS_TOPIC_CANDLESTICK_EXT};
+use utils::{
+    pubsub::{create_bus, PublishError, Publisher},
+    wait_redis,
+};
+
+// Bars of the same timestamp are published together, wait a few seconds for all of them
+const GRACE_PERIOD: i64 = 5000;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const MILLIS_PER_YEAR: f64 = 365.0 * 86400000.0;
+
+// Fields of Candlestick used by basis
+#[derive(Deserialize)]
+struct Bar {
+    exchange: String,
+    market_type: MarketType,
+    symbol: String,
+    pair: String,
+    bar_size: i64,
+    timestamp: i64,
+    close: f64,
+    vwap: f64,
+    vwap_usd: f64,
+    volume_usd: f64,
+    count: i64,
+}
+
+impl Bar {
+    // close price converted to USD by the quote price implied by vwap_usd
+    fn close_usd(&self) -> f64 {
+        self.close * self.vwap_usd / self.vwap
+    }
+}
+
+/// Basis between a derivative and the spot market of the same base asset on the same exchange.
+#[derive(Serialize)]
+struct BasisMsg {
+    exchange: String,
+    base: String,
+    bar_size: i64,  // in millisecond
+    timestamp: i64, // bar end time, in millisecond
+
+    spot_symbol: String,
+    spot_pair: String,
+    spot_price: f64, // close price in USD
+
+    market_type: MarketType, // market type of the derivative
+    symbol: String,
+    pair: String,
+    price: f64, // close price in USD
+
+    basis: f64,                      // price - spot_price, in USD
+    premium: f64,                    // basis / spot_price, in percentage
+    annualized_premium: Option<f64>, // in percentage, null for perpetuals and unknown expiries
+}
+
+// Bars of one exchange, base asset, bar size and timestamp
+struct Group {
+    created_at: i64,
+    spot: Option<Bar>, // the spot bar with the highest volume_usd
+    derivatives: Vec<Bar>,
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// Parse the expiry of a future from a YYMMDD suffix, e.g., BTCUSD_230331 and BTC-USD-230331
+fn parse_expiry(symbol: &str) -> Option<i64> {
+    let digits: String = symbol
+        .chars()
+        .rev()
+        .take_while(|c| c.is_ascii_digit())
+        .collect::<Vec<char>>()
+        .into_iter()
+        .rev()
+        .collect();
+    if digits.len() != 6 {
+        return None;
+    }
+    let year = 2000 + digits[0..2].parse::<i64>().ok()?;
+    let month = digits[2..4].parse::<i64>().ok()?;
+    let day = digits[4..6].parse::<i64>().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // Most futures expire at 08:00 UTC
+    Some(days_from_civil(year, month, day) * 86400000 + 8 * 3600000)
+}
+
+fn calc_basis(spot: &Bar, derivative: &Bar) -> BasisMsg {
+    let spot_price = spot.close_usd();
+    let price = derivative.close_usd();
+    let basis = price - spot_price;
+    let premium = basis / spot_price * 100.0;
+
+    // Perpetuals never converge to spot at a fixed time, their carry is the funding rate
+    let annualized_premium = match derivative.market_type {
+        MarketType::InverseFuture | MarketType::LinearFuture => parse_expiry(&derivative.symbol)
+            .map(|expiry| expiry - derivative.timestamp)
+            .filter(|time_to_expiry| *time_to_expiry > 0)
+            .map(|time_to_expiry| premium * MILLIS_PER_YEAR / time_to_expiry as f64),
+        _ => None,
+    };
+
+    BasisMsg {
+        exchange: derivative.exchange.clone(),
+        base: derivative.pair.split('/').next().unwrap().to_string(),
+        bar_size: derivative.bar_size,
+        timestamp: derivative.timestamp,
+
+        spot_symbol: spot.symbol.clone(),
+        spot_pair: spot.pair.clone(),
+        spot_price,
+
+        market_type: derivative.market_type,
+        symbol: derivative.symbol.clone(),
+        pair: derivative.pair.clone(),
+        price,
+
+        basis,
+        premium,
+        annualized_premium,
+    }
+}
+
+// Put a bar into the group of its exchange, base asset, bar size and timestamp
+fn add_bar(groups: &mut HashMap<String, Group>, bar: Bar, now: i64) {
+    if bar.count == 0 || bar.vwap <= 0.0 {
+        return;
+    }
+    let key = if let Some((base, _)) = bar.pair.split_once('/') {
+        format!(
+            "{}-{}-{}-{}",
+            bar.exchange, base, bar.bar_size, bar.timestamp
+        )
+    } else {
+        return;
+    };
+    let group = groups.entry(key).or_insert_with(|| Group {
+        created_at: now,
+        spot: None,
+        derivatives: Vec::new(),
+    });
+    match bar.market_type {
+        MarketType::Spot
+            if group
+                .spot
+                .as_ref()
+                .map(|spot| spot.volume_usd < bar.volume_usd)
+                .unwrap_or(true) =>
+        {
+            group.spot = Some(bar)
+        }
+        MarketType::InverseSwap
+        | MarketType::LinearSwap
+        | MarketType::InverseFuture
+        | MarketType::LinearFuture => group.derivatives.push(bar),
+        _ => (),
+    }
+}
+
+// Remove groups which have waited long enough, returns basis of their derivatives
+fn flush_groups(groups: &mut HashMap<String, Group>, now: i64) -> Vec<BasisMsg> {
+    let keys: Vec<String> = groups
+        .iter()
+        .filter(|(_, group)| group.created_at + GRACE_PERIOD <= now)
+        .map(|(key, _)| key.clone())
+        .collect();
+    let mut output = Vec::new();
+    for key in keys {
+        let group = groups.remove(&key).unwrap();
+        if let Some(spot) = group.spot {
+            for derivative in group.derivatives.iter() {
+                output.push(calc_basis(&spot, derivative));
+            }
+        }
+    }
+    output
+}
+
+// Calculate basis and premium between spot and derivative markets
+fn main() {
+    env_logger::init();
+    let redis_url = if std::env::var("REDIS_URL").is_err() {
+        info!(
+            "The REDIS_URL environment variable is empty, using redis://localhost:6379 by default"
+        );
+        "redis://localhost:6379"
+    } else {
+        let url = std::env::var("REDIS_URL").unwrap();
+        Box::leak(url.into_boxed_str())
+    };
+    wait_redis(redis_url);
+    let bus = create_bus(redis_url);
+
+    let mut publisher = Publisher::new(bus.as_ref());
+    let mut subscriber = bus.subscribe(&[REDIS_TOPIC_CANDLESTICK_EXT]);
+    let mut groups: HashMap<String, Group> = HashMap::new();
+
+    loop {
+        // next_message() times out periodically so that groups are published even if no bar follows
+        if let Some(msg) = subscriber.next_message(Some(FLUSH_INTERVAL)) {
+            match serde_json::from_str::<Bar>(&msg.payload) {
+                Ok(bar) => add_bar(&mut groups, bar, now_ms()),
+                Err(err) => warn!("{}, {}", err, msg.payload),
+            }
+        }
+
+        for msg in flush_groups(&mut groups, now_ms()) {
+            if let Err(PublishError::Redis(err)) =
+                publisher.publish::<BasisMsg>(REDIS_TOPIC_BASIS, &msg)
+            {
+                error!("{}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 86400000;
+    const HOUR: i64 = 3600000;
+
+    fn bar(market_type: MarketType, symbol: &str, pair: &str, close: f64, timestamp: i64) -> Bar {
+        Bar {
+            exchange: "binance".to_string(),
+            market_type,
+            symbol: symbol.to_string(),
+            pair: pair.to_string(),
+            bar_size: 60000,
+            timestamp,
+            close,
+            vwap: close,
+            vwap_usd: close,
+            volume_usd: 1000.0,
+            count: 1,
+        }
+    }
+
+    #[test]
+    fn days_since_epoch() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        // 2020 and 2000 are leap years, 2100 is not
+        assert_eq!(days_from_civil(2020, 2, 29), 18321);
+        assert_eq!(days_from_civil(2020, 3, 1), 18322);
+        assert_eq!(
+            days_from_civil(2100, 3, 1) - days_from_civil(2100, 2, 28),
+            1
+        );
+        assert_eq!(days_from_civil(2100, 3, 1), 47541);
+        assert_eq!(
+            days_from_civil(2024, 1, 1) - days_from_civil(2023, 12, 31),
+            1
+        );
+    }
+
+    #[test]
+    fn parse_quarterly_expiry() {
+        for symbol in ["BTCUSD_240329", "BTC-USD-240329", "BTCUSDT_240329"] {
+            assert_eq!(
+                parse_expiry(symbol),
+                Some(19811 * DAY + 8 * HOUR),
+                "{}",
+                symbol
+            );
+        }
+        assert_eq!(parse_expiry("BTCUSD_231229"), Some(19720 * DAY + 8 * HOUR));
+        // The quarter from December to March spans a new year and February 29th
+        assert_eq!(
+            parse_expiry("BTCUSD_240329").unwrap() - parse_expiry("BTCUSD_231229").unwrap(),
+            91 * DAY
+        );
+
+        for symbol in [
+            "BTCUSD_PERP",
+            "BTCUSDT",
+            "BTCUSD_2403",
+            "BTC-USD-1240329",
+            "BTCUSD_241329",
+            "BTCUSD_240300",
+            "BTCUSD_240332",
+        ] {
+            assert_eq!(parse_expiry(symbol), None, "{}", symbol);
+        }
+    }
+
+    #[test]
+    fn annualized_basis() {
+        let now = parse_expiry("BTCUSD_231229").unwrap();
+        let spot = bar(MarketType::Spot, "BTCUSDT", "BTC/USDT", 20000.0, now);
+
+        let future = bar(
+            MarketType::InverseFuture,
+            "BTCUSD_240329",
+            "BTC/USD",
+            20200.0,
+            now,
+        );
+        let msg = calc_basis(&spot, &future);
+        assert_eq!(msg.base, "BTC");
+        assert_eq!(
+            (msg.spot_price, msg.price, msg.basis),
+            (20000.0, 20200.0, 200.0)
+        );
+        assert!((msg.premium - 1.0).abs() < 1e-12);
+        let annualized = msg.annualized_premium.unwrap();
+        assert!((annualized - 365.0 / 91.0).abs() < 1e-9, "{}", annualized);
+
+        // Prices in other quote currencies are converted to USD by vwap_usd / vwap
+        let mut future = bar(
+            MarketType::LinearFuture,
+            "BTCEUR_240329",
+            "BTC/EUR",
+            16000.0,
+            now,
+        );
+        future.vwap_usd = future.vwap * 1.25;
+        let msg = calc_basis(&spot, &future);
+        assert_eq!(msg.price, 20000.0);
+        assert_eq!(msg.annualized_premium, Some(0.0));
+
+        // Perpetuals and expired futures have no annualized premium
+        let swap = bar(
+            MarketType::InverseSwap,
+            "BTCUSD_PERP",
+            "BTC/USD",
+            20200.0,
+            now,
+        );
+        assert_eq!(calc_basis(&spot, &swap).annualized_premium, None);
+        let expired = bar(
+            MarketType::InverseFuture,
+            "BTCUSD_231229",
+            "BTC/USD",
+            20200.0,
+            now,
+        );
+        assert_eq!(calc_basis(&spot, &expired).annualized_premium, None);
+    }
+
+    #[test]
+    fn flush_after_grace_period() {
+        let timestamp = parse_expiry("BTCUSD_231229").unwrap();
+        let mut groups = HashMap::new();
+        let mut quiet_spot = bar(MarketType::Spot, "BTCBUSD", "BTC/BUSD", 19000.0, timestamp);
+        quiet_spot.volume_usd = 1.0;
+        add_bar(&mut groups, quiet_spot, 0);
+        add_bar(
+            &mut groups,
+            bar(MarketType::Spot, "BTCUSDT", "BTC/USDT", 20000.0, timestamp),
+            0,
+        );
+        add_bar(
+            &mut groups,
+            bar(
+                MarketType::InverseSwap,
+                "BTCUSD_PERP",
+                "BTC/USD",
+                20100.0,
+                timestamp,
+            ),
+            1000,
+        );
+        // A group without spot bars has no basis
+        add_bar(
+            &mut groups,
+            bar(
+                MarketType::InverseSwap,
+                "ETHUSD_PERP",
+                "ETH/USD",
+                1500.0,
+                timestamp,
+            ),
+            0,
+        );
+        assert_eq!(groups.len(), 2);
+
+        assert!(flush_groups(&mut groups, GRACE_PERIOD - 1).is_empty());
+        // The last group is flushed without another bar arriving
+        let output = flush_groups(&mut groups, GRACE_PERIOD);
+        assert!(groups.is_empty());
+        assert_eq!(output.len(), 1);
+        // The spot market with the highest volume_usd is the reference
+        assert_eq!(output[0].spot_symbol, "BTCUSDT");
+        assert_eq!(output[0].basis, 100.0);
+    }
+}
@@ -0,0 +1,573 @@
+use crypto_market_type::MarketType;
+use crypto_message::{BboMsg, OrderBookMsg};
+use log::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use transform::constants::{
+    REDIS_TOPIC_BBO_PARSED, REDIS_TOPIC_BOOK_STATS, REDIS_TOPIC_L2_SNAPSHOT_PARSED,
+};
+use transform::duration::parse_bar_sizes;
+use transform::price::{usd_price, PriceSource};
+use utils::{
+    pubsub::{create_bus, PublishError, Publisher},
+    wait_redis, PriceCache,
+};
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+// Bars are closed by the wall clock minus this grace period if a symbol is quiet
+const GRACE_PERIOD: i64 = 3000;
+// The top of book is dropped if it has not been updated for this long, in milliseconds
+const MAX_QUOTE_AGE: i64 = 60000;
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+// Read bar sizes from the BAR_SIZES environment variable, in milliseconds
+fn get_bar_sizes() -> Vec<i64> {
+    let text = if let Ok(text) = std::env::var("BAR_SIZES") {
+        text
+    } else {
+        info!("The BAR_SIZES environment variable is empty, using 5m by default");
+        "5m".to_string()
+    };
+    parse_bar_sizes(&text).unwrap_or_else(|err| panic!("{}", err))
+}
+
+// Best bid and ask of a symbol
+#[derive(Clone, Copy)]
+struct TopOfBook {
+    bid_price: f64,
+    bid_quantity_quote: f64,
+    ask_price: f64,
+    ask_quantity_quote: f64,
+}
+
+impl TopOfBook {
+    fn from_bbo(msg: &BboMsg) -> Option<Self> {
+        TopOfBook {
+            bid_price: msg.bid_price,
+            bid_quantity_quote: msg.bid_quantity_quote,
+            ask_price: msg.ask_price,
+            ask_quantity_quote: msg.ask_quantity_quote,
+        }
+        .validate()
+    }
+
+    fn from_orderbook(msg: &OrderBookMsg) -> Option<Self> {
+        let bid = msg.bids.first()?;
+        let ask = msg.asks.first()?;
+        TopOfBook {
+            bid_price: bid.price,
+            bid_quantity_quote: bid.quantity_quote,
+            ask_price: ask.price,
+            ask_quantity_quote: ask.quantity_quote,
+        }
+        .validate()
+    }
+
+    // Drop empty or crossed books
+    fn validate(self) -> Option<Self> {
+        if self.bid_price > 0.0 && self.ask_price >= self.bid_price {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
+    fn spread_bps(&self) -> f64 {
+        let mid = (self.bid_price + self.ask_price) / 2.0;
+        (self.ask_price - self.bid_price) / mid * 10000.0
+    }
+
+    // Mid price weighted by the quantity on the opposite side
+    fn microprice(&self) -> f64 {
+        let total = self.bid_quantity_quote + self.ask_quantity_quote;
+        if total > 0.0 {
+            (self.bid_price * self.ask_quantity_quote + self.ask_price * self.bid_quantity_quote)
+                / total
+        } else {
+            (self.bid_price + self.ask_price) / 2.0
+        }
+    }
+}
+
+// A BBO or L2 snapshot message reduced to the top of book
+struct BookUpdate {
+    exchange: String,
+    market_type: MarketType,
+    symbol: String,
+    pair: String,
+    timestamp: i64,
+    top: Option<TopOfBook>,
+}
+
+impl BookUpdate {
+    fn from_bbo(msg: &BboMsg) -> Self {
+        BookUpdate {
+            exchange: msg.exchange.clone(),
+            market_type: msg.market_type,
+            symbol: msg.symbol.clone(),
+            pair: msg.pair.clone(),
+            timestamp: msg.timestamp,
+            top: TopOfBook::from_bbo(msg),
+        }
+    }
+
+    fn from_orderbook(msg: &OrderBookMsg) -> Self {
+        BookUpdate {
+            exchange: msg.exchange.clone(),
+            market_type: msg.market_type,
+            symbol: msg.symbol.clone(),
+            pair: msg.pair.clone(),
+            timestamp: msg.timestamp,
+            top: TopOfBook::from_orderbook(msg),
+        }
+    }
+}
+
+/// Top-of-book statistics of a bar, time-weighted over the bar.
+#[derive(Serialize)]
+struct BookStats {
+    exchange: String,
+    market_type: MarketType,
+    symbol: String,
+    pair: String,
+    bar_size: i64,   // in millisecond
+    timestamp: i64,  // bar end time, in millisecond
+    spread_bps: f64, // (ask - bid) / mid, in basis points
+    #[serde(skip_serializing_if = "Option::is_none")]
+    depth_usd: Option<f64>, // bid and ask quantity at the top of book, in USD
+    microprice: f64,
+    best_bid: f64, // the last best bid of this bar
+    best_ask: f64, // the last best ask of this bar
+    updates: u64,  // number of book updates in this bar
+}
+
+// Accumulates a time-weighted bar
+struct BookBar {
+    timestamp: i64, // bar end time, in millisecond
+    duration: i64,  // milliseconds with a known top of book
+    spread_bps_sum: f64,
+    microprice_sum: f64,
+    depth_usd_sum: f64,
+    depth_duration: i64, // milliseconds with a known quote price
+    last: Option<TopOfBook>,
+    updates: u64,
+}
+
+impl BookBar {
+    fn new(timestamp: i64) -> Self {
+        BookBar {
+            timestamp,
+            duration: 0,
+            spread_bps_sum: 0.0,
+            microprice_sum: 0.0,
+            depth_usd_sum: 0.0,
+            depth_duration: 0,
+            last: None,
+            updates: 0,
+        }
+    }
+
+    fn accumulate(&mut self, top: &TopOfBook, quote_price: Option<f64>, elapsed: i64) {
+        if elapsed <= 0 {
+            return;
+        }
+        self.duration += elapsed;
+        self.spread_bps_sum += top.spread_bps() * elapsed as f64;
+        self.microprice_sum += top.microprice() * elapsed as f64;
+        if let Some(quote_price) = quote_price {
+            self.depth_usd_sum +=
+                (top.bid_quantity_quote + top.ask_quantity_quote) * quote_price * elapsed as f64;
+            self.depth_duration += elapsed;
+        }
+        self.last = Some(*top);
+    }
+}
+
+// Top of book and open bars of a symbol
+struct Series {
+    exchange: String,
+    market_type: MarketType,
+    symbol: String,
+    pair: String,
+    quote: String,
+    top: Option<TopOfBook>,
+    top_time: i64,  // timestamp of the last top of book update
+    last_time: i64, // time up to which the top of book has been accumulated
+    bars: HashMap<i64, BookBar>,
+}
+
+impl Series {
+    fn new(exchange: &str, market_type: MarketType, symbol: &str, pair: &str) -> Self {
+        Series {
+            exchange: exchange.to_string(),
+            market_type,
+            symbol: symbol.to_string(),
+            pair: pair.to_string(),
+            quote: pair.split('/').nth(1).unwrap_or_default().to_string(),
+            top: None,
+            top_time: 0,
+            last_time: 0,
+            bars: HashMap::new(),
+        }
+    }
+
+    fn quote_price(&self, prices: &dyn PriceSource) -> Option<f64> {
+        usd_price(&self.quote, prices)
+    }
+
+    // Accumulate the current top of book up to `time` and close finished bars
+    fn advance(
+        &mut self,
+        time: i64,
+        bar_sizes: &[i64],
+        prices: &dyn PriceSource,
+        output: &mut Vec<BookStats>,
+    ) {
+        if time <= self.last_time {
+            return;
+        }
+        // A stale top of book is not carried forward
+        let top = self.top;
+        let until = match top {
+            Some(_) => std::cmp::min(time, self.top_time + MAX_QUOTE_AGE),
+            None => self.last_time,
+        };
+        let quote_price = self.quote_price(prices);
+        for bar_size in bar_sizes {
+            let mut from = self.last_time;
+            loop {
+                if from >= until && !self.bars.contains_key(bar_size) {
+                    break;
+                }
+                let bar = self
+                    .bars
+                    .entry(*bar_size)
+                    .or_insert_with(|| BookBar::new((from / bar_size) * bar_size + bar_size));
+                if let Some(top) = top {
+                    let to = std::cmp::min(until, bar.timestamp);
+                    bar.accumulate(&top, quote_price, to - from);
+                }
+                if bar.timestamp > time {
+                    break;
+                }
+                let bar = self.bars.remove(bar_size).unwrap();
+                from = bar.timestamp;
+                if let Some(stats) = self.finalize(*bar_size, bar) {
+                    output.push(stats);
+                }
+            }
+        }
+        self.last_time = time;
+        if until < time {
+            self.top = None;
+        }
+    }
+
+    // Whether the top of book is stale and all bars are closed
+    fn is_idle(&self) -> bool {
+        self.top.is_none() && self.bars.is_empty()
+    }
+
+    fn update(
+        &mut self,
+        top: TopOfBook,
+        timestamp: i64,
+        bar_sizes: &[i64],
+        prices: &dyn PriceSource,
+        output: &mut Vec<BookStats>,
+    ) {
+        if self.top.is_none() {
+            // Never reopen bars which have been closed
+            self.last_time = self.last_time.max(timestamp);
+        }
+        self.advance(timestamp, bar_sizes, prices, output);
+        // Updates older than last_time take effect from last_time
+        self.top = Some(top);
+        self.top_time = self.last_time;
+        for bar_size in bar_sizes {
+            let time = self.last_time;
+            let bar = self
+                .bars
+                .entry(*bar_size)
+                .or_insert_with(|| BookBar::new((time / bar_size) * bar_size + bar_size));
+            bar.updates += 1;
+        }
+    }
+
+    fn finalize(&self, bar_size: i64, bar: BookBar) -> Option<BookStats> {
+        let last = bar.last?;
+        if bar.duration == 0 {
+            return None;
+        }
+        Some(BookStats {
+            exchange: self.exchange.clone(),
+            market_type: self.market_type,
+            symbol: self.symbol.clone(),
+            pair: self.pair.clone(),
+            bar_size,
+            timestamp: bar.timestamp,
+            spread_bps: bar.spread_bps_sum / bar.duration as f64,
+            depth_usd: if bar.depth_duration > 0 {
+                Some(bar.depth_usd_sum / bar.depth_duration as f64)
+            } else {
+                None
+            },
+            microprice: bar.microprice_sum / bar.duration as f64,
+            best_bid: last.bid_price,
+            best_ask: last.ask_price,
+            updates: bar.updates,
+        })
+    }
+}
+
+fn series_key(exchange: &str, market_type: MarketType, pair: &str, symbol: &str) -> String {
+    format!("{}-{}-{}-{}", exchange, market_type, pair, symbol)
+}
+
+// Aggregate best bid and ask into time-weighted top-of-book statistics
+fn main() {
+    env_logger::init();
+    let redis_url = if std::env::var("REDIS_URL").is_err() {
+        info!(
+            "The REDIS_URL environment variable is empty, using redis://localhost:6379 by default"
+        );
+        "redis://localhost:6379"
+    } else {
+        let url = std::env::var("REDIS_URL").unwrap();
+        Box::leak(url.into_boxed_str())
+    };
+    wait_redis(redis_url);
+
+    let price_cache = PriceCache::new(redis_url);
+    price_cache.wait_until_ready();
+
+    let bar_sizes = get_bar_sizes();
+    let mut series_map: HashMap<String, Series> = HashMap::new();
+    let bus = create_bus(redis_url);
+    let mut publisher = Publisher::new(bus.as_ref());
+    let mut subscriber = bus.subscribe(&[REDIS_TOPIC_BBO_PARSED, REDIS_TOPIC_L2_SNAPSHOT_PARSED]);
+
+    let mut last_flush_time = now_ms();
+    loop {
+        let mut output: Vec<BookStats> = Vec::new();
+        // next_message() times out periodically so that bars of quiet symbols are closed
+        if let Some(msg) = subscriber.next_message(Some(FLUSH_INTERVAL)) {
+            let payload = msg.payload;
+            let update = if msg.channel == REDIS_TOPIC_BBO_PARSED {
+                serde_json::from_str::<BboMsg>(&payload).map(|msg| BookUpdate::from_bbo(&msg))
+            } else {
+                serde_json::from_str::<OrderBookMsg>(&payload)
+                    .map(|msg| BookUpdate::from_orderbook(&msg))
+            };
+            match update {
+                Ok(update) => {
+                    if let Some(top) = update.top {
+                        let key = series_key(
+                            &update.exchange,
+                            update.market_type,
+                            &update.pair,
+                            &update.symbol,
+                        );
+                        let series = series_map.entry(key).or_insert_with(|| {
+                            Series::new(
+                                &update.exchange,
+                                update.market_type,
+                                &update.symbol,
+                                &update.pair,
+                            )
+                        });
+                        series.update(top, update.timestamp, &bar_sizes, &price_cache, &mut output);
+                    }
+                }
+                Err(err) => warn!("{}, {}", err, payload),
+            }
+        }
+
+        let now = now_ms();
+        if now - last_flush_time >= FLUSH_INTERVAL.as_millis() as i64 {
+            for series in series_map.values_mut() {
+                series.advance(now - GRACE_PERIOD, &bar_sizes, &price_cache, &mut output);
+            }
+            // Remove symbols which are no longer quoted
+            series_map.retain(|_, series| !series.is_idle());
+            last_flush_time = now;
+        }
+
+        for stats in output {
+            match publisher.publish::<BookStats>(REDIS_TOPIC_BOOK_STATS, &stats) {
+                Ok(()) => (),
+                Err(PublishError::Redis(err)) => error!("{}", err),
+                // Publisher buffers messages while Redis is down, and logs outages and drops itself
+                Err(PublishError::Disconnected) | Err(PublishError::BufferFull) => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transform::price::StaticPrices;
+
+    const MINUTE: i64 = 60000;
+    const START: i64 = 1599998400000; // 2020-09-13T12:00:00Z
+    const SECOND: i64 = 1000;
+
+    fn top(
+        bid_price: f64,
+        ask_price: f64,
+        bid_quantity_quote: f64,
+        ask_quantity_quote: f64,
+    ) -> TopOfBook {
+        TopOfBook {
+            bid_price,
+            bid_quantity_quote,
+            ask_price,
+            ask_quantity_quote,
+        }
+    }
+
+    fn series() -> Series {
+        Series::new("binance", MarketType::Spot, "BTCUSDT", "BTC/USDT")
+    }
+
+    fn assert_approx(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn time_weighted_across_bars() {
+        let prices = StaticPrices::default();
+        let a = top(100.0, 101.0, 1000.0, 3000.0);
+        let b = top(100.0, 100.5, 3000.0, 1000.0);
+        assert_approx(a.microprice(), 100.25);
+        assert_approx(b.microprice(), 100.375);
+
+        let mut series = series();
+        let mut output = Vec::new();
+        series.update(a, START + 30 * SECOND, &[MINUTE], &prices, &mut output);
+        assert!(output.is_empty());
+
+        // The bar ending at START + 1m only has the first quote
+        series.update(b, START + 90 * SECOND, &[MINUTE], &prices, &mut output);
+        assert_eq!(output.len(), 1);
+        let stats = &output[0];
+        assert_eq!(stats.timestamp, START + MINUTE);
+        assert_eq!(stats.updates, 1);
+        assert_approx(stats.spread_bps, a.spread_bps());
+        assert_approx(stats.microprice, 100.25);
+        assert_eq!(stats.depth_usd, Some(4000.0));
+
+        // The next bar has 30 seconds of each quote
+        output.clear();
+        series.advance(START + 2 * MINUTE, &[MINUTE], &prices, &mut output);
+        assert_eq!(output.len(), 1);
+        let stats = &output[0];
+        assert_eq!(stats.timestamp, START + 2 * MINUTE);
+        assert_eq!(stats.updates, 1);
+        assert_approx(stats.spread_bps, (a.spread_bps() + b.spread_bps()) / 2.0);
+        assert_approx(stats.microprice, (100.25 + 100.375) / 2.0);
+        assert_eq!((stats.best_bid, stats.best_ask), (100.0, 100.5));
+    }
+
+    #[test]
+    fn drop_stale_quote() {
+        // No price of the quote currency, so depth is unknown
+        let prices = StaticPrices::default();
+        let mut series = Series::new("kraken", MarketType::Spot, "XBT/EUR", "BTC/EUR");
+        let mut output = Vec::new();
+        let a = top(100.0, 101.0, 1000.0, 3000.0);
+        series.update(a, START + 10 * SECOND, &[MINUTE], &prices, &mut output);
+        assert!(!series.is_idle());
+
+        // The quote is carried forward for MAX_QUOTE_AGE only
+        series.advance(START + 3 * MINUTE, &[MINUTE], &prices, &mut output);
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[0].timestamp, START + MINUTE);
+        assert_eq!(output[1].timestamp, START + 2 * MINUTE);
+        assert_approx(output[1].spread_bps, a.spread_bps());
+        assert_eq!(output[1].depth_usd, None);
+        assert_eq!(output[1].updates, 0);
+
+        // Symbols without a quote or open bars are evicted
+        assert!(series.is_idle());
+        output.clear();
+        series.advance(START + 10 * MINUTE, &[MINUTE], &prices, &mut output);
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn never_reopen_closed_bars() {
+        let prices = StaticPrices::default();
+        let mut series = series();
+        let mut output = Vec::new();
+        series.update(
+            top(100.0, 101.0, 1.0, 1.0),
+            START + 10 * SECOND,
+            &[MINUTE],
+            &prices,
+            &mut output,
+        );
+        series.advance(START + 2 * MINUTE, &[MINUTE], &prices, &mut output);
+        assert!(series.is_idle());
+        output.clear();
+
+        // A late quote takes effect from the latest closed bar
+        let b = top(100.0, 100.5, 1.0, 1.0);
+        series.update(b, START + 30 * SECOND, &[MINUTE], &prices, &mut output);
+        assert!(output.is_empty());
+        series.advance(START + 3 * MINUTE, &[MINUTE], &prices, &mut output);
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].timestamp, START + 3 * MINUTE);
+        assert_approx(output[0].spread_bps, b.spread_bps());
+
+        // An out-of-order update of an active series doesn't move time backwards
+        output.clear();
+        let c = top(99.0, 101.0, 1.0, 1.0);
+        series.update(c, START + 150 * SECOND, &[MINUTE], &prices, &mut output);
+        series.update(b, START + 140 * SECOND, &[MINUTE], &prices, &mut output);
+        series.advance(START + 4 * MINUTE, &[MINUTE], &prices, &mut output);
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].timestamp, START + 4 * MINUTE);
+        assert_eq!(output[0].updates, 2);
+        assert_approx(output[0].spread_bps, b.spread_bps());
+    }
+
+    #[test]
+    fn evict_idle_series() {
+        let prices = StaticPrices::default();
+        let mut series_map: HashMap<String, Series> = HashMap::new();
+        let mut output = Vec::new();
+        for (symbol, timestamp) in [("BTCUSDT", START), ("ETHUSDT", START + 5 * MINUTE)] {
+            let mut series = Series::new("binance", MarketType::Spot, symbol, "BTC/USDT");
+            series.update(
+                top(100.0, 101.0, 1.0, 1.0),
+                timestamp,
+                &[MINUTE],
+                &prices,
+                &mut output,
+            );
+            series_map.insert(symbol.to_string(), series);
+        }
+
+        for series in series_map.values_mut() {
+            series.advance(START + 5 * MINUTE + SECOND, &[MINUTE], &prices, &mut output);
+        }
+        series_map.retain(|_, series| !series.is_idle());
+        let symbols: Vec<&String> = series_map.keys().collect();
+        assert_eq!(symbols, ["ETHUSDT"]);
+    }
+}
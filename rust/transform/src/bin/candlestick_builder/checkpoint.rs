@@ -0,0 +1,329 @@
+use log::*;
+use redis::Commands;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use transform::sketch::QuantileSketch;
+
+use transform::candlestick::Candlestick;
+
+use crate::composite::SavedComposite;
+
+const REDIS_KEY_CHECKPOINT: &str = "coinsignal:checkpoint:candlestick_builder";
+
+// Candlestick doesn't serialize its dedup set and sketches, so they are saved separately
+#[derive(Serialize, Deserialize)]
+struct SavedCandlestick {
+    candlestick: Candlestick,
+    dedup: Vec<u64>,
+    price_sketch: QuantileSketch,
+    size_sketch: QuantileSketch,
+}
+
+impl SavedCandlestick {
+    fn save(candlestick: &Candlestick) -> Self {
+        let mut dedup: Vec<u64> = candlestick.dedup.iter().copied().collect();
+        dedup.sort_unstable();
+        SavedCandlestick {
+            candlestick: candlestick.clone(),
+            dedup,
+            price_sketch: candlestick.price_sketch.clone(),
+            size_sketch: candlestick.size_sketch.clone(),
+        }
+    }
+
+    fn restore(self) -> Candlestick {
+        let mut candlestick = self.candlestick;
+        candlestick.dedup = self.dedup.into_iter().collect();
+        candlestick.price_sketch = self.price_sketch;
+        candlestick.size_sketch = self.size_sketch;
+        candlestick
+    }
+}
+
+/// A snapshot of all in-flight candlesticks.
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub timestamp: i64,
+    candlesticks: Vec<SavedCandlestick>,
+    published: Vec<SavedCandlestick>,
+    rollup: Vec<SavedCandlestick>,
+    #[serde(default)]
+    info_bars: Vec<SavedCandlestick>,
+    #[serde(default)]
+    composites: Vec<SavedComposite>,
+}
+
+/// Candlesticks restored from a checkpoint.
+pub struct RestoredCandlesticks {
+    pub candlesticks: Vec<Candlestick>,  // open bars
+    pub published: Vec<Candlestick>,     // published bars which can still be amended
+    pub rollup: Vec<Candlestick>,        // open rolled-up bars
+    pub info_bars: Vec<Candlestick>,     // open tick, volume and dollar bars
+    pub composites: Vec<SavedComposite>, // open composite bars
+}
+
+impl Checkpoint {
+    pub fn new<'a>(
+        timestamp: i64,
+        candlesticks: impl Iterator<Item = &'a Candlestick>,
+        published: impl Iterator<Item = &'a Candlestick>,
+        rollup: impl Iterator<Item = &'a Candlestick>,
+        info_bars: impl Iterator<Item = &'a Candlestick>,
+        composites: Vec<SavedComposite>,
+    ) -> Self {
+        Checkpoint {
+            timestamp,
+            candlesticks: candlesticks.map(SavedCandlestick::save).collect(),
+            published: published.map(SavedCandlestick::save).collect(),
+            rollup: rollup.map(SavedCandlestick::save).collect(),
+            info_bars: info_bars.map(SavedCandlestick::save).collect(),
+            composites,
+        }
+    }
+
+    pub fn restore(self) -> RestoredCandlesticks {
+        let restore = |saved: Vec<SavedCandlestick>| -> Vec<Candlestick> {
+            saved.into_iter().map(SavedCandlestick::restore).collect()
+        };
+        RestoredCandlesticks {
+            candlesticks: restore(self.candlesticks),
+            published: restore(self.published),
+            rollup: restore(self.rollup),
+            info_bars: restore(self.info_bars),
+            composites: self.composites,
+        }
+    }
+}
+
+/// Where checkpoints are saved to, a local file or Redis.
+pub enum CheckpointStore {
+    File(PathBuf),
+    // A connection is opened for every save and load, so that a Redis outage only skips checkpoints
+    Redis(redis::Client),
+}
+
+impl CheckpointStore {
+    /// Save checkpoints to the file in the CHECKPOINT_FILE environment variable, or to Redis if it is empty.
+    pub fn from_env(redis_url: &str) -> Self {
+        if let Ok(path) = std::env::var("CHECKPOINT_FILE") {
+            CheckpointStore::File(PathBuf::from(path))
+        } else {
+            info!(
+                "The CHECKPOINT_FILE environment variable is empty, saving checkpoints to {}",
+                REDIS_KEY_CHECKPOINT
+            );
+            let client = redis::Client::open(redis_url)
+                .unwrap_or_else(|err| panic!("Invalid Redis URL {}, {}", redis_url, err));
+            CheckpointStore::Redis(client)
+        }
+    }
+
+    pub fn save(&mut self, checkpoint: &Checkpoint) {
+        let json = serde_json::to_string(checkpoint).unwrap();
+        match self {
+            CheckpointStore::File(path) => {
+                // Write to a temporary file first so that a crash never leaves a partial checkpoint
+                let tmp_path = path.with_extension("tmp");
+                if let Err(err) =
+                    std::fs::write(&tmp_path, json).and_then(|_| std::fs::rename(&tmp_path, &*path))
+                {
+                    error!("Failed to save checkpoint to {}: {}", path.display(), err);
+                }
+            }
+            CheckpointStore::Redis(client) => {
+                if let Err(err) = client
+                    .get_connection()
+                    .and_then(|mut conn| conn.set::<&str, String, ()>(REDIS_KEY_CHECKPOINT, json))
+                {
+                    error!("Failed to save checkpoint to Redis: {}", err);
+                }
+            }
+        }
+    }
+
+    pub fn load(&mut self) -> Option<Checkpoint> {
+        let json = match self {
+            CheckpointStore::File(path) => std::fs::read_to_string(path).ok(),
+            CheckpointStore::Redis(client) => client
+                .get_connection()
+                .and_then(|mut conn| conn.get::<&str, Option<String>>(REDIS_KEY_CHECKPOINT))
+                .unwrap_or_else(|err| {
+                    error!("Failed to load checkpoint from Redis: {}", err);
+                    None
+                }),
+        }?;
+        match serde_json::from_str::<Checkpoint>(&json) {
+            Ok(checkpoint) => Some(checkpoint),
+            Err(err) => {
+                error!("Discarded corrupted checkpoint: {}", err);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto_market_type::MarketType;
+    use crypto_message::{TradeMsg, TradeSide};
+    use crypto_msg_type::MessageType;
+    use transform::{candlestick::BarType, price::StaticPrices};
+
+    const MINUTE: i64 = 60000;
+    const START: i64 = 1599998400000; // 2020-09-13T12:00:00Z
+
+    fn trade(i: i64) -> TradeMsg {
+        TradeMsg {
+            exchange: "binance".to_string(),
+            market_type: MarketType::Spot,
+            msg_type: MessageType::Trade,
+            pair: "BTC/USDT".to_string(),
+            symbol: "BTCUSDT".to_string(),
+            timestamp: START + i * 1000,
+            side: if i % 2 == 0 {
+                TradeSide::Buy
+            } else {
+                TradeSide::Sell
+            },
+            price: 10000.0 + i as f64,
+            quantity_base: 0.5,
+            quantity_quote: (10000.0 + i as f64) * 0.5,
+            quantity_contract: None,
+            trade_id: i.to_string(),
+            json: String::new(),
+        }
+    }
+
+    fn prices() -> StaticPrices {
+        let mut prices = StaticPrices::default();
+        prices.set_price("BTC", 10000.0);
+        prices
+    }
+
+    // A bar ending at bar_time, trades are indexed by seconds since START
+    fn time_bar(bar_time: i64, trades: std::ops::Range<i64>) -> Candlestick {
+        let prices = prices();
+        let mut candlestick = Candlestick::new(
+            "binance".to_string(),
+            MarketType::Spot,
+            "BTCUSDT".to_string(),
+            "BTC/USDT".to_string(),
+            MINUTE,
+            bar_time,
+        );
+        for i in trades {
+            assert!(candlestick.append(&trade(i), &prices));
+        }
+        candlestick
+    }
+
+    fn assert_same(restored: &Candlestick, saved: &Candlestick) {
+        assert_eq!(restored.key(), saved.key());
+        assert_eq!(restored.bar_type, saved.bar_type);
+        assert_eq!(restored.threshold, saved.threshold);
+        assert_eq!(
+            (restored.timestamp_start, restored.timestamp_end),
+            (saved.timestamp_start, saved.timestamp_end)
+        );
+        assert_eq!(
+            (restored.open, restored.high, restored.low, restored.close),
+            (saved.open, saved.high, saved.low, saved.close)
+        );
+        assert_eq!(
+            (restored.volume, restored.volume_buy, restored.volume_sell),
+            (saved.volume, saved.volume_buy, saved.volume_sell)
+        );
+        assert_eq!(restored.volume_usd, saved.volume_usd);
+        assert_eq!(restored.count, saved.count);
+        assert_eq!(restored.dedup, saved.dedup);
+        for (restored, saved) in [
+            (&restored.price_sketch, &saved.price_sketch),
+            (&restored.size_sketch, &saved.size_sketch),
+        ] {
+            assert_eq!(restored.count(), saved.count());
+            assert_eq!(restored.sum(), saved.sum());
+            assert_eq!((restored.min(), restored.max()), (saved.min(), saved.max()));
+            for q in [0.1, 0.5, 0.9, 0.99] {
+                assert_eq!(restored.quantile(q), saved.quantile(q));
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let open = time_bar(START + MINUTE, 0..10);
+        let mut published = time_bar(START, -60..-40);
+        published.finalize();
+        let mut info_bar = Candlestick::new_info_bar(
+            "binance".to_string(),
+            MarketType::Spot,
+            "BTCUSDT".to_string(),
+            "BTC/USDT".to_string(),
+            BarType::Tick,
+            100.0,
+        );
+        for i in 0..5 {
+            assert!(info_bar.append(&trade(i), &prices()));
+        }
+
+        let checkpoint = Checkpoint::new(
+            START,
+            std::iter::once(&open),
+            std::iter::once(&published),
+            std::iter::once(&open),
+            std::iter::once(&info_bar),
+            Vec::new(),
+        );
+        let json = serde_json::to_string(&checkpoint).unwrap();
+        let checkpoint = serde_json::from_str::<Checkpoint>(&json).unwrap();
+        assert_eq!(checkpoint.timestamp, START);
+        let restored = checkpoint.restore();
+
+        assert_eq!(restored.candlesticks.len(), 1);
+        assert_same(&restored.candlesticks[0], &open);
+        assert_same(&restored.published[0], &published);
+        assert_eq!(restored.published[0].median, published.median);
+        assert_same(&restored.rollup[0], &open);
+        assert_same(&restored.info_bars[0], &info_bar);
+        assert!(restored.composites.is_empty());
+
+        // Restored bars still drop duplicated trades and keep accumulating
+        let mut candlestick = restored.candlesticks.into_iter().next().unwrap();
+        assert!(!candlestick.append(&trade(3), &prices()));
+        assert!(candlestick.append(&trade(10), &prices()));
+        candlestick.finalize();
+        let mut expected = time_bar(START + MINUTE, 0..11);
+        expected.finalize();
+        assert_same(&candlestick, &expected);
+        assert_eq!(candlestick.median, expected.median);
+    }
+
+    #[test]
+    fn file_store() {
+        let path = std::env::temp_dir().join(format!(
+            "candlestick_builder_checkpoint_{}.json",
+            std::process::id()
+        ));
+        let mut store = CheckpointStore::File(path.clone());
+        assert!(store.load().is_none());
+
+        let open = time_bar(START + MINUTE, 0..10);
+        store.save(&Checkpoint::new(
+            START,
+            std::iter::once(&open),
+            std::iter::empty(),
+            std::iter::empty(),
+            std::iter::empty(),
+            Vec::new(),
+        ));
+        let restored = store.load().unwrap().restore();
+        assert_same(&restored.candlesticks[0], &open);
+
+        // A corrupted checkpoint is discarded
+        std::fs::write(&path, "{").unwrap();
+        assert!(store.load().is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
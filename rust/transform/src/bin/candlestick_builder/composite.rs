@@ -0,0 +1,384 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use transform::candlestick::Candlestick;
+
+/// A candlestick of one base asset merged across exchanges and market types, prices are in USD.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CompositeCandlestick {
+    base: String,
+    bar_size: i64,        // in millisecond
+    timestamp: i64,       // bar end time, in millisecond
+    timestamp_start: i64, // timestamp of the fist trade
+    timestamp_end: i64,   // timestamp of the last trade
+
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+
+    volume: f64,      // base volume
+    volume_sell: f64, // base volume at sell side
+    volume_buy: f64,  // base volume at buy side
+
+    volume_usd: f64,      // volume converted to USD
+    volume_usd_sell: f64, // volume_usd at sell side
+    volume_usd_buy: f64,  // volume_usd at buy side
+
+    vwap_usd: f64, // volume weighted average price in USD
+
+    count: i64,      // number of trades
+    count_sell: i64, // number of sell trades
+    count_buy: i64,  // number of buy trades
+
+    volume_share: BTreeMap<String, f64>, // exchange -> share of volume_usd
+
+    #[serde(skip_serializing, default)]
+    volume_usd_by_exchange: BTreeMap<String, f64>,
+}
+
+// CompositeCandlestick doesn't serialize its volume per exchange, so it is saved separately
+#[derive(Serialize, Deserialize)]
+pub struct SavedComposite {
+    candlestick: CompositeCandlestick,
+    volume_usd_by_exchange: BTreeMap<String, f64>,
+}
+
+impl CompositeCandlestick {
+    fn new(base: String, bar_size: i64, timestamp: i64) -> Self {
+        CompositeCandlestick {
+            base,
+            bar_size,
+            timestamp,
+            timestamp_start: 0,
+            timestamp_end: 0,
+
+            open: 0.0,
+            high: 0.0,
+            low: 0.0,
+            close: 0.0,
+
+            volume: 0.0,
+            volume_sell: 0.0,
+            volume_buy: 0.0,
+            volume_usd: 0.0,
+            volume_usd_sell: 0.0,
+            volume_usd_buy: 0.0,
+
+            vwap_usd: 0.0,
+
+            count: 0,
+            count_sell: 0,
+            count_buy: 0,
+
+            volume_share: BTreeMap::new(),
+            volume_usd_by_exchange: BTreeMap::new(),
+        }
+    }
+
+    fn merge(&mut self, bar: &Candlestick) {
+        // prices are converted to USD by the quote price implied by vwap_usd
+        let quote_price = bar.vwap_usd / bar.vwap;
+        let (open, high, low, close) = (
+            bar.open * quote_price,
+            bar.high * quote_price,
+            bar.low * quote_price,
+            bar.close * quote_price,
+        );
+
+        if self.count == 0 {
+            self.timestamp_start = bar.timestamp_start;
+            self.timestamp_end = bar.timestamp_end;
+
+            self.open = open;
+            self.high = high;
+            self.low = low;
+            self.close = close;
+        } else {
+            if self.timestamp_start > bar.timestamp_start {
+                self.timestamp_start = bar.timestamp_start;
+                self.open = open;
+            }
+
+            if self.timestamp_end < bar.timestamp_end {
+                self.timestamp_end = bar.timestamp_end;
+                self.close = close;
+            }
+
+            if self.high < high {
+                self.high = high;
+            }
+            if self.low > low {
+                self.low = low;
+            }
+        }
+
+        self.volume += bar.volume;
+        self.volume_sell += bar.volume_sell;
+        self.volume_buy += bar.volume_buy;
+        self.volume_usd += bar.volume_usd;
+        self.volume_usd_sell += bar.volume_usd_sell;
+        self.volume_usd_buy += bar.volume_usd_buy;
+
+        self.count += bar.count;
+        self.count_sell += bar.count_sell;
+        self.count_buy += bar.count_buy;
+
+        *self
+            .volume_usd_by_exchange
+            .entry(bar.exchange.clone())
+            .or_insert(0.0) += bar.volume_usd;
+    }
+
+    fn finalize(&mut self) {
+        self.vwap_usd = self.volume_usd / self.volume;
+        self.volume_share = self
+            .volume_usd_by_exchange
+            .iter()
+            .map(|(exchange, volume_usd)| (exchange.clone(), volume_usd / self.volume_usd))
+            .collect();
+    }
+}
+
+/// Merges finalized time bars of the same base asset across exchanges and market types.
+pub struct Composites {
+    // open composite bars
+    candlesticks: HashMap<String, CompositeCandlestick>,
+}
+
+impl Composites {
+    pub fn new() -> Self {
+        Composites {
+            candlesticks: HashMap::new(),
+        }
+    }
+
+    /// Merge a finalized time bar into the composite bar of its base asset.
+    pub fn push(&mut self, bar: &Candlestick) {
+        if bar.count == 0 || bar.volume <= 0.0 {
+            return;
+        }
+        let base = if let Some((base, _)) = bar.pair.split_once('/') {
+            base
+        } else {
+            return;
+        };
+        let key = format!("{}-{}-{}", base, bar.bar_size, bar.timestamp);
+        self.candlesticks
+            .entry(key)
+            .or_insert_with(|| {
+                CompositeCandlestick::new(base.to_string(), bar.bar_size, bar.timestamp)
+            })
+            .merge(bar);
+    }
+
+    /// Open composite bars, saved for checkpoints.
+    pub fn save(&self) -> Vec<SavedComposite> {
+        self.candlesticks
+            .values()
+            .map(|candlestick| SavedComposite {
+                candlestick: candlestick.clone(),
+                volume_usd_by_exchange: candlestick.volume_usd_by_exchange.clone(),
+            })
+            .collect()
+    }
+
+    /// Put back an open composite bar restored from a checkpoint.
+    pub fn restore(&mut self, saved: SavedComposite) {
+        let mut candlestick = saved.candlestick;
+        candlestick.volume_usd_by_exchange = saved.volume_usd_by_exchange;
+        let key = format!(
+            "{}-{}-{}",
+            candlestick.base, candlestick.bar_size, candlestick.timestamp
+        );
+        self.candlesticks.insert(key, candlestick);
+    }
+
+    /// Finalize composite bars which end at or before the watermark of every exchange.
+    pub fn flush(&mut self, watermark: i64) -> Vec<CompositeCandlestick> {
+        let keys: Vec<String> = self
+            .candlesticks
+            .iter()
+            .filter(|(_, candlestick)| candlestick.timestamp <= watermark)
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.iter()
+            .map(|key| {
+                let mut candlestick = self.candlesticks.remove(key).unwrap();
+                candlestick.finalize();
+                candlestick
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto_market_type::MarketType;
+    use crypto_message::{TradeMsg, TradeSide};
+    use crypto_msg_type::MessageType;
+    use transform::price::StaticPrices;
+
+    const MINUTE: i64 = 60000;
+    const START: i64 = 1599998400000; // 2020-09-13T12:00:00Z
+
+    // A finalized one-minute bar ending at START + MINUTE, trades are (offset, price, quantity, side)
+    fn bar(exchange: &str, pair: &str, trades: &[(i64, f64, f64, TradeSide)]) -> Candlestick {
+        let mut prices = StaticPrices::default();
+        prices.set_price("BTC", 20000.0);
+        prices.set_price("EUR", 1.25);
+
+        let symbol = pair.replace('/', "");
+        let mut candlestick = Candlestick::new(
+            exchange.to_string(),
+            MarketType::Spot,
+            symbol.clone(),
+            pair.to_string(),
+            MINUTE,
+            START + MINUTE,
+        );
+        for (i, (offset, price, quantity, side)) in trades.iter().enumerate() {
+            let trade = TradeMsg {
+                exchange: exchange.to_string(),
+                market_type: MarketType::Spot,
+                msg_type: MessageType::Trade,
+                pair: pair.to_string(),
+                symbol: symbol.clone(),
+                timestamp: START + offset,
+                side: *side,
+                price: *price,
+                quantity_base: *quantity,
+                quantity_quote: price * quantity,
+                quantity_contract: None,
+                trade_id: i.to_string(),
+                json: String::new(),
+            };
+            assert!(candlestick.append(&trade, &prices));
+        }
+        candlestick.finalize();
+        candlestick
+    }
+
+    fn binance() -> Candlestick {
+        bar(
+            "binance",
+            "BTC/USDT",
+            &[
+                (0, 20000.0, 1.0, TradeSide::Buy),
+                (10000, 20200.0, 1.0, TradeSide::Sell),
+            ],
+        )
+    }
+
+    // Prices in EUR, which is 1.25 USD
+    fn kraken() -> Candlestick {
+        bar(
+            "kraken",
+            "BTC/EUR",
+            &[
+                (5000, 16000.0, 0.5, TradeSide::Buy),
+                (20000, 16400.0, 0.5, TradeSide::Buy),
+            ],
+        )
+    }
+
+    fn assert_approx(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    fn assert_merged(candlestick: &CompositeCandlestick) {
+        assert_eq!(candlestick.base, "BTC");
+        assert_eq!(candlestick.bar_size, MINUTE);
+        assert_eq!(candlestick.timestamp, START + MINUTE);
+        assert_eq!(
+            (candlestick.timestamp_start, candlestick.timestamp_end),
+            (START, START + 20000)
+        );
+        // open from binance, close from kraken, converted to USD by vwap_usd / vwap
+        assert_approx(candlestick.open, 20000.0);
+        assert_approx(candlestick.high, 20500.0);
+        assert_approx(candlestick.low, 20000.0);
+        assert_approx(candlestick.close, 20500.0);
+
+        assert_approx(candlestick.volume, 3.0);
+        assert_approx(candlestick.volume_buy, 2.0);
+        assert_approx(candlestick.volume_sell, 1.0);
+        assert_approx(candlestick.volume_usd, 40200.0 + 20250.0);
+        assert_approx(candlestick.vwap_usd, 60450.0 / 3.0);
+        assert_eq!(
+            (
+                candlestick.count,
+                candlestick.count_buy,
+                candlestick.count_sell
+            ),
+            (4, 3, 1)
+        );
+
+        let share = &candlestick.volume_share;
+        assert_eq!(share.len(), 2);
+        assert_approx(share["binance"], 40200.0 / 60450.0);
+        assert_approx(share["kraken"], 20250.0 / 60450.0);
+        assert_approx(share.values().sum(), 1.0);
+    }
+
+    #[test]
+    fn merge_exchanges_in_usd() {
+        let mut composites = Composites::new();
+        composites.push(&binance());
+        composites.push(&kraken());
+        composites.push(&bar(
+            "binance",
+            "ETH/USDT",
+            &[(0, 1500.0, 2.0, TradeSide::Buy)],
+        ));
+        // Empty bars and invalid pairs are skipped
+        composites.push(&bar("binance", "SOL/USDT", &[]));
+        let mut invalid = binance();
+        invalid.pair = "BTCUSDT".to_string();
+        composites.push(&invalid);
+
+        let mut finished = composites.flush(START + MINUTE);
+        finished.sort_by(|a, b| a.base.cmp(&b.base));
+        assert_eq!(finished.len(), 2);
+        assert_merged(&finished[0]);
+        assert_eq!(finished[1].base, "ETH");
+        assert_eq!(finished[1].volume_share["binance"], 1.0);
+    }
+
+    #[test]
+    fn flush_after_watermark() {
+        let mut composites = Composites::new();
+        composites.push(&binance());
+        assert!(composites.flush(START).is_empty());
+        assert!(composites.flush(START + MINUTE - 1).is_empty());
+
+        // Bars arriving before the watermark passes are still merged
+        composites.push(&kraken());
+        let finished = composites.flush(START + MINUTE);
+        assert_eq!(finished.len(), 1);
+        assert_merged(&finished[0]);
+        assert!(composites.flush(START + 2 * MINUTE).is_empty());
+    }
+
+    #[test]
+    fn save_and_restore() {
+        let mut composites = Composites::new();
+        composites.push(&binance());
+        let json = serde_json::to_string(&composites.save()).unwrap();
+
+        let mut restored = Composites::new();
+        for saved in serde_json::from_str::<Vec<SavedComposite>>(&json).unwrap() {
+            restored.restore(saved);
+        }
+        restored.push(&kraken());
+        let finished = restored.flush(START + MINUTE);
+        assert_eq!(finished.len(), 1);
+        assert_merged(&finished[0]);
+    }
+}
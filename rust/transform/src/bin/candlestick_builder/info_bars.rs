@@ -0,0 +1,270 @@
+use crypto_message::TradeMsg;
+use log::*;
+use std::collections::HashMap;
+
+use transform::candlestick::{BarType, Candlestick};
+use transform::price::PriceSource;
+
+/// A tick, volume or dollar bar specification, e.g., dollar:1000000
+#[derive(Clone, Copy)]
+struct InfoBarSpec {
+    bar_type: BarType,
+    threshold: f64,
+}
+
+impl InfoBarSpec {
+    fn parse(text: &str) -> Option<Self> {
+        let (bar_type, threshold) = text.trim().split_once(':')?;
+        let bar_type = match bar_type {
+            "tick" => BarType::Tick,
+            "volume" => BarType::Volume,
+            "dollar" => BarType::Dollar,
+            _ => return None,
+        };
+        let threshold = threshold.parse::<f64>().ok().filter(|x| *x > 0.0)?;
+        Some(InfoBarSpec {
+            bar_type,
+            threshold,
+        })
+    }
+}
+
+/// Builds tick, volume and dollar bars, which are sampled by trading activity instead of time.
+pub struct InfoBars {
+    specs: Vec<InfoBarSpec>,
+    // open bars
+    candlesticks: HashMap<String, Candlestick>,
+}
+
+impl InfoBars {
+    /// Read bar specifications from the INFO_BARS environment variable,
+    /// e.g., INFO_BARS=tick:1000,volume:100,dollar:1000000
+    pub fn from_env() -> Self {
+        let specs = if let Ok(text) = std::env::var("INFO_BARS") {
+            text.split(',')
+                .filter(|x| !x.trim().is_empty())
+                .map(|x| InfoBarSpec::parse(x).unwrap_or_else(|| panic!("Invalid info bar {}", x)))
+                .collect()
+        } else {
+            info!("The INFO_BARS environment variable is empty, tick, volume and dollar bars are disabled");
+            Vec::new()
+        };
+        InfoBars {
+            specs,
+            candlesticks: HashMap::new(),
+        }
+    }
+
+    /// Append a trade to all open bars of its symbol, returns bars which reached their threshold.
+    pub fn append(&mut self, trade: &TradeMsg, prices: &dyn PriceSource) -> Vec<Candlestick> {
+        let mut finished = Vec::new();
+        for spec in self.specs.iter() {
+            let key = format!(
+                "{}-{}-{}-{}-{}-{}",
+                trade.exchange,
+                trade.market_type,
+                trade.pair,
+                trade.symbol,
+                spec.bar_type,
+                spec.threshold
+            );
+            let candlestick = self.candlesticks.entry(key.clone()).or_insert_with(|| {
+                Candlestick::new_info_bar(
+                    trade.exchange.clone(),
+                    trade.market_type,
+                    trade.symbol.clone(),
+                    trade.pair.clone(),
+                    spec.bar_type,
+                    spec.threshold,
+                )
+            });
+            if !candlestick.append(trade, prices) {
+                continue;
+            }
+
+            let progress = match spec.bar_type {
+                BarType::Tick => candlestick.count as f64,
+                BarType::Volume => candlestick.volume,
+                BarType::Dollar => candlestick.volume_usd,
+                BarType::Time => unreachable!(),
+            };
+            if progress >= spec.threshold {
+                let mut candlestick = self.candlesticks.remove(&key).unwrap();
+                // An info bar ends at its last trade
+                candlestick.timestamp = candlestick.timestamp_end;
+                candlestick.finalize();
+                finished.push(candlestick);
+            }
+        }
+        finished
+    }
+
+    /// Open bars of all specifications.
+    pub fn candlesticks(&self) -> impl Iterator<Item = &Candlestick> {
+        self.candlesticks.values()
+    }
+
+    /// Put back an open bar restored from a checkpoint.
+    pub fn restore(&mut self, candlestick: Candlestick) {
+        let threshold = candlestick.threshold.unwrap_or_default();
+        if self
+            .specs
+            .iter()
+            .any(|spec| spec.bar_type == candlestick.bar_type && spec.threshold == threshold)
+        {
+            let key = format!(
+                "{}-{}-{}-{}-{}-{}",
+                candlestick.exchange,
+                candlestick.market_type,
+                candlestick.pair,
+                candlestick.symbol,
+                candlestick.bar_type,
+                threshold
+            );
+            self.candlesticks.insert(key, candlestick);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto_market_type::MarketType;
+    use crypto_message::TradeSide;
+    use crypto_msg_type::MessageType;
+    use transform::price::StaticPrices;
+
+    const START: i64 = 1599998400000; // 2020-09-13T12:00:00Z
+
+    // The i-th trade of 0.5 BTC
+    fn trade(i: i64, price: f64) -> TradeMsg {
+        TradeMsg {
+            exchange: "binance".to_string(),
+            market_type: MarketType::Spot,
+            msg_type: MessageType::Trade,
+            pair: "BTC/USDT".to_string(),
+            symbol: "BTCUSDT".to_string(),
+            timestamp: START + i * 1000,
+            side: TradeSide::Buy,
+            price,
+            quantity_base: 0.5,
+            quantity_quote: price * 0.5,
+            quantity_contract: None,
+            trade_id: i.to_string(),
+            json: String::new(),
+        }
+    }
+
+    fn info_bars(specs: &[&str]) -> InfoBars {
+        InfoBars {
+            specs: specs
+                .iter()
+                .map(|x| InfoBarSpec::parse(x).unwrap())
+                .collect(),
+            candlesticks: HashMap::new(),
+        }
+    }
+
+    fn prices() -> StaticPrices {
+        let mut prices = StaticPrices::default();
+        prices.set_price("BTC", 10000.0);
+        prices
+    }
+
+    #[test]
+    fn parse_spec() {
+        let spec = InfoBarSpec::parse("tick:1000").unwrap();
+        assert_eq!((spec.bar_type, spec.threshold), (BarType::Tick, 1000.0));
+        let spec = InfoBarSpec::parse(" volume:0.5 ").unwrap();
+        assert_eq!((spec.bar_type, spec.threshold), (BarType::Volume, 0.5));
+        let spec = InfoBarSpec::parse("dollar:1e6").unwrap();
+        assert_eq!(
+            (spec.bar_type, spec.threshold),
+            (BarType::Dollar, 1000000.0)
+        );
+
+        for text in [
+            "",
+            "tick",
+            "tick:",
+            "tick:abc",
+            "tick:0",
+            "tick:-1",
+            "tick:NaN",
+            "time:60",
+            "Tick:1000",
+        ] {
+            assert!(InfoBarSpec::parse(text).is_none(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn close_at_threshold() {
+        let prices = prices();
+        let mut info_bars = info_bars(&["tick:3", "volume:1", "dollar:15000"]);
+
+        // 0.5 BTC and 5000 USD per trade
+        assert!(info_bars.append(&trade(0, 10000.0), &prices).is_empty());
+        let finished = info_bars.append(&trade(1, 10000.0), &prices);
+        assert_eq!(finished.len(), 1);
+        let volume_bar = &finished[0];
+        assert_eq!(volume_bar.bar_type, BarType::Volume);
+        assert_eq!(volume_bar.threshold, Some(1.0));
+        assert_eq!((volume_bar.count, volume_bar.volume), (2, 1.0));
+        assert_eq!(volume_bar.timestamp, START + 1000);
+
+        let finished = info_bars.append(&trade(2, 10000.0), &prices);
+        let bar_types: Vec<BarType> = finished.iter().map(|x| x.bar_type).collect();
+        assert_eq!(bar_types.len(), 2);
+        assert!(bar_types.contains(&BarType::Tick) && bar_types.contains(&BarType::Dollar));
+        for candlestick in finished.iter() {
+            assert_eq!(candlestick.count, 3);
+            assert_eq!(candlestick.volume_usd, 15000.0);
+            assert_eq!(candlestick.timestamp_start, START);
+            assert_eq!(candlestick.timestamp, START + 2000);
+        }
+
+        // The next trade opens new tick and dollar bars, and closes the second volume bar
+        let finished = info_bars.append(&trade(3, 10000.0), &prices);
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].bar_type, BarType::Volume);
+        assert_eq!(finished[0].timestamp_start, START + 2000);
+        let counts: Vec<i64> = info_bars.candlesticks().map(|x| x.count).collect();
+        assert_eq!(counts, [1, 1]);
+    }
+
+    #[test]
+    fn restore_open_bar() {
+        let prices = prices();
+        let mut info_bars = info_bars(&["tick:3"]);
+        info_bars.append(&trade(0, 10000.0), &prices);
+        let open: Vec<Candlestick> = info_bars.candlesticks().cloned().collect();
+
+        let mut restored = self::info_bars(&["tick:3"]);
+        for candlestick in open.iter().cloned() {
+            restored.restore(candlestick);
+        }
+        assert!(restored.append(&trade(1, 10100.0), &prices).is_empty());
+        let finished = restored.append(&trade(2, 9900.0), &prices);
+        assert_eq!(finished.len(), 1);
+        let candlestick = &finished[0];
+        assert_eq!(candlestick.count, 3);
+        assert_eq!(
+            (
+                candlestick.open,
+                candlestick.high,
+                candlestick.low,
+                candlestick.close
+            ),
+            (10000.0, 10100.0, 9900.0, 9900.0)
+        );
+        assert_eq!(candlestick.timestamp_start, START);
+
+        // Bars of a specification which is no longer configured are dropped
+        let mut restored = self::info_bars(&["tick:5"]);
+        for candlestick in open {
+            restored.restore(candlestick);
+        }
+        assert_eq!(restored.candlesticks().count(), 0);
+    }
+}
@@ -0,0 +1,261 @@
+use crypto_message::TradeMsg;
+use log::*;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use transform::candlestick::{candlestick_key, extract_quote, is_good, Candlestick};
+use transform::{constants::*, duration::parse_bar_sizes, rollup::Rollup};
+use utils::{
+    pubsub::{create_bus, PublishError, Publisher},
+    wait_redis, PriceCache,
+};
+
+mod checkpoint;
+mod composite;
+mod info_bars;
+mod watermark;
+
+use checkpoint::{Checkpoint, CheckpointStore};
+use composite::{CompositeCandlestick, Composites};
+use info_bars::InfoBars;
+use watermark::Watermarks;
+
+const DEFAULT_BAR_SIZES: &str = "5m";
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+// Bar sizes are read from the BAR_SIZES environment variable, e.g., BAR_SIZES=1m,5m,15m,1h,4h,1d
+fn get_bar_sizes() -> Vec<i64> {
+    let text = if let Ok(text) = std::env::var("BAR_SIZES") {
+        text
+    } else {
+        info!(
+            "The BAR_SIZES environment variable is empty, using {} by default",
+            DEFAULT_BAR_SIZES
+        );
+        DEFAULT_BAR_SIZES.to_string()
+    };
+    parse_bar_sizes(&text).unwrap_or_else(|err| panic!("{}", err))
+}
+
+// Check whether a boolean environment variable is true
+fn env_enabled(name: &str) -> bool {
+    std::env::var(name)
+        .map(|x| x == "true" || x == "1")
+        .unwrap_or(false)
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+// Merge trades into klines of multiple bar sizes
+fn main() {
+    env_logger::init();
+    let redis_url = if std::env::var("REDIS_URL").is_err() {
+        info!(
+            "The REDIS_URL environment variable is empty, using redis://localhost:6379 by default"
+        );
+        "redis://localhost:6379"
+    } else {
+        let url = std::env::var("REDIS_URL").unwrap();
+        Box::leak(url.into_boxed_str())
+    };
+    wait_redis(redis_url);
+
+    let price_cache = PriceCache::new(redis_url);
+    price_cache.wait_until_ready();
+
+    let bar_sizes = get_bar_sizes();
+    let mut watermarks = Watermarks::from_env();
+    // Publish amended bars if the AMENDED_BARS environment variable is true
+    let amended_bars = env_enabled("AMENDED_BARS");
+    // Publish cross-exchange bars if the COMPOSITE_BARS environment variable is true
+    let composite_bars = env_enabled("COMPOSITE_BARS");
+
+    let bus = create_bus(redis_url);
+    let mut publisher = Publisher::new(bus.as_ref());
+    let mut subscriber = bus.subscribe(&[REDIS_TOPIC_TRADE_PARSED]);
+
+    // Only the smallest bar size is built from raw trades, the others are rolled up from it
+    let mut rollup = Rollup::new(&bar_sizes);
+    let bar_size = rollup.base_bar_size();
+
+    // tick, volume and dollar bars
+    let mut info_bars = InfoBars::from_env();
+    // cross-exchange bars
+    let mut composites = Composites::new();
+
+    // open bars
+    let mut candlesticks: HashMap<String, Candlestick> = HashMap::new();
+    // published bars kept for one more bar so that late trades can amend them
+    let mut published: HashMap<String, Candlestick> = HashMap::new();
+
+    // Restore in-flight bars saved before the last restart
+    let mut checkpoint_store = CheckpointStore::from_env(redis_url);
+    if let Some(checkpoint) = checkpoint_store.load() {
+        info!("Restoring the checkpoint saved at {}", checkpoint.timestamp);
+        let restored = checkpoint.restore();
+        for candlestick in restored.candlesticks {
+            if candlestick.bar_size == bar_size {
+                candlesticks.insert(candlestick.key(), candlestick);
+            }
+        }
+        for candlestick in restored.published {
+            if candlestick.bar_size == bar_size {
+                published.insert(candlestick.key(), candlestick);
+            } else {
+                rollup.restore_published(candlestick);
+            }
+        }
+        for candlestick in restored.rollup {
+            rollup.restore(candlestick);
+        }
+        for candlestick in restored.info_bars {
+            info_bars.restore(candlestick);
+        }
+        if composite_bars {
+            for saved in restored.composites {
+                composites.restore(saved);
+            }
+        }
+    }
+
+    let mut last_flush_time = now_ms();
+    let mut last_checkpoint_time = last_flush_time;
+
+    loop {
+        // next_message() times out periodically so that bars are flushed even if there is no trade
+        let trade_msg = subscriber
+            .next_message(Some(FLUSH_INTERVAL))
+            .and_then(|msg| match serde_json::from_str::<TradeMsg>(&msg.payload) {
+                Ok(trade_msg) => Some(trade_msg),
+                Err(err) => {
+                    warn!("{}, {}", err, msg.payload);
+                    None
+                }
+            });
+
+        if let Some(trade_msg) = trade_msg {
+            if is_good(extract_quote(&trade_msg.pair), &price_cache) {
+                watermarks.observe(&trade_msg.exchange, trade_msg.timestamp, now_ms());
+
+                for candlestick in info_bars.append(&trade_msg, &price_cache) {
+                    if let Err(PublishError::Redis(err)) =
+                        publisher.publish::<Candlestick>(REDIS_TOPIC_CANDLESTICK_INFO, &candlestick)
+                    {
+                        error!("{}", err);
+                    }
+                }
+
+                let msg_bar_time = (trade_msg.timestamp / bar_size) * bar_size + bar_size;
+                let key = candlestick_key(&trade_msg, msg_bar_time);
+                if let Some(candlestick) = published.get_mut(&key) {
+                    // A late trade which belongs to a published bar
+                    if candlestick.append(&trade_msg, &price_cache) {
+                        // Rolled-up bars covering the trade are amended too
+                        let amended = rollup.amend(&trade_msg, &price_cache);
+                        if amended_bars {
+                            candlestick.finalize();
+                            for candlestick in std::iter::once(&*candlestick).chain(amended.iter())
+                            {
+                                if let Err(PublishError::Redis(err)) = publisher
+                                    .publish::<Candlestick>(
+                                        REDIS_TOPIC_CANDLESTICK_EXT_AMENDED,
+                                        candlestick,
+                                    )
+                                {
+                                    error!("{}", err);
+                                }
+                            }
+                        }
+                    }
+                } else if msg_bar_time <= watermarks.watermark(&trade_msg.exchange, now_ms()) {
+                    warn!(
+                        "Dropped late trade {}",
+                        serde_json::to_string(&trade_msg).unwrap()
+                    );
+                } else {
+                    let candlestick = candlesticks.entry(key).or_insert_with(|| {
+                        Candlestick::new(
+                            trade_msg.exchange.clone(),
+                            trade_msg.market_type,
+                            trade_msg.symbol.clone(),
+                            trade_msg.pair.clone(),
+                            bar_size,
+                            msg_bar_time,
+                        )
+                    });
+                    candlestick.append(&trade_msg, &price_cache);
+                }
+            }
+        }
+
+        let now = now_ms();
+        if now - last_flush_time >= FLUSH_INTERVAL.as_millis() as i64 {
+            // Discard published bars which can not be amended any more
+            published.retain(|_, candlestick| {
+                candlestick.timestamp + bar_size > watermarks.watermark(&candlestick.exchange, now)
+            });
+
+            // output bars which have ended
+            let keys: Vec<String> = candlesticks
+                .iter()
+                .filter(|(_, candlestick)| {
+                    candlestick.timestamp <= watermarks.watermark(&candlestick.exchange, now)
+                })
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in keys {
+                let mut candlestick = candlesticks.remove(&key).unwrap();
+                candlestick.finalize();
+                if let Err(PublishError::Redis(err)) =
+                    publisher.publish::<Candlestick>(REDIS_TOPIC_CANDLESTICK_EXT, &candlestick)
+                {
+                    error!("{}", err);
+                }
+                rollup.push(&candlestick);
+                if composite_bars {
+                    composites.push(&candlestick);
+                }
+                published.insert(key, candlestick);
+            }
+            for candlestick in rollup.flush(|exchange| watermarks.watermark(exchange, now)) {
+                if let Err(PublishError::Redis(err)) =
+                    publisher.publish::<Candlestick>(REDIS_TOPIC_CANDLESTICK_EXT, &candlestick)
+                {
+                    error!("{}", err);
+                }
+                if composite_bars {
+                    composites.push(&candlestick);
+                }
+            }
+            for candlestick in composites.flush(watermarks.min_watermark(now)) {
+                if let Err(PublishError::Redis(err)) = publisher.publish::<CompositeCandlestick>(
+                    REDIS_TOPIC_CANDLESTICK_COMPOSITE,
+                    &candlestick,
+                ) {
+                    error!("{}", err);
+                }
+            }
+
+            last_flush_time = now;
+        }
+
+        if now - last_checkpoint_time >= CHECKPOINT_INTERVAL.as_millis() as i64 {
+            let checkpoint = Checkpoint::new(
+                now,
+                candlesticks.values(),
+                published.values().chain(rollup.published()),
+                rollup.candlesticks(),
+                info_bars.candlesticks(),
+                composites.save(),
+            );
+            checkpoint_store.save(&checkpoint);
+            last_checkpoint_time = now;
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use log::*;
+use std::collections::HashMap;
+use transform::duration::parse_duration;
+
+const DEFAULT_ALLOWED_LATENESS: &str = "3s";
+const DEFAULT_IDLE_TIMEOUT: &str = "5s";
+
+/// Event-time watermarks per exchange.
+///
+/// The watermark of an exchange is its event time, i.e., the latest trade timestamp seen from it,
+/// minus its allowed lateness. A bar is closed once the watermark passes its end.
+///
+/// Once an exchange has been idle for longer than the idle timeout, its event time advances with
+/// the wall clock, so that bars of quiet exchanges are still closed. The wall clock is also used
+/// for exchanges which have not been seen yet.
+pub struct Watermarks {
+    default_lateness: i64,
+    lateness: HashMap<String, i64>,
+    idle_timeout: i64,
+    // exchange -> (the latest trade timestamp, the wall clock time when it was observed)
+    event_times: HashMap<String, (i64, i64)>,
+}
+
+impl Watermarks {
+    /// Read allowed lateness from the ALLOWED_LATENESS environment variable,
+    /// e.g., ALLOWED_LATENESS=3s,bitmex=10s,huobi=5s, and the idle timeout from
+    /// the WATERMARK_IDLE_TIMEOUT environment variable.
+    pub fn from_env() -> Self {
+        let text = if let Ok(text) = std::env::var("ALLOWED_LATENESS") {
+            text
+        } else {
+            info!(
+                "The ALLOWED_LATENESS environment variable is empty, using {} by default",
+                DEFAULT_ALLOWED_LATENESS
+            );
+            DEFAULT_ALLOWED_LATENESS.to_string()
+        };
+        let idle_timeout = if let Ok(text) = std::env::var("WATERMARK_IDLE_TIMEOUT") {
+            parse_duration(&text)
+                .unwrap_or_else(|| panic!("Invalid WATERMARK_IDLE_TIMEOUT {}", text))
+        } else {
+            info!(
+                "The WATERMARK_IDLE_TIMEOUT environment variable is empty, using {} by default",
+                DEFAULT_IDLE_TIMEOUT
+            );
+            parse_duration(DEFAULT_IDLE_TIMEOUT).unwrap()
+        };
+        Self::parse(&text, idle_timeout)
+    }
+
+    fn parse(text: &str, idle_timeout: i64) -> Self {
+        let mut default_lateness = parse_duration(DEFAULT_ALLOWED_LATENESS).unwrap();
+        let mut lateness = HashMap::new();
+        for item in text.split(',').filter(|x| !x.trim().is_empty()) {
+            if let Some((exchange, duration)) = item.split_once('=') {
+                let duration = parse_duration(duration)
+                    .unwrap_or_else(|| panic!("Invalid allowed lateness {}", item));
+                lateness.insert(exchange.trim().to_string(), duration);
+            } else {
+                default_lateness = parse_duration(item)
+                    .unwrap_or_else(|| panic!("Invalid allowed lateness {}", item));
+            }
+        }
+        Watermarks {
+            default_lateness,
+            lateness,
+            idle_timeout,
+            event_times: HashMap::new(),
+        }
+    }
+
+    pub fn allowed_lateness(&self, exchange: &str) -> i64 {
+        self.lateness
+            .get(exchange)
+            .copied()
+            .unwrap_or(self.default_lateness)
+    }
+
+    /// Advance the event time of an exchange by a trade observed at the wall clock time now.
+    pub fn observe(&mut self, exchange: &str, timestamp: i64, now: i64) {
+        let event_time = if self.event_times.contains_key(exchange) {
+            // Never move backwards after the event time has advanced with the wall clock
+            self.event_time(exchange, now).max(timestamp)
+        } else {
+            timestamp
+        };
+        self.event_times
+            .insert(exchange.to_string(), (event_time, now));
+    }
+
+    fn event_time(&self, exchange: &str, now: i64) -> i64 {
+        match self.event_times.get(exchange) {
+            Some(&(event_time, observed_at)) => {
+                event_time + (now - observed_at - self.idle_timeout).max(0)
+            }
+            None => now,
+        }
+    }
+
+    pub fn watermark(&self, exchange: &str, now: i64) -> i64 {
+        self.event_time(exchange, now) - self.allowed_lateness(exchange)
+    }
+
+    /// The lowest watermark of all exchanges seen so far.
+    pub fn min_watermark(&self, now: i64) -> i64 {
+        self.event_times
+            .keys()
+            .map(|exchange| self.watermark(exchange, now))
+            .min()
+            .unwrap_or(now - self.default_lateness)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watermark_follows_event_time() {
+        let mut watermarks = Watermarks::parse("3s,bitmex=10s", 5000);
+        assert_eq!(watermarks.allowed_lateness("binance"), 3000);
+        assert_eq!(watermarks.allowed_lateness("bitmex"), 10000);
+        // the wall clock is used before the first trade
+        assert_eq!(watermarks.watermark("binance", 100000), 97000);
+
+        // the exchange clock lags the wall clock by 2 seconds
+        watermarks.observe("binance", 98000, 100000);
+        assert_eq!(watermarks.watermark("binance", 100000), 95000);
+        assert_eq!(watermarks.watermark("binance", 104000), 95000);
+        // older trades don't move the watermark backwards
+        watermarks.observe("binance", 97000, 104000);
+        assert_eq!(watermarks.watermark("binance", 104000), 95000);
+        watermarks.observe("binance", 102000, 104000);
+        assert_eq!(watermarks.watermark("binance", 104000), 99000);
+        assert_eq!(watermarks.min_watermark(104000), 99000);
+    }
+
+    #[test]
+    fn watermark_advances_with_wall_clock_when_idle() {
+        let mut watermarks = Watermarks::parse("3s", 5000);
+        watermarks.observe("binance", 98000, 100000);
+        assert_eq!(watermarks.watermark("binance", 105000), 95000);
+        assert_eq!(watermarks.watermark("binance", 107000), 97000);
+
+        // a trade older than the advanced event time keeps the watermark
+        watermarks.observe("binance", 99000, 107000);
+        assert_eq!(watermarks.watermark("binance", 107000), 97000);
+        assert_eq!(watermarks.watermark("binance", 110000), 97000);
+    }
+}
@@ -0,0 +1,385 @@
+use log::*;
+use redis::Commands;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use transform::{
+    constants::{REDIS_TOPIC_CANDLESTICK_COMPOSITE, REDIS_TOPIC_CORRELATION},
+    duration::parse_duration,
+};
+use utils::{
+    pubsub::{create_bus, PublishError, Publisher, Subscriber},
+    wait_redis,
+};
+
+const DEFAULT_BAR_SIZE: &str = "5m";
+const DEFAULT_LOOKBACK: usize = 288; // one day of 5-minute bars
+const DEFAULT_TOP_N: usize = 20;
+const SNAPSHOT_INTERVAL: i64 = 60000; // publish a snapshot every minute
+const BENCHMARKS: [&str; 2] = ["BTC", "ETH"];
+// The latest snapshot
+const REDIS_KEY_CORRELATION: &str = "coinsignal:correlation_snapshot";
+
+// Fields of CompositeCandlestick used by correlations
+#[derive(Deserialize)]
+struct CompositeBar {
+    base: String,
+    bar_size: i64,
+    timestamp: i64,
+    close: f64,
+    volume_usd: f64,
+    count: i64,
+}
+
+/// Rolling return correlations of the top assets by volume_usd, and their betas to BTC and ETH.
+#[derive(Serialize)]
+struct CorrelationSnapshot {
+    timestamp: i64,
+    bar_size: i64,   // in millisecond
+    lookback: usize, // number of bars
+    assets: Vec<String>,
+    // correlation[i][j] is the correlation between assets[i] and assets[j],
+    // null if they have too few returns in common
+    correlation: Vec<Vec<Option<f64>>>,
+    beta: BTreeMap<String, BTreeMap<String, Option<f64>>>, // benchmark -> asset -> beta
+}
+
+// Latest bars of a base asset
+#[derive(Default)]
+struct History {
+    bars: BTreeMap<i64, (f64, f64)>, // timestamp -> (close, volume_usd)
+}
+
+impl History {
+    fn push(&mut self, timestamp: i64, close: f64, volume_usd: f64, lookback: usize) {
+        self.bars.insert(timestamp, (close, volume_usd));
+        while self.bars.len() > lookback + 1 {
+            self.bars.pop_first();
+        }
+    }
+
+    fn volume_usd(&self) -> f64 {
+        self.bars.values().map(|(_, volume_usd)| volume_usd).sum()
+    }
+
+    // timestamp -> log return since the previous bar, only if the previous bar exists
+    fn returns(&self, bar_size: i64) -> HashMap<i64, f64> {
+        self.bars
+            .iter()
+            .filter_map(|(timestamp, (close, _))| {
+                self.bars
+                    .get(&(timestamp - bar_size))
+                    .map(|(prev_close, _)| (*timestamp, (close / prev_close).ln()))
+            })
+            .collect()
+    }
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+// Returns (correlation, beta of x to y) over timestamps in common
+fn correlation_and_beta(
+    x: &HashMap<i64, f64>,
+    y: &HashMap<i64, f64>,
+    min_count: usize,
+) -> (Option<f64>, Option<f64>) {
+    let pairs: Vec<(f64, f64)> = x
+        .iter()
+        .filter_map(|(timestamp, a)| y.get(timestamp).map(|b| (*a, *b)))
+        .collect();
+    if pairs.len() < min_count.max(2) {
+        return (None, None);
+    }
+    let n = pairs.len() as f64;
+    let mean_x = pairs.iter().map(|(a, _)| a).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|(_, b)| b).sum::<f64>() / n;
+    let mut cov = 0.0;
+    let mut var_x = 0.0;
+    let mut var_y = 0.0;
+    for (a, b) in pairs.iter() {
+        cov += (a - mean_x) * (b - mean_y);
+        var_x += (a - mean_x) * (a - mean_x);
+        var_y += (b - mean_y) * (b - mean_y);
+    }
+    let correlation = if var_x > 0.0 && var_y > 0.0 {
+        Some(cov / (var_x * var_y).sqrt())
+    } else {
+        None
+    };
+    let beta = if var_y > 0.0 { Some(cov / var_y) } else { None };
+    (correlation, beta)
+}
+
+fn build_snapshot(
+    histories: &HashMap<String, History>,
+    bar_size: i64,
+    lookback: usize,
+    top_n: usize,
+    now: i64,
+) -> CorrelationSnapshot {
+    let mut assets: Vec<(&String, f64)> = histories
+        .iter()
+        .map(|(base, history)| (base, history.volume_usd()))
+        .collect();
+    assets.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(b.0)));
+    let mut assets: Vec<String> = assets
+        .into_iter()
+        .take(top_n)
+        .map(|(base, _)| base.clone())
+        .collect();
+    // benchmarks are always included
+    for benchmark in BENCHMARKS.iter() {
+        if histories.contains_key(*benchmark) && !assets.iter().any(|x| x == benchmark) {
+            assets.push(benchmark.to_string());
+        }
+    }
+
+    let returns: Vec<HashMap<i64, f64>> = assets
+        .iter()
+        .map(|base| histories[base].returns(bar_size))
+        .collect();
+    let min_count = lookback / 2;
+
+    let correlation = (0..assets.len())
+        .map(|i| {
+            (0..assets.len())
+                .map(|j| {
+                    if i == j {
+                        Some(1.0)
+                    } else {
+                        correlation_and_beta(&returns[i], &returns[j], min_count).0
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut beta = BTreeMap::new();
+    for benchmark in BENCHMARKS.iter() {
+        if let Some(k) = assets.iter().position(|x| x == benchmark) {
+            let betas: BTreeMap<String, Option<f64>> = assets
+                .iter()
+                .enumerate()
+                .map(|(i, base)| {
+                    (
+                        base.clone(),
+                        correlation_and_beta(&returns[i], &returns[k], min_count).1,
+                    )
+                })
+                .collect();
+            beta.insert(benchmark.to_string(), betas);
+        }
+    }
+
+    CorrelationSnapshot {
+        timestamp: now,
+        bar_size,
+        lookback,
+        assets,
+        correlation,
+        beta,
+    }
+}
+
+// Calculate rolling correlations and betas from composite candlesticks
+fn main() {
+    env_logger::init();
+    let redis_url = if std::env::var("REDIS_URL").is_err() {
+        info!(
+            "The REDIS_URL environment variable is empty, using redis://localhost:6379 by default"
+        );
+        "redis://localhost:6379"
+    } else {
+        let url = std::env::var("REDIS_URL").unwrap();
+        Box::leak(url.into_boxed_str())
+    };
+    wait_redis(redis_url);
+    let bus = create_bus(redis_url);
+
+    let bar_size = parse_duration(
+        &std::env::var("CORRELATION_BAR_SIZE").unwrap_or_else(|_| DEFAULT_BAR_SIZE.to_string()),
+    )
+    .filter(|x| *x > 0)
+    .expect("Invalid CORRELATION_BAR_SIZE");
+    let lookback = std::env::var("CORRELATION_LOOKBACK")
+        .map(|x| x.parse::<usize>().expect("Invalid CORRELATION_LOOKBACK"))
+        .unwrap_or(DEFAULT_LOOKBACK);
+    let top_n = std::env::var("CORRELATION_TOP_N")
+        .map(|x| x.parse::<usize>().expect("Invalid CORRELATION_TOP_N"))
+        .unwrap_or(DEFAULT_TOP_N);
+
+    let mut publisher = Publisher::new(bus.as_ref());
+    let mut conn = {
+        let client = redis::Client::open(redis_url).unwrap();
+        client.get_connection().unwrap()
+    };
+    let mut histories: HashMap<String, History> = HashMap::new();
+    let mut last_snapshot_time = now_ms();
+
+    let mut subscriber = Subscriber::new(
+        bus.as_ref(),
+        REDIS_TOPIC_CANDLESTICK_COMPOSITE,
+        Box::new(move |payload: String| {
+            let bar = match serde_json::from_str::<CompositeBar>(&payload) {
+                Ok(bar) => bar,
+                Err(err) => {
+                    warn!("{}, {}", err, payload);
+                    return;
+                }
+            };
+            if bar.bar_size == bar_size && bar.count > 0 && bar.close > 0.0 {
+                histories.entry(bar.base).or_default().push(
+                    bar.timestamp,
+                    bar.close,
+                    bar.volume_usd,
+                    lookback,
+                );
+            }
+
+            let now = now_ms();
+            if now - last_snapshot_time >= SNAPSHOT_INTERVAL {
+                // Discard assets which have no bar within the lookback
+                histories.retain(|_, history| {
+                    history
+                        .bars
+                        .keys()
+                        .next_back()
+                        .map(|timestamp| *timestamp > now - bar_size * lookback as i64)
+                        .unwrap_or(false)
+                });
+
+                let snapshot = build_snapshot(&histories, bar_size, lookback, top_n, now);
+                if let Err(PublishError::Redis(err)) =
+                    publisher.publish::<CorrelationSnapshot>(REDIS_TOPIC_CORRELATION, &snapshot)
+                {
+                    error!("{}", err);
+                }
+                if let Err(err) = conn.set::<&str, String, ()>(
+                    REDIS_KEY_CORRELATION,
+                    serde_json::to_string(&snapshot).unwrap(),
+                ) {
+                    error!("{}", err);
+                }
+                last_snapshot_time = now;
+            }
+        }),
+    );
+    subscriber.run();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BAR_SIZE: i64 = 300000;
+
+    fn history(closes: &[f64], volume_usd: f64) -> History {
+        let mut history = History::default();
+        for (i, close) in closes.iter().enumerate() {
+            history.push(i as i64 * BAR_SIZE, *close, volume_usd, closes.len());
+        }
+        history
+    }
+
+    // Closes with the given log returns, starting from 100
+    fn closes(returns: &[f64]) -> Vec<f64> {
+        let mut closes = vec![100.0];
+        for r in returns {
+            closes.push(closes.last().unwrap() * r.exp());
+        }
+        closes
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.unwrap();
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn history_returns() {
+        let mut history = history(&[100.0, 110.0, 99.0], 1.0);
+        let returns = history.returns(BAR_SIZE);
+        assert_eq!(returns.len(), 2);
+        assert!((returns[&BAR_SIZE] - 1.1f64.ln()).abs() < 1e-12);
+        assert!((returns[&(2 * BAR_SIZE)] - 0.9f64.ln()).abs() < 1e-12);
+
+        // No return across a missing bar
+        history.push(4 * BAR_SIZE, 100.0, 1.0, 10);
+        assert_eq!(history.returns(BAR_SIZE).len(), 2);
+
+        // Only lookback + 1 bars are kept
+        history.push(5 * BAR_SIZE, 100.0, 1.0, 2);
+        assert_eq!(history.bars.len(), 3);
+        assert_eq!(history.volume_usd(), 3.0);
+    }
+
+    #[test]
+    fn correlation_and_beta_of_returns() {
+        let y: HashMap<i64, f64> = [0.01, -0.02, 0.03, -0.01, 0.02]
+            .iter()
+            .enumerate()
+            .map(|(i, r)| (i as i64, *r))
+            .collect();
+        let scaled: HashMap<i64, f64> = y.iter().map(|(t, r)| (*t, 2.0 * r)).collect();
+        let inverse: HashMap<i64, f64> = y.iter().map(|(t, r)| (*t, -0.5 * r)).collect();
+
+        let (correlation, beta) = correlation_and_beta(&scaled, &y, 2);
+        assert_close(correlation, 1.0);
+        assert_close(beta, 2.0);
+        let (correlation, beta) = correlation_and_beta(&inverse, &y, 2);
+        assert_close(correlation, -1.0);
+        assert_close(beta, -0.5);
+
+        // Too few timestamps in common
+        assert_eq!(correlation_and_beta(&scaled, &y, 6), (None, None));
+        // A flat benchmark has no correlation or beta
+        let flat: HashMap<i64, f64> = y.keys().map(|t| (*t, 0.0)).collect();
+        assert_eq!(correlation_and_beta(&scaled, &flat, 2), (None, None));
+    }
+
+    #[test]
+    fn snapshot_of_correlated_assets() {
+        let btc = [0.01, -0.02, 0.03, -0.01, 0.02, 0.005];
+        let eth: Vec<f64> = btc.iter().map(|r| 1.5 * r).collect();
+        let xyz: Vec<f64> = btc.iter().map(|r| -r).collect();
+        let mut histories = HashMap::new();
+        histories.insert("BTC".to_string(), history(&closes(&btc), 3.0));
+        histories.insert("ETH".to_string(), history(&closes(&eth), 2.0));
+        histories.insert("XYZ".to_string(), history(&closes(&xyz), 1.0));
+        // A single bar has no returns
+        histories.insert("ABC".to_string(), history(&[1.0], 100.0));
+
+        // Sorted by volume_usd, benchmarks are always included
+        let snapshot = build_snapshot(&histories, BAR_SIZE, 6, 1, 1);
+        assert_eq!(snapshot.assets, ["ABC", "BTC", "ETH"]);
+
+        let snapshot = build_snapshot(&histories, BAR_SIZE, 6, 4, 1);
+        assert_eq!(snapshot.assets, ["ABC", "BTC", "ETH", "XYZ"]);
+        let correlation = &snapshot.correlation;
+        assert_eq!(correlation[0], [Some(1.0), None, None, None]);
+        assert_close(correlation[1][2], 1.0);
+        assert_close(correlation[2][1], 1.0);
+        assert_close(correlation[1][3], -1.0);
+        assert_close(correlation[2][3], -1.0);
+
+        let btc_beta = &snapshot.beta["BTC"];
+        assert_close(btc_beta["BTC"], 1.0);
+        assert_close(btc_beta["ETH"], 1.5);
+        assert_close(btc_beta["XYZ"], -1.0);
+        assert_eq!(btc_beta["ABC"], None);
+        let eth_beta = &snapshot.beta["ETH"];
+        assert_close(eth_beta["BTC"], 1.0 / 1.5);
+        assert_close(eth_beta["XYZ"], -1.0 / 1.5);
+    }
+}
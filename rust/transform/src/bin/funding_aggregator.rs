@@ -0,0 +1,334 @@
+use crypto_market_type::MarketType;
+use crypto_message::FundingRateMsg;
+use log::*;
+use redis::Commands;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use indicators::median;
+use transform::constants::{REDIS_TOPIC_FUNDING_RATE_PARSED, REDIS_TOPIC_FUNDING_RATE_SNAPSHOT};
+use utils::{
+    pubsub::{create_bus, PublishError, Publisher, Subscriber},
+    wait_redis,
+};
+
+const HOUR: i64 = 3600000;
+const DAY: i64 = 24 * HOUR;
+const MILLIS_PER_YEAR: f64 = 365.0 * DAY as f64;
+const DEFAULT_FUNDING_INTERVAL: i64 = 8 * HOUR;
+const SNAPSHOT_INTERVAL: i64 = 10000; // publish snapshots every 10 seconds
+const STALE_AFTER: i64 = HOUR; // rates not updated within an hour are excluded from snapshots
+
+// The latest snapshots, kept in a hash of base asset -> snapshot
+const REDIS_KEY_FUNDING_RATE_SNAPSHOT: &str = "coinsignal:funding_rate_snapshots";
+
+// Funding rates of one perpetual market
+struct FundingState {
+    exchange: String,
+    market_type: MarketType,
+    symbol: String,
+    pair: String,
+    timestamp: i64, // when the latest message was received
+    funding_rate: f64,
+    funding_time: i64,
+    estimated_rate: Option<f64>,
+    history: BTreeMap<i64, f64>, // funding_time -> funding_rate, in the last 7 days
+}
+
+impl FundingState {
+    fn update(&mut self, msg: &FundingRateMsg) {
+        self.timestamp = msg.timestamp;
+        self.funding_rate = msg.funding_rate;
+        self.funding_time = msg.funding_time;
+        self.estimated_rate = msg.estimated_rate;
+
+        self.history.insert(msg.funding_time, msg.funding_rate);
+        let expired = msg.funding_time - 7 * DAY;
+        self.history
+            .retain(|funding_time, _| *funding_time > expired);
+    }
+
+    // The interval between the latest two funding times
+    fn funding_interval(&self) -> i64 {
+        let mut iter = self.history.keys().rev();
+        match (iter.next(), iter.next()) {
+            (Some(last), Some(prev)) if last > prev => last - prev,
+            _ => DEFAULT_FUNDING_INTERVAL,
+        }
+    }
+
+    // Sum of funding rates settled within (now - window, now]
+    fn cumulative(&self, now: i64, window: i64) -> f64 {
+        self.history
+            .range(now - window + 1..=now)
+            .map(|(_, rate)| rate)
+            .sum()
+    }
+}
+
+#[derive(Serialize)]
+struct ExchangeFundingRate {
+    exchange: String,
+    market_type: MarketType,
+    symbol: String,
+    pair: String,
+    funding_rate: f64,
+    funding_time: i64,
+    estimated_rate: Option<f64>,
+    funding_interval: i64, // in millisecond
+    annualized_rate: f64,
+    cumulative_24h: f64, // sum of funding rates settled in the last 24 hours
+    cumulative_7d: f64,  // sum of funding rates settled in the last 7 days
+}
+
+/// Funding rates of one base asset across exchanges.
+#[derive(Serialize)]
+struct FundingRateSnapshot {
+    base: String,
+    timestamp: i64,
+    average_rate: f64,
+    median_rate: f64,
+    average_annualized_rate: f64,
+    median_annualized_rate: f64,
+    rates: Vec<ExchangeFundingRate>,
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn build_snapshots(states: &HashMap<String, FundingState>, now: i64) -> Vec<FundingRateSnapshot> {
+    let mut groups: BTreeMap<String, Vec<ExchangeFundingRate>> = BTreeMap::new();
+    for state in states.values() {
+        if state.timestamp + STALE_AFTER < now {
+            continue;
+        }
+        let base = state.pair.split('/').next().unwrap().to_string();
+        let funding_interval = state.funding_interval();
+        groups.entry(base).or_default().push(ExchangeFundingRate {
+            exchange: state.exchange.clone(),
+            market_type: state.market_type,
+            symbol: state.symbol.clone(),
+            pair: state.pair.clone(),
+            funding_rate: state.funding_rate,
+            funding_time: state.funding_time,
+            estimated_rate: state.estimated_rate,
+            funding_interval,
+            annualized_rate: state.funding_rate * MILLIS_PER_YEAR / funding_interval as f64,
+            cumulative_24h: state.cumulative(now, DAY),
+            cumulative_7d: state.cumulative(now, 7 * DAY),
+        });
+    }
+
+    groups
+        .into_iter()
+        .map(|(base, mut rates)| {
+            rates.sort_by(|a, b| (&a.exchange, &a.symbol).cmp(&(&b.exchange, &b.symbol)));
+            let funding_rates: Vec<f64> = rates.iter().map(|x| x.funding_rate).collect();
+            let annualized_rates: Vec<f64> = rates.iter().map(|x| x.annualized_rate).collect();
+            FundingRateSnapshot {
+                base,
+                timestamp: now,
+                average_rate: mean(&funding_rates),
+                median_rate: median(&funding_rates),
+                average_annualized_rate: mean(&annualized_rates),
+                median_annualized_rate: median(&annualized_rates),
+                rates,
+            }
+        })
+        .collect()
+}
+
+// Aggregate funding rates across exchanges
+fn main() {
+    env_logger::init();
+    let redis_url = if std::env::var("REDIS_URL").is_err() {
+        info!(
+            "The REDIS_URL environment variable is empty, using redis://localhost:6379 by default"
+        );
+        "redis://localhost:6379"
+    } else {
+        let url = std::env::var("REDIS_URL").unwrap();
+        Box::leak(url.into_boxed_str())
+    };
+    wait_redis(redis_url);
+    let bus = create_bus(redis_url);
+
+    let mut publisher = Publisher::new(bus.as_ref());
+    let mut conn = {
+        let client = redis::Client::open(redis_url).unwrap();
+        client.get_connection().unwrap()
+    };
+    let mut states: HashMap<String, FundingState> = HashMap::new();
+    let mut last_snapshot_time = 0;
+
+    let mut subscriber = Subscriber::new(
+        bus.as_ref(),
+        REDIS_TOPIC_FUNDING_RATE_PARSED,
+        Box::new(move |payload: String| {
+            let msg = match serde_json::from_str::<FundingRateMsg>(&payload) {
+                Ok(msg) => msg,
+                Err(err) => {
+                    warn!("{}, {}", err, payload);
+                    return;
+                }
+            };
+            if !msg.pair.contains('/') {
+                warn!("Invalid pair {}", msg.pair);
+                return;
+            }
+
+            let key = format!("{}-{}-{}", msg.exchange, msg.market_type, msg.symbol);
+            states
+                .entry(key)
+                .or_insert_with(|| FundingState {
+                    exchange: msg.exchange.clone(),
+                    market_type: msg.market_type,
+                    symbol: msg.symbol.clone(),
+                    pair: msg.pair.clone(),
+                    timestamp: msg.timestamp,
+                    funding_rate: msg.funding_rate,
+                    funding_time: msg.funding_time,
+                    estimated_rate: msg.estimated_rate,
+                    history: BTreeMap::new(),
+                })
+                .update(&msg);
+
+            let now = now_ms();
+            if now - last_snapshot_time >= SNAPSHOT_INTERVAL {
+                for snapshot in build_snapshots(&states, now) {
+                    if let Err(PublishError::Redis(err)) = publisher.publish::<FundingRateSnapshot>(
+                        REDIS_TOPIC_FUNDING_RATE_SNAPSHOT,
+                        &snapshot,
+                    ) {
+                        error!("{}", err);
+                    }
+                    if let Err(err) = conn.hset::<&str, &str, String, i64>(
+                        REDIS_KEY_FUNDING_RATE_SNAPSHOT,
+                        &snapshot.base,
+                        serde_json::to_string(&snapshot).unwrap(),
+                    ) {
+                        error!("{}", err);
+                    }
+                }
+                last_snapshot_time = now;
+            }
+        }),
+    );
+    subscriber.run();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1600012800000; // 2020-09-13T16:00:00Z
+
+    // A state settled at the given funding times, the latest rate is the last one
+    fn state(exchange: &str, pair: &str, settlements: &[(i64, f64)]) -> FundingState {
+        let (funding_time, funding_rate) = *settlements.last().unwrap();
+        FundingState {
+            exchange: exchange.to_string(),
+            market_type: MarketType::LinearSwap,
+            symbol: pair.replace('/', ""),
+            pair: pair.to_string(),
+            timestamp: NOW,
+            funding_rate,
+            funding_time,
+            estimated_rate: None,
+            history: settlements.iter().copied().collect(),
+        }
+    }
+
+    fn states(list: Vec<FundingState>) -> HashMap<String, FundingState> {
+        list.into_iter()
+            .map(|x| (format!("{}-{}", x.exchange, x.symbol), x))
+            .collect()
+    }
+
+    #[test]
+    fn spread_and_median_across_exchanges() {
+        let states = states(vec![
+            state("binance", "BTC/USDT", &[(NOW - 8 * HOUR, 0.0001)]),
+            state("bybit", "BTC/USDT", &[(NOW - 8 * HOUR, 0.0003)]),
+            state("okx", "BTC/USDT", &[(NOW - 8 * HOUR, 0.0008)]),
+            state("binance", "ETH/USDT", &[(NOW - 8 * HOUR, -0.0002)]),
+        ]);
+        let snapshots = build_snapshots(&states, NOW);
+        assert_eq!(snapshots.len(), 2);
+
+        let btc = &snapshots[0];
+        assert_eq!(btc.base, "BTC");
+        assert_eq!(btc.timestamp, NOW);
+        let exchanges: Vec<&str> = btc.rates.iter().map(|x| x.exchange.as_str()).collect();
+        assert_eq!(exchanges, ["binance", "bybit", "okx"]);
+        assert!((btc.average_rate - 0.0004).abs() < 1e-12);
+        assert_eq!(btc.median_rate, 0.0003);
+        assert!((btc.median_annualized_rate - 0.0003 * 3.0 * 365.0).abs() < 1e-12);
+
+        let eth = &snapshots[1];
+        assert_eq!(eth.base, "ETH");
+        assert_eq!(eth.rates.len(), 1);
+        assert_eq!(eth.median_rate, -0.0002);
+        assert_eq!(eth.average_rate, -0.0002);
+    }
+
+    #[test]
+    fn annualize_by_funding_interval() {
+        let states = states(vec![
+            // Settled every 8 hours
+            state(
+                "binance",
+                "BTC/USDT",
+                &[(NOW - 16 * HOUR, 0.0002), (NOW - 8 * HOUR, 0.0001)],
+            ),
+            // Settled every hour
+            state(
+                "dydx",
+                "BTC/USD",
+                &[(NOW - 2 * HOUR, 0.00002), (NOW - HOUR, 0.00001)],
+            ),
+            // A single settlement falls back to the default interval
+            state("okx", "BTC/USDT", &[(NOW - HOUR, 0.0002)]),
+        ]);
+        let snapshots = build_snapshots(&states, NOW);
+        assert_eq!(snapshots.len(), 1);
+        let rates = &snapshots[0].rates;
+
+        assert_eq!(rates[0].exchange, "binance");
+        assert_eq!(rates[0].funding_interval, 8 * HOUR);
+        assert!((rates[0].annualized_rate - 0.0001 * 3.0 * 365.0).abs() < 1e-12);
+        assert!((rates[0].cumulative_24h - 0.0003).abs() < 1e-12);
+
+        assert_eq!(rates[1].exchange, "dydx");
+        assert_eq!(rates[1].funding_interval, HOUR);
+        assert!((rates[1].annualized_rate - 0.00001 * 24.0 * 365.0).abs() < 1e-12);
+
+        assert_eq!(rates[2].funding_interval, DEFAULT_FUNDING_INTERVAL);
+
+        // Rates are compared per year, the hourly rate of dydx is the lowest
+        assert!((snapshots[0].median_annualized_rate - 0.1095).abs() < 1e-12);
+        assert_eq!(snapshots[0].median_rate, 0.0001);
+    }
+
+    #[test]
+    fn exclude_stale_rates() {
+        let mut stale = state("bybit", "BTC/USDT", &[(NOW - 8 * HOUR, 0.0003)]);
+        stale.timestamp = NOW - STALE_AFTER - 1;
+        let states = states(vec![
+            state("binance", "BTC/USDT", &[(NOW - 8 * HOUR, 0.0001)]),
+            stale,
+        ]);
+        let snapshots = build_snapshots(&states, NOW);
+        assert_eq!(snapshots[0].rates.len(), 1);
+        assert_eq!(snapshots[0].rates[0].exchange, "binance");
+    }
+}
@@ -0,0 +1,697 @@
+use crypto_market_type::MarketType;
+use indicators::{
+    imbalance, AnomalyDetector, Atr, BollingerBands, Cvd, Ema, Macd, Obv, Rsi, Sma, Volatility,
+};
+use log::*;
+use redis::Commands;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+
+use transform::constants::{
+    REDIS_TOPIC_ANOMALY, REDIS_TOPIC_CANDLESTICK_COMPOSITE, REDIS_TOPIC_CANDLESTICK_EXT,
+    REDIS_TOPIC_INDICATOR, REDIS_TOPIC_ORDER_FLOW, REDIS_TOPIC_ORDER_FLOW_COMPOSITE,
+    REDIS_TOPIC_VOLATILITY,
+};
+use utils::{
+    pubsub::{create_bus, MessageBus, PublishError, Publisher, Subscriber},
+    wait_redis,
+};
+
+const SMA_PERIOD: usize = 20;
+const EMA_PERIOD: usize = 20;
+const RSI_PERIOD: usize = 14;
+const ATR_PERIOD: usize = 14;
+const DEFAULT_VOLATILITY_LOOKBACKS: &str = "20";
+const MILLIS_PER_YEAR: f64 = 365.0 * 86400000.0;
+const DEFAULT_ANOMALY_WINDOW: usize = 100;
+const Z_SCORE_THRESHOLD: f64 = 4.0;
+const ROBUST_Z_SCORE_THRESHOLD: f64 = 3.5;
+
+// Cumulative volume deltas are saved in this hash so that they persist across restarts
+const REDIS_KEY_CVD: &str = "coinsignal:cvd";
+
+// Buy and sell side fields of Candlestick and CompositeCandlestick
+#[derive(Deserialize)]
+struct SideVolumes {
+    volume_sell: f64,
+    volume_buy: f64,
+    volume_usd_sell: f64,
+    volume_usd_buy: f64,
+    count_sell: i64,
+    count_buy: i64,
+}
+
+// Fields of Candlestick used by indicators
+#[derive(Deserialize)]
+struct Bar {
+    exchange: String,
+    market_type: MarketType,
+    symbol: String,
+    pair: String,
+    bar_size: i64,
+    timestamp: i64,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    volume: f64,
+    volume_usd: f64,
+    count: i64,
+    #[serde(flatten)]
+    sides: SideVolumes,
+}
+
+// Fields of CompositeCandlestick used by order flow
+#[derive(Deserialize)]
+struct CompositeBar {
+    base: String,
+    bar_size: i64,
+    timestamp: i64,
+    count: i64,
+    #[serde(flatten)]
+    sides: SideVolumes,
+}
+
+/// Indicator values of one bar, null if an indicator is not warmed up yet.
+#[derive(Serialize)]
+struct IndicatorMsg {
+    exchange: String,
+    market_type: MarketType,
+    symbol: String,
+    pair: String,
+    bar_size: i64,  // in millisecond
+    timestamp: i64, // bar end time, in millisecond
+    close: f64,
+
+    sma: Option<f64>,
+    ema: Option<f64>,
+    rsi: Option<f64>,
+    macd: Option<f64>,
+    macd_signal: Option<f64>,
+    macd_histogram: Option<f64>,
+    bb_upper: Option<f64>,
+    bb_middle: Option<f64>,
+    bb_lower: Option<f64>,
+    atr: Option<f64>,
+    obv: Option<f64>,
+}
+
+/// Annualized volatility over the latest lookback bars, null until lookback bars are seen.
+#[derive(Serialize)]
+struct VolatilityMsg {
+    exchange: String,
+    market_type: MarketType,
+    symbol: String,
+    pair: String,
+    bar_size: i64,   // in millisecond
+    timestamp: i64,  // bar end time, in millisecond
+    lookback: usize, // number of bars
+
+    close_to_close: Option<f64>,
+    parkinson: Option<f64>,
+    garman_klass: Option<f64>,
+    rogers_satchell: Option<f64>,
+}
+
+// Metrics checked by anomaly detection
+#[derive(Clone, Copy)]
+enum Metric {
+    VolumeUsd,
+    Count,
+    Return, // log return of close
+}
+
+impl Metric {
+    fn name(&self) -> &'static str {
+        match self {
+            Metric::VolumeUsd => "volume_usd",
+            Metric::Count => "count",
+            Metric::Return => "return",
+        }
+    }
+}
+
+/// A metric of one bar which deviates from its baseline of previous bars.
+#[derive(Serialize)]
+struct AnomalyMsg {
+    exchange: String,
+    market_type: MarketType,
+    symbol: String,
+    pair: String,
+    bar_size: i64,              // in millisecond
+    timestamp: i64,             // bar end time, in millisecond
+    metric: &'static str,       // volume_usd, count or return
+    methods: Vec<&'static str>, // z_score and/or mad, the detection methods triggered
+
+    value: f64,
+    mean: f64,
+    std_dev: f64,
+    z_score: Option<f64>, // null if the baseline is flat
+    median: f64,
+    mad: f64,
+    robust_z_score: Option<f64>, // null if the baseline is flat
+    window: usize,               // number of bars in the baseline
+}
+
+/// Order flow of one bar.
+#[derive(Serialize)]
+struct OrderFlow {
+    delta: f64,                    // volume_buy - volume_sell
+    delta_usd: f64,                // volume_usd_buy - volume_usd_sell
+    cvd: f64,                      // cumulative delta
+    cvd_usd: f64,                  // cumulative delta_usd
+    volume_imbalance: Option<f64>, // delta / (volume_buy + volume_sell)
+    count_imbalance: Option<f64>,  // (count_buy - count_sell) / (count_buy + count_sell)
+}
+
+#[derive(Serialize)]
+struct OrderFlowMsg {
+    exchange: String,
+    market_type: MarketType,
+    symbol: String,
+    pair: String,
+    bar_size: i64,  // in millisecond
+    timestamp: i64, // bar end time, in millisecond
+    #[serde(flatten)]
+    order_flow: OrderFlow,
+}
+
+/// Order flow of one base asset across exchanges and market types.
+#[derive(Serialize)]
+struct CompositeOrderFlowMsg {
+    base: String,
+    bar_size: i64,  // in millisecond
+    timestamp: i64, // bar end time, in millisecond
+    #[serde(flatten)]
+    order_flow: OrderFlow,
+}
+
+// Saved cumulative volume delta of a series
+#[derive(Serialize, Deserialize)]
+struct CvdState {
+    timestamp: i64,
+    cvd: f64,
+    cvd_usd: f64,
+}
+
+// Saves CVD to Redis, a lost connection is reopened on the next load or save
+struct CvdStore {
+    client: redis::Client,
+    conn: Option<redis::Connection>,
+}
+
+impl CvdStore {
+    fn new(redis_url: &str) -> Self {
+        CvdStore {
+            client: redis::Client::open(redis_url)
+                .unwrap_or_else(|err| panic!("Invalid Redis URL {}, {}", redis_url, err)),
+            conn: None,
+        }
+    }
+
+    fn query<T>(
+        &mut self,
+        f: impl FnOnce(&mut redis::Connection) -> redis::RedisResult<T>,
+    ) -> redis::RedisResult<T> {
+        if self.conn.is_none() {
+            self.conn = Some(self.client.get_connection()?);
+        }
+        let result = f(self.conn.as_mut().unwrap());
+        if let Err(err) = &result {
+            if err.is_io_error() || err.is_connection_dropped() {
+                self.conn = None;
+            }
+        }
+        result
+    }
+
+    fn load(&mut self, key: &str) -> Option<CvdState> {
+        self.query(|conn| conn.hget::<&str, &str, Option<String>>(REDIS_KEY_CVD, key))
+            .unwrap_or_else(|err| {
+                error!("Failed to load CVD of {}: {}", key, err);
+                None
+            })
+            .and_then(|json| serde_json::from_str::<CvdState>(&json).ok())
+    }
+
+    fn save(&mut self, key: &str, state: &CvdState) {
+        let json = serde_json::to_string(state).unwrap();
+        if let Err(err) =
+            self.query(|conn| conn.hset::<&str, &str, String, i64>(REDIS_KEY_CVD, key, json))
+        {
+            error!("Failed to save CVD of {}: {}", key, err);
+        }
+    }
+}
+
+// Cumulative volume delta of one series, saved to Redis on every update
+struct OrderFlowSeries {
+    key: String,
+    timestamp: i64, // end time of the latest bar
+    cvd: Cvd,
+    cvd_usd: Cvd,
+}
+
+impl OrderFlowSeries {
+    fn load(key: String, store: &mut CvdStore) -> Self {
+        let state = store.load(&key).unwrap_or(CvdState {
+            timestamp: 0,
+            cvd: 0.0,
+            cvd_usd: 0.0,
+        });
+        OrderFlowSeries {
+            key,
+            timestamp: state.timestamp,
+            cvd: Cvd::new(state.cvd),
+            cvd_usd: Cvd::new(state.cvd_usd),
+        }
+    }
+
+    // Returns None if the bar is not newer than the latest one
+    fn update(
+        &mut self,
+        timestamp: i64,
+        sides: &SideVolumes,
+        store: &mut CvdStore,
+    ) -> Option<OrderFlow> {
+        if timestamp <= self.timestamp {
+            return None;
+        }
+        self.timestamp = timestamp;
+        let order_flow = OrderFlow {
+            delta: sides.volume_buy - sides.volume_sell,
+            delta_usd: sides.volume_usd_buy - sides.volume_usd_sell,
+            cvd: self.cvd.update(sides.volume_buy, sides.volume_sell),
+            cvd_usd: self
+                .cvd_usd
+                .update(sides.volume_usd_buy, sides.volume_usd_sell),
+            volume_imbalance: imbalance(sides.volume_buy, sides.volume_sell),
+            count_imbalance: imbalance(sides.count_buy as f64, sides.count_sell as f64),
+        };
+
+        let state = CvdState {
+            timestamp,
+            cvd: self.cvd.value(),
+            cvd_usd: self.cvd_usd.value(),
+        };
+        store.save(&self.key, &state);
+        Some(order_flow)
+    }
+}
+
+// Rolling state of one series
+struct Series {
+    timestamp: i64, // end time of the latest bar
+    sma: Sma,
+    ema: Ema,
+    rsi: Rsi,
+    macd: Macd,
+    bollinger: BollingerBands,
+    atr: Atr,
+    obv: Obv,
+    volatility: Vec<(usize, Volatility)>, // lookback -> estimators
+    prev_close: Option<f64>,
+    anomaly_window: usize,
+    anomalies: Vec<(Metric, AnomalyDetector)>,
+}
+
+impl Series {
+    fn new(bar_size: i64, volatility_lookbacks: &[usize], anomaly_window: usize) -> Self {
+        let periods_per_year = MILLIS_PER_YEAR / bar_size as f64;
+        Series {
+            timestamp: 0,
+            sma: Sma::new(SMA_PERIOD),
+            ema: Ema::new(EMA_PERIOD),
+            rsi: Rsi::new(RSI_PERIOD),
+            macd: Macd::default(),
+            bollinger: BollingerBands::default(),
+            atr: Atr::new(ATR_PERIOD),
+            obv: Obv::new(),
+            volatility: volatility_lookbacks
+                .iter()
+                .map(|lookback| (*lookback, Volatility::new(*lookback, periods_per_year)))
+                .collect(),
+            prev_close: None,
+            anomaly_window,
+            anomalies: [Metric::VolumeUsd, Metric::Count, Metric::Return]
+                .iter()
+                .map(|metric| (*metric, AnomalyDetector::new(anomaly_window)))
+                .collect(),
+        }
+    }
+
+    fn update(&mut self, bar: &Bar) -> IndicatorMsg {
+        self.timestamp = bar.timestamp;
+        let macd = self.macd.update(bar.close);
+        let (macd, macd_signal, macd_histogram) = if let Some(macd) = macd {
+            (Some(macd.macd), Some(macd.signal), Some(macd.histogram))
+        } else {
+            (None, None, None)
+        };
+        let bollinger = self.bollinger.update(bar.close);
+
+        IndicatorMsg {
+            exchange: bar.exchange.clone(),
+            market_type: bar.market_type,
+            symbol: bar.symbol.clone(),
+            pair: bar.pair.clone(),
+            bar_size: bar.bar_size,
+            timestamp: bar.timestamp,
+            close: bar.close,
+
+            sma: self.sma.update(bar.close),
+            ema: self.ema.update(bar.close),
+            rsi: self.rsi.update(bar.close),
+            macd,
+            macd_signal,
+            macd_histogram,
+            bb_upper: bollinger.as_ref().map(|x| x.upper),
+            bb_middle: bollinger.as_ref().map(|x| x.middle),
+            bb_lower: bollinger.as_ref().map(|x| x.lower),
+            atr: self.atr.update(bar.high, bar.low, bar.close),
+            obv: self.obv.update(bar.close, bar.volume),
+        }
+    }
+
+    fn update_volatility(&mut self, bar: &Bar) -> Vec<VolatilityMsg> {
+        self.volatility
+            .iter_mut()
+            .map(|(lookback, volatility)| {
+                let output = volatility.update(bar.open, bar.high, bar.low, bar.close);
+                VolatilityMsg {
+                    exchange: bar.exchange.clone(),
+                    market_type: bar.market_type,
+                    symbol: bar.symbol.clone(),
+                    pair: bar.pair.clone(),
+                    bar_size: bar.bar_size,
+                    timestamp: bar.timestamp,
+                    lookback: *lookback,
+
+                    close_to_close: output.close_to_close,
+                    parkinson: output.parkinson,
+                    garman_klass: output.garman_klass,
+                    rogers_satchell: output.rogers_satchell,
+                }
+            })
+            .collect()
+    }
+
+    fn update_anomalies(&mut self, bar: &Bar) -> Vec<AnomalyMsg> {
+        let ret = self
+            .prev_close
+            .map(|prev_close| (bar.close / prev_close).ln());
+        self.prev_close = Some(bar.close);
+
+        let mut msgs = Vec::new();
+        for (metric, detector) in self.anomalies.iter_mut() {
+            let value = match metric {
+                Metric::VolumeUsd => bar.volume_usd,
+                Metric::Count => bar.count as f64,
+                Metric::Return => {
+                    if let Some(ret) = ret {
+                        ret
+                    } else {
+                        continue;
+                    }
+                }
+            };
+            if let Some(score) = detector.update(value) {
+                let mut methods = Vec::new();
+                if score.z_score.is_some_and(|x| x.abs() > Z_SCORE_THRESHOLD) {
+                    methods.push("z_score");
+                }
+                if score
+                    .robust_z_score
+                    .is_some_and(|x| x.abs() > ROBUST_Z_SCORE_THRESHOLD)
+                {
+                    methods.push("mad");
+                }
+                if !methods.is_empty() {
+                    msgs.push(AnomalyMsg {
+                        exchange: bar.exchange.clone(),
+                        market_type: bar.market_type,
+                        symbol: bar.symbol.clone(),
+                        pair: bar.pair.clone(),
+                        bar_size: bar.bar_size,
+                        timestamp: bar.timestamp,
+                        metric: metric.name(),
+                        methods,
+
+                        value: score.value,
+                        mean: score.mean,
+                        std_dev: score.std_dev,
+                        z_score: score.z_score,
+                        median: score.median,
+                        mad: score.mad,
+                        robust_z_score: score.robust_z_score,
+                        window: self.anomaly_window,
+                    });
+                }
+            }
+        }
+        msgs
+    }
+}
+
+// Volatility lookbacks in bars are read from the VOLATILITY_LOOKBACKS environment variable,
+// e.g., VOLATILITY_LOOKBACKS=20,60
+fn get_volatility_lookbacks() -> Vec<usize> {
+    let text = if let Ok(text) = std::env::var("VOLATILITY_LOOKBACKS") {
+        text
+    } else {
+        info!(
+            "The VOLATILITY_LOOKBACKS environment variable is empty, using {} by default",
+            DEFAULT_VOLATILITY_LOOKBACKS
+        );
+        DEFAULT_VOLATILITY_LOOKBACKS.to_string()
+    };
+    text.split(',')
+        .filter(|x| !x.trim().is_empty())
+        .map(|x| {
+            x.trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|lookback| *lookback > 1)
+                .unwrap_or_else(|| panic!("Invalid volatility lookback {}", x))
+        })
+        .collect()
+}
+
+// Order flow of base assets across exchanges, from composite candlesticks
+fn create_composite_thread(
+    redis_url: String,
+    bus: Arc<dyn MessageBus>,
+) -> std::thread::JoinHandle<()> {
+    std::thread::Builder::new()
+        .name("composite".to_string())
+        .spawn(move || {
+            let mut publisher = Publisher::new(bus.as_ref());
+            let mut cvd_store = CvdStore::new(&redis_url);
+            let mut order_flows: HashMap<String, OrderFlowSeries> = HashMap::new();
+
+            let mut subscriber = Subscriber::new(
+                bus.as_ref(),
+                REDIS_TOPIC_CANDLESTICK_COMPOSITE,
+                Box::new(move |payload: String| {
+                    let bar = match serde_json::from_str::<CompositeBar>(&payload) {
+                        Ok(bar) => bar,
+                        Err(err) => {
+                            warn!("{}, {}", err, payload);
+                            return;
+                        }
+                    };
+                    if bar.count == 0 {
+                        return;
+                    }
+
+                    let key = format!("composite-{}-{}", bar.base, bar.bar_size);
+                    let order_flow = order_flows
+                        .entry(key.clone())
+                        .or_insert_with(|| OrderFlowSeries::load(key, &mut cvd_store));
+                    if let Some(order_flow) =
+                        order_flow.update(bar.timestamp, &bar.sides, &mut cvd_store)
+                    {
+                        let msg = CompositeOrderFlowMsg {
+                            base: bar.base,
+                            bar_size: bar.bar_size,
+                            timestamp: bar.timestamp,
+                            order_flow,
+                        };
+                        if let Err(PublishError::Redis(err)) = publisher
+                            .publish::<CompositeOrderFlowMsg>(
+                                REDIS_TOPIC_ORDER_FLOW_COMPOSITE,
+                                &msg,
+                            )
+                        {
+                            error!("{}", err);
+                        }
+                    }
+                }),
+            );
+            subscriber.run();
+        })
+        .unwrap()
+}
+
+// Calculate indicators over candlesticks
+fn main() {
+    env_logger::init();
+    let redis_url = if std::env::var("REDIS_URL").is_err() {
+        info!(
+            "The REDIS_URL environment variable is empty, using redis://localhost:6379 by default"
+        );
+        "redis://localhost:6379"
+    } else {
+        let url = std::env::var("REDIS_URL").unwrap();
+        Box::leak(url.into_boxed_str())
+    };
+    wait_redis(redis_url);
+    let bus = create_bus(redis_url);
+
+    let composite_thread = create_composite_thread(redis_url.to_string(), bus.clone());
+    std::thread::spawn(move || {
+        // Exit if the composite thread panics, otherwise order flow of base assets stops silently
+        let _ = composite_thread.join();
+        error!("The composite thread exited");
+        std::process::exit(1);
+    });
+
+    let volatility_lookbacks = get_volatility_lookbacks();
+    let anomaly_window = std::env::var("ANOMALY_WINDOW")
+        .map(|x| {
+            x.parse::<usize>()
+                .ok()
+                .filter(|window| *window > 1)
+                .expect("Invalid ANOMALY_WINDOW")
+        })
+        .unwrap_or(DEFAULT_ANOMALY_WINDOW);
+
+    let mut publisher = Publisher::new(bus.as_ref());
+    let mut cvd_store = CvdStore::new(redis_url);
+    let mut series: HashMap<String, Series> = HashMap::new();
+    let mut order_flows: HashMap<String, OrderFlowSeries> = HashMap::new();
+
+    let mut subscriber = Subscriber::new(
+        bus.as_ref(),
+        REDIS_TOPIC_CANDLESTICK_EXT,
+        Box::new(move |payload: String| {
+            let bar = match serde_json::from_str::<Bar>(&payload) {
+                Ok(bar) => bar,
+                Err(err) => {
+                    warn!("{}, {}", err, payload);
+                    return;
+                }
+            };
+            if bar.count == 0 || bar.open <= 0.0 {
+                return;
+            }
+
+            let key = format!(
+                "{}-{}-{}-{}-{}",
+                bar.exchange, bar.market_type, bar.pair, bar.symbol, bar.bar_size
+            );
+            let order_flow = order_flows
+                .entry(key.clone())
+                .or_insert_with(|| OrderFlowSeries::load(key.clone(), &mut cvd_store));
+            if let Some(order_flow) = order_flow.update(bar.timestamp, &bar.sides, &mut cvd_store) {
+                let msg = OrderFlowMsg {
+                    exchange: bar.exchange.clone(),
+                    market_type: bar.market_type,
+                    symbol: bar.symbol.clone(),
+                    pair: bar.pair.clone(),
+                    bar_size: bar.bar_size,
+                    timestamp: bar.timestamp,
+                    order_flow,
+                };
+                if let Err(PublishError::Redis(err)) =
+                    publisher.publish::<OrderFlowMsg>(REDIS_TOPIC_ORDER_FLOW, &msg)
+                {
+                    error!("{}", err);
+                }
+            }
+
+            let series = series.entry(key).or_insert_with(|| {
+                Series::new(bar.bar_size, &volatility_lookbacks, anomaly_window)
+            });
+            if bar.timestamp <= series.timestamp {
+                warn!(
+                    "Skipped out-of-order bar {} of {}-{}-{}-{}",
+                    bar.timestamp, bar.exchange, bar.market_type, bar.pair, bar.bar_size
+                );
+                return;
+            }
+            let msg = series.update(&bar);
+            if let Err(PublishError::Redis(err)) =
+                publisher.publish::<IndicatorMsg>(REDIS_TOPIC_INDICATOR, &msg)
+            {
+                error!("{}", err);
+            }
+            for msg in series.update_volatility(&bar) {
+                if let Err(PublishError::Redis(err)) =
+                    publisher.publish::<VolatilityMsg>(REDIS_TOPIC_VOLATILITY, &msg)
+                {
+                    error!("{}", err);
+                }
+            }
+            for msg in series.update_anomalies(&bar) {
+                if let Err(PublishError::Redis(err)) =
+                    publisher.publish::<AnomalyMsg>(REDIS_TOPIC_ANOMALY, &msg)
+                {
+                    error!("{}", err);
+                }
+            }
+        }),
+    );
+    subscriber.run();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sides(volume_buy: f64, volume_sell: f64, count_buy: i64, count_sell: i64) -> SideVolumes {
+        SideVolumes {
+            volume_sell,
+            volume_buy,
+            volume_usd_sell: volume_sell * 100.0,
+            volume_usd_buy: volume_buy * 100.0,
+            count_sell,
+            count_buy,
+        }
+    }
+
+    #[test]
+    fn order_flow_without_redis() {
+        // Nothing listens on port 1, so every load and save fails and is logged
+        let mut store = CvdStore::new("redis://127.0.0.1:1");
+        let mut series = OrderFlowSeries::load("binance-spot-BTC/USDT".to_string(), &mut store);
+        assert_eq!(series.timestamp, 0);
+
+        let order_flow = series
+            .update(1, &sides(3.0, 1.0, 3, 1), &mut store)
+            .unwrap();
+        assert_eq!((order_flow.delta, order_flow.delta_usd), (2.0, 200.0));
+        assert_eq!((order_flow.cvd, order_flow.cvd_usd), (2.0, 200.0));
+        assert_eq!(order_flow.volume_imbalance, Some(0.5));
+        assert_eq!(order_flow.count_imbalance, Some(0.5));
+
+        let order_flow = series
+            .update(2, &sides(0.0, 5.0, 0, 2), &mut store)
+            .unwrap();
+        assert_eq!(order_flow.delta, -5.0);
+        assert_eq!(order_flow.cvd, -3.0);
+        assert_eq!(order_flow.volume_imbalance, Some(-1.0));
+
+        // Bars which are not newer are skipped
+        assert!(series
+            .update(2, &sides(1.0, 0.0, 1, 0), &mut store)
+            .is_none());
+        assert_eq!(series.cvd.value(), -3.0);
+
+        let order_flow = series
+            .update(3, &sides(0.0, 0.0, 0, 0), &mut store)
+            .unwrap();
+        assert_eq!(order_flow.cvd, -3.0);
+        assert_eq!(order_flow.count_imbalance, None);
+    }
+}
@@ -0,0 +1,122 @@
+use crypto_market_type::MarketType;
+use crypto_msg_type::MessageType;
+use log::*;
+use serde::Serialize;
+
+use transform::parser::ParseError;
+
+// Messages that failed to parse
+const REDIS_KEY_DEAD_LETTER: &str = "coinsignal:dead_letter:msg_parser";
+// Number of errors per kind
+const REDIS_KEY_ERROR_COUNT: &str = "coinsignal:error_count:msg_parser";
+const MAX_DEAD_LETTERS: isize = 10000; // the capacity of the dead letter list
+
+/// A message that failed to parse, with the reason.
+#[derive(Serialize)]
+pub struct DeadLetter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exchange: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub market_type: Option<MarketType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub msg_type: Option<MessageType>,
+    pub kind: &'static str,
+    pub reason: String,
+    pub payload: String, // the raw payload
+    pub timestamp: i64,  // when the error occurred, in millisecond
+}
+
+impl DeadLetter {
+    pub fn new(error: &ParseError, payload: String, timestamp: i64) -> Self {
+        DeadLetter {
+            exchange: None,
+            market_type: None,
+            msg_type: None,
+            kind: error.kind(),
+            reason: error.to_string(),
+            payload,
+            timestamp,
+        }
+    }
+}
+
+/// Saves dead letters to a capped Redis list and counts errors per kind in a
+/// Redis hash.
+///
+/// A lost connection is reopened on the next push, dead letters are only
+/// logged while Redis is down.
+pub struct DeadLetterQueue {
+    client: redis::Client,
+    connection: Option<redis::Connection>,
+}
+
+impl DeadLetterQueue {
+    pub fn new(redis_url: &str) -> Self {
+        let client = redis::Client::open(redis_url)
+            .unwrap_or_else(|err| panic!("Invalid Redis URL {}, {}", redis_url, err));
+        Self {
+            client,
+            connection: None,
+        }
+    }
+
+    fn save(&mut self, letter: &DeadLetter) -> redis::RedisResult<()> {
+        if self.connection.is_none() {
+            self.connection = Some(self.client.get_connection()?);
+        }
+        let result = redis::pipe()
+            .lpush(
+                REDIS_KEY_DEAD_LETTER,
+                serde_json::to_string(letter).unwrap(),
+            )
+            .ltrim(REDIS_KEY_DEAD_LETTER, 0, MAX_DEAD_LETTERS - 1)
+            .hincr(REDIS_KEY_ERROR_COUNT, letter.kind, 1)
+            .query::<()>(self.connection.as_mut().unwrap());
+        if let Err(err) = &result {
+            if err.is_io_error() || err.is_connection_dropped() {
+                self.connection = None;
+            }
+        }
+        result
+    }
+
+    pub fn push(&mut self, letter: &DeadLetter) {
+        warn!("{}, {}", letter.reason, letter.payload);
+        if let Err(err) = self.save(letter) {
+            error!("Failed to save the dead letter: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_without_redis() {
+        // Nothing listens on port 1, dead letters are logged and msg_parser keeps running
+        let mut queue = DeadLetterQueue::new("redis://127.0.0.1:1");
+        let err = ParseError::UnknownMessageType(MessageType::L2Event);
+        for _ in 0..2 {
+            queue.push(&DeadLetter::new(&err, "{}".to_string(), 1654400000000));
+            assert!(queue.connection.is_none());
+        }
+    }
+
+    #[test]
+    fn serialize_letter() {
+        let err = ParseError::ParseFailed("missing field".to_string());
+        let mut letter = DeadLetter::new(&err, "{}".to_string(), 1654400000000);
+        assert_eq!(
+            serde_json::to_string(&letter).unwrap(),
+            r#"{"kind":"parse_failed","reason":"failed to parse: missing field","payload":"{}","timestamp":1654400000000}"#
+        );
+        letter.exchange = Some("binance".to_string());
+        letter.market_type = Some(MarketType::Spot);
+        letter.msg_type = Some(MessageType::Trade);
+        let json = serde_json::to_string(&letter).unwrap();
+        assert!(
+            json.starts_with(r#"{"exchange":"binance","market_type":"spot","msg_type":"trade","#)
+        );
+    }
+}
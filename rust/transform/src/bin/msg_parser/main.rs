@@ -0,0 +1,178 @@
+use std::{
+    convert::TryInto,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crypto_message::{BboMsg, FundingRateMsg, OrderBookMsg, TradeMsg};
+use log::*;
+use redis::Commands;
+use transform::constants::{
+    REDIS_TOPIC_BBO_PARSED, REDIS_TOPIC_FUNDING_RATE_PARSED, REDIS_TOPIC_L2_SNAPSHOT_PARSED,
+    REDIS_TOPIC_OPEN_INTEREST_PARSED, REDIS_TOPIC_TICKER_PARSED, REDIS_TOPIC_TRADE_PARSED,
+};
+use transform::parser::{self, Message, NormalizedMsg, ParsedMsg};
+use transform::worker_pool::WorkerPool;
+use utils::{
+    pubsub::{create_bus, MessageBus, PublishError, Publisher, RedisPubSub},
+    wait_redis,
+};
+
+mod dead_letter;
+
+use dead_letter::{DeadLetter, DeadLetterQueue};
+
+const REDIS_TOPIC_TRADE: &str = "carbonbot:trade";
+const REDIS_TOPIC_FUNDING_RATE: &str = "carbonbot:funding_rate";
+const REDIS_TOPIC_BBO: &str = "carbonbot:bbo";
+const REDIS_TOPIC_L2_SNAPSHOT: &str = "carbonbot:l2_snapshot";
+const REDIS_TOPIC_L2_TOPK: &str = "carbonbot:l2_topk";
+const REDIS_TOPIC_TICKER: &str = "carbonbot:ticker";
+const REDIS_TOPIC_OPEN_INTEREST: &str = "carbonbot:open_interest";
+
+// Backpressure metrics of the parser pool
+const REDIS_KEY_METRICS: &str = "coinsignal:metrics:msg_parser";
+const METRICS_INTERVAL: Duration = Duration::from_secs(10);
+const DEFAULT_PARSER_QUEUE_SIZE: usize = 4096;
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+        .try_into()
+        .unwrap()
+}
+
+fn publish(publisher: &mut Publisher, msg: &ParsedMsg) -> Result<(), PublishError> {
+    match msg {
+        ParsedMsg::Trade(msg) => publisher.publish::<TradeMsg>(REDIS_TOPIC_TRADE_PARSED, msg),
+        ParsedMsg::FundingRate(msg) => {
+            publisher.publish::<FundingRateMsg>(REDIS_TOPIC_FUNDING_RATE_PARSED, msg)
+        }
+        ParsedMsg::Bbo(msg) => publisher.publish::<BboMsg>(REDIS_TOPIC_BBO_PARSED, msg),
+        ParsedMsg::L2Snapshot(msg) => {
+            publisher.publish::<OrderBookMsg>(REDIS_TOPIC_L2_SNAPSHOT_PARSED, msg)
+        }
+        ParsedMsg::Ticker(msg) => {
+            publisher.publish::<NormalizedMsg>(REDIS_TOPIC_TICKER_PARSED, msg)
+        }
+        ParsedMsg::OpenInterest(msg) => {
+            publisher.publish::<NormalizedMsg>(REDIS_TOPIC_OPEN_INTEREST_PARSED, msg)
+        }
+    }
+}
+
+// Parse messages and publish them, one handler per worker thread
+fn create_parser(redis_url: &str, bus: &dyn MessageBus) -> impl FnMut(Message) {
+    let mut publisher = Publisher::new(bus);
+    let mut dead_letters = DeadLetterQueue::new(redis_url);
+    move |raw_msg: Message| {
+        let received_at = now_ms();
+        match parser::parse(&raw_msg, received_at) {
+            Ok(msgs) => {
+                for msg in msgs.iter() {
+                    if let Err(PublishError::Redis(err)) = publish(&mut publisher, msg) {
+                        error!("{}", err);
+                    }
+                }
+            }
+            Err(err) => {
+                let mut letter = DeadLetter::new(&err, raw_msg.json, received_at);
+                letter.exchange = Some(raw_msg.exchange);
+                letter.market_type = Some(raw_msg.market_type);
+                letter.msg_type = Some(raw_msg.msg_type);
+                dead_letters.push(&letter);
+            }
+        }
+    }
+}
+
+fn get_env_usize(name: &str, default: usize) -> usize {
+    if let Ok(text) = std::env::var(name) {
+        text.parse::<usize>()
+            .unwrap_or_else(|_| panic!("Invalid {} {}", name, text))
+    } else {
+        info!(
+            "The {} environment variable is empty, using {} by default",
+            name, default
+        );
+        default
+    }
+}
+
+fn main() {
+    env_logger::init();
+    let redis_url = if std::env::var("REDIS_URL").is_err() {
+        info!(
+            "The REDIS_URL environment variable is empty, using redis://localhost:6379 by default"
+        );
+        "redis://localhost:6379"
+    } else {
+        let url = std::env::var("REDIS_URL").unwrap();
+        Box::leak(url.into_boxed_str())
+    };
+    wait_redis(redis_url);
+
+    // carbonbot publishes to Redis pub/sub only, while parsed messages go to the configured bus
+    let mut subscriber = RedisPubSub::new(redis_url).subscribe(&[
+        REDIS_TOPIC_TRADE,
+        REDIS_TOPIC_FUNDING_RATE,
+        REDIS_TOPIC_BBO,
+        REDIS_TOPIC_L2_SNAPSHOT,
+        REDIS_TOPIC_L2_TOPK,
+        REDIS_TOPIC_TICKER,
+        REDIS_TOPIC_OPEN_INTEREST,
+    ]);
+    let bus = create_bus(redis_url);
+
+    let num_threads = get_env_usize(
+        "PARSER_THREADS",
+        std::thread::available_parallelism().map_or(1, |n| n.get()),
+    );
+    let queue_size = get_env_usize("PARSER_QUEUE_SIZE", DEFAULT_PARSER_QUEUE_SIZE);
+    let pool = WorkerPool::new("parser", num_threads, queue_size, |_| {
+        create_parser(redis_url, bus.as_ref())
+    });
+
+    let mut dead_letters = DeadLetterQueue::new(redis_url);
+    let mut metrics_conn = {
+        let client = redis::Client::open(redis_url).unwrap();
+        client.get_connection().unwrap()
+    };
+    let mut last_metrics_time = now_ms();
+    loop {
+        // next_message() times out periodically so that metrics are reported even if there is no message
+        if let Some(msg) = subscriber.next_message(Some(METRICS_INTERVAL)) {
+            let payload = msg.payload;
+            match parser::parse_payload(&payload) {
+                Ok(raw_msg) => pool.dispatch(&parser::shard_key(&raw_msg), raw_msg),
+                Err(err) => dead_letters.push(&DeadLetter::new(&err, payload, now_ms())),
+            }
+        }
+
+        let now = now_ms();
+        if now - last_metrics_time >= METRICS_INTERVAL.as_millis() as i64 {
+            let metrics = pool.metrics();
+            let queue_lens = metrics.queue_lens();
+            info!(
+                "dispatched: {}, blocked: {}, queue lengths: {:?}",
+                metrics.dispatched(),
+                metrics.blocked(),
+                queue_lens
+            );
+            let mut fields: Vec<(String, u64)> = vec![
+                ("dispatched".to_string(), metrics.dispatched()),
+                ("blocked".to_string(), metrics.blocked()),
+            ];
+            for (index, len) in queue_lens.into_iter().enumerate() {
+                fields.push((format!("queue_len:{}", index), len as u64));
+            }
+            if let Err(err) =
+                metrics_conn.hset_multiple::<&str, String, u64, ()>(REDIS_KEY_METRICS, &fields)
+            {
+                error!("{}", err);
+            }
+            last_metrics_time = now;
+        }
+    }
+}
@@ -9,13 +9,17 @@ use std::{
     thread::{self, JoinHandle},
 };
 use transform::constants::{REDIS_TOPIC_CURRENCY_PRICE, REDIS_TOPIC_TRADE_PARSED};
-use utils::wait_redis;
+use utils::{
+    pubsub::{create_bus, MessageBus, RedisPubSub},
+    wait_redis,
+};
 
 const BETA: f64 = 0.9; // Vt=βVt-1 + (1-β)
 const REDIS_TOPIC_CURRENCY_PRICE_CHANNEL: &str = "carbonbot:misc:currency_price_channel";
 
 pub struct PriceUpdater {
     redis_url: String,
+    bus: Arc<dyn MessageBus>,
     prices: Arc<Mutex<HashMap<String, f64>>>,
     conn: Arc<Mutex<redis::Connection>>,
 }
@@ -27,12 +31,13 @@ struct CurrencyPrice {
 }
 
 impl PriceUpdater {
-    pub fn new(redis_url: &str) -> Self {
+    pub fn new(redis_url: &str, bus: Arc<dyn MessageBus>) -> Self {
         let client = redis::Client::open(redis_url).unwrap();
         let conn = client.get_connection().unwrap();
 
         PriceUpdater {
             redis_url: redis_url.to_string(),
+            bus,
             prices: Arc::new(Mutex::new(HashMap::new())),
             conn: Arc::new(Mutex::new(conn)),
         }
@@ -56,40 +61,25 @@ impl PriceUpdater {
     fn subscribe_trade(&self) -> JoinHandle<()> {
         let prices_clone = self.prices.clone();
         let conn_clone = self.conn.clone();
-        let redis_url = self.redis_url.to_string();
-        thread::spawn(move || {
-            let client = redis::Client::open(redis_url).unwrap();
-            let mut connection = client.get_connection().unwrap();
-            let mut pubsub = connection.as_pubsub();
-            pubsub.subscribe(REDIS_TOPIC_TRADE_PARSED).unwrap();
-
-            loop {
-                match pubsub.get_message() {
-                    Ok(msg) => {
-                        let payload: String = msg.get_payload().unwrap();
-                        let trade_msg = serde_json::from_str::<TradeMsg>(&payload).unwrap();
-                        match trade_msg.market_type {
-                            MarketType::Spot | MarketType::InverseSwap | MarketType::LinearSwap => {
-                                let v: Vec<&str> = trade_msg.pair.split('/').collect();
-                                let base = v[0];
-                                let quote = v[1];
-                                if quote == "USD"
-                                    || quote == "USDT"
-                                    || quote == "USDC"
-                                    || quote == "BUSD"
-                                {
-                                    Self::update_price(
-                                        base,
-                                        trade_msg.price,
-                                        prices_clone.clone(),
-                                        conn_clone.clone(),
-                                    )
-                                }
-                            }
-                            _ => (),
+        let mut subscriber = self.bus.subscribe(&[REDIS_TOPIC_TRADE_PARSED]);
+        thread::spawn(move || loop {
+            if let Some(msg) = subscriber.next_message(None) {
+                let trade_msg = serde_json::from_str::<TradeMsg>(&msg.payload).unwrap();
+                match trade_msg.market_type {
+                    MarketType::Spot | MarketType::InverseSwap | MarketType::LinearSwap => {
+                        let v: Vec<&str> = trade_msg.pair.split('/').collect();
+                        let base = v[0];
+                        let quote = v[1];
+                        if quote == "USD" || quote == "USDT" || quote == "USDC" || quote == "BUSD" {
+                            Self::update_price(
+                                base,
+                                trade_msg.price,
+                                prices_clone.clone(),
+                                conn_clone.clone(),
+                            )
                         }
                     }
-                    Err(err) => error!("{}", err),
+                    _ => (),
                 }
             }
         })
@@ -98,19 +88,12 @@ impl PriceUpdater {
     fn subscribe_mark_price(&self) -> JoinHandle<()> {
         let prices_clone = self.prices.clone();
         let conn_clone = self.conn.clone();
-        let redis_url = self.redis_url.to_string();
-        thread::spawn(move || {
-            let client = redis::Client::open(redis_url).unwrap();
-            let mut connection = client.get_connection().unwrap();
-            let mut pubsub = connection.as_pubsub();
-            pubsub
-                .subscribe(REDIS_TOPIC_CURRENCY_PRICE_CHANNEL)
-                .unwrap();
-
-            loop {
-                let msg = pubsub.get_message().unwrap();
-                let payload: String = msg.get_payload().unwrap();
-                if let Ok(mark_price) = serde_json::from_str::<CurrencyPrice>(&payload) {
+        // carbonbot publishes to Redis pub/sub only
+        let mut subscriber =
+            RedisPubSub::new(&self.redis_url).subscribe(&[REDIS_TOPIC_CURRENCY_PRICE_CHANNEL]);
+        thread::spawn(move || loop {
+            if let Some(msg) = subscriber.next_message(None) {
+                if let Ok(mark_price) = serde_json::from_str::<CurrencyPrice>(&msg.payload) {
                     Self::update_price(
                         &mark_price.currency,
                         mark_price.price,
@@ -158,6 +141,6 @@ fn main() {
     };
     wait_redis(redis_url);
 
-    let updater = PriceUpdater::new(redis_url);
+    let updater = PriceUpdater::new(redis_url, create_bus(redis_url));
     updater.run();
 }
@@ -0,0 +1,246 @@
+use crypto_market_type::MarketType;
+use crypto_message::{TradeMsg, TradeSide};
+use log::*;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use transform::constants::{REDIS_TOPIC_TRADE_PARSED, REDIS_TOPIC_WHALE_TRADE};
+use transform::price::{usd_price, PriceSource};
+use utils::{
+    pubsub::{create_bus, PublishError, Publisher, Subscriber},
+    wait_redis, PriceCache,
+};
+
+const DEFAULT_WHALE_THRESHOLD_USD: f64 = 1000000.0;
+// The latest whale trades, kept in a list
+const REDIS_KEY_WHALE_TRADES: &str = "coinsignal:whale_trades";
+const MAX_WHALE_TRADES: isize = 1000; // the capacity of the whale trade list
+
+/// A trade whose USD notional is above the threshold of its base asset.
+#[derive(Serialize)]
+struct WhaleTradeMsg {
+    exchange: String,
+    market_type: MarketType,
+    symbol: String,
+    pair: String,
+    base: String,
+    quote: String,
+    timestamp: i64,
+    side: TradeSide,
+    price: f64,
+    quantity_base: f64,
+    quantity_quote: f64,
+    notional_usd: f64,  // quantity_quote converted to USD
+    threshold_usd: f64, // the threshold of base
+    trade_id: String,
+}
+
+// Per-asset thresholds are read from the WHALE_THRESHOLDS environment variable,
+// e.g., WHALE_THRESHOLDS=BTC=5000000,ETH=2000000
+fn get_whale_thresholds() -> HashMap<String, f64> {
+    if let Ok(text) = std::env::var("WHALE_THRESHOLDS") {
+        parse_whale_thresholds(&text).unwrap_or_else(|err| panic!("{}", err))
+    } else {
+        HashMap::new()
+    }
+}
+
+fn parse_whale_thresholds(text: &str) -> Result<HashMap<String, f64>, String> {
+    text.split(',')
+        .filter(|x| !x.trim().is_empty())
+        .map(|x| {
+            x.split_once('=')
+                .and_then(|(base, threshold)| {
+                    threshold
+                        .trim()
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|threshold| threshold.is_finite() && *threshold >= 0.0)
+                        .map(|threshold| (base.trim().to_string(), threshold))
+                })
+                .ok_or_else(|| format!("Invalid whale threshold {}", x))
+        })
+        .collect()
+}
+
+// Returns the trade as a whale trade if its USD notional reaches the threshold of its base asset
+fn detect(
+    trade: &TradeMsg,
+    prices: &dyn PriceSource,
+    thresholds: &HashMap<String, f64>,
+    global_threshold: f64,
+) -> Option<WhaleTradeMsg> {
+    let (base, quote) = if let Some((base, quote)) = trade.pair.split_once('/') {
+        (base, quote)
+    } else {
+        warn!("Invalid pair {}", trade.pair);
+        return None;
+    };
+
+    let quote_price = usd_price(quote, prices)?;
+    let notional_usd = trade.quantity_quote * quote_price;
+    let threshold_usd = thresholds.get(base).copied().unwrap_or(global_threshold);
+    if notional_usd < threshold_usd {
+        return None;
+    }
+
+    Some(WhaleTradeMsg {
+        exchange: trade.exchange.clone(),
+        market_type: trade.market_type,
+        symbol: trade.symbol.clone(),
+        pair: trade.pair.clone(),
+        base: base.to_string(),
+        quote: quote.to_string(),
+        timestamp: trade.timestamp,
+        side: trade.side,
+        price: trade.price,
+        quantity_base: trade.quantity_base,
+        quantity_quote: trade.quantity_quote,
+        notional_usd,
+        threshold_usd,
+        trade_id: trade.trade_id.clone(),
+    })
+}
+
+// Detect large trades
+fn main() {
+    env_logger::init();
+    let redis_url = if std::env::var("REDIS_URL").is_err() {
+        info!(
+            "The REDIS_URL environment variable is empty, using redis://localhost:6379 by default"
+        );
+        "redis://localhost:6379"
+    } else {
+        let url = std::env::var("REDIS_URL").unwrap();
+        Box::leak(url.into_boxed_str())
+    };
+    wait_redis(redis_url);
+    let bus = create_bus(redis_url);
+
+    let price_cache = PriceCache::new(redis_url);
+    price_cache.wait_until_ready();
+
+    // The global threshold is read from the WHALE_THRESHOLD_USD environment variable
+    let global_threshold = std::env::var("WHALE_THRESHOLD_USD")
+        .map(|x| x.parse::<f64>().expect("Invalid WHALE_THRESHOLD_USD"))
+        .unwrap_or(DEFAULT_WHALE_THRESHOLD_USD);
+    let thresholds = get_whale_thresholds();
+
+    let mut publisher = Publisher::new(bus.as_ref());
+    let mut conn = {
+        let client = redis::Client::open(redis_url).unwrap();
+        client.get_connection().unwrap()
+    };
+
+    let mut subscriber = Subscriber::new(
+        bus.as_ref(),
+        REDIS_TOPIC_TRADE_PARSED,
+        Box::new(move |payload: String| {
+            let trade = match serde_json::from_str::<TradeMsg>(&payload) {
+                Ok(trade) => trade,
+                Err(err) => {
+                    warn!("{}, {}", err, payload);
+                    return;
+                }
+            };
+            let msg = match detect(&trade, &price_cache, &thresholds, global_threshold) {
+                Some(msg) => msg,
+                None => return,
+            };
+            if let Err(PublishError::Redis(err)) =
+                publisher.publish::<WhaleTradeMsg>(REDIS_TOPIC_WHALE_TRADE, &msg)
+            {
+                error!("{}", err);
+            }
+            if let Err(err) = redis::pipe()
+                .lpush(REDIS_KEY_WHALE_TRADES, serde_json::to_string(&msg).unwrap())
+                .ltrim(REDIS_KEY_WHALE_TRADES, 0, MAX_WHALE_TRADES - 1)
+                .query::<()>(&mut conn)
+            {
+                error!("{}", err);
+            }
+        }),
+    );
+    subscriber.run();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto_msg_type::MessageType;
+    use transform::price::StaticPrices;
+
+    fn trade(pair: &str, quantity_quote: f64) -> TradeMsg {
+        TradeMsg {
+            exchange: "binance".to_string(),
+            market_type: MarketType::Spot,
+            msg_type: MessageType::Trade,
+            pair: pair.to_string(),
+            symbol: pair.replace('/', ""),
+            timestamp: 1599998400000,
+            side: TradeSide::Buy,
+            price: 1.0,
+            quantity_base: quantity_quote,
+            quantity_quote,
+            quantity_contract: None,
+            trade_id: "1".to_string(),
+            json: String::new(),
+        }
+    }
+
+    #[test]
+    fn parse_thresholds() {
+        let thresholds = parse_whale_thresholds("BTC=5000000, ETH = 2000000,").unwrap();
+        assert_eq!(thresholds.len(), 2);
+        assert_eq!(thresholds["BTC"], 5000000.0);
+        assert_eq!(thresholds["ETH"], 2000000.0);
+        assert!(parse_whale_thresholds("").unwrap().is_empty());
+
+        for text in [
+            "BTC",
+            "BTC=",
+            "BTC=abc",
+            "BTC=-1",
+            "BTC=NaN",
+            "BTC=5000000,ETH",
+        ] {
+            assert_eq!(
+                parse_whale_thresholds(text),
+                Err(format!(
+                    "Invalid whale threshold {}",
+                    text.rsplit(',').next().unwrap()
+                )),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn detect_by_usd_notional() {
+        let mut prices = StaticPrices::default();
+        prices.set_price("BTC", 20000.0);
+        let thresholds = parse_whale_thresholds("BTC=5000000").unwrap();
+        let detect = |trade: &TradeMsg| detect(trade, &prices, &thresholds, 1000000.0);
+
+        // Stable coins are worth 1 USD
+        assert!(detect(&trade("ETH/USDT", 999999.0)).is_none());
+        let msg = detect(&trade("ETH/USDT", 1000000.0)).unwrap();
+        assert_eq!((msg.base.as_str(), msg.quote.as_str()), ("ETH", "USDT"));
+        assert_eq!(msg.notional_usd, 1000000.0);
+        assert_eq!(msg.threshold_usd, 1000000.0);
+
+        // Quantities in BTC are converted to USD, and BTC has its own threshold
+        let msg = detect(&trade("ETH/BTC", 50.0)).unwrap();
+        assert_eq!(msg.notional_usd, 1000000.0);
+        assert!(detect(&trade("BTC/USDT", 4999999.0)).is_none());
+        assert_eq!(
+            detect(&trade("BTC/USDT", 5000000.0)).unwrap().threshold_usd,
+            5000000.0
+        );
+
+        // Quote currencies without a price and invalid pairs are skipped
+        assert!(detect(&trade("ETH/XYZ", 1e12)).is_none());
+        assert!(detect(&trade("ETHUSDT", 1e12)).is_none());
+    }
+}
@@ -0,0 +1,449 @@
+use crypto_market_type::MarketType;
+use crypto_message::{TradeMsg, TradeSide};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+use crate::price::{usd_price, PriceSource};
+use crate::sketch::QuantileSketch;
+
+pub fn extract_quote(pair: &str) -> &str {
+    if pair.find('/').is_none() {
+        warn!("{}", pair);
+    }
+    let slash_pos = pair.find('/').unwrap();
+    &pair[slash_pos + 1..]
+}
+
+/// Whether trades of this quote currency can be converted to USD.
+pub fn is_good(quote: &str, prices: &dyn PriceSource) -> bool {
+    usd_price(quote, prices).is_some()
+}
+
+/// How a bar is sampled, by time or by trading activity.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BarType {
+    #[default]
+    Time, // every bar_size milliseconds
+    Tick,   // every threshold trades
+    Volume, // every threshold base units
+    Dollar, // every threshold USD of volume_usd
+}
+
+impl BarType {
+    pub fn is_time(&self) -> bool {
+        *self == BarType::Time
+    }
+}
+
+impl std::fmt::Display for BarType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            BarType::Time => "time",
+            BarType::Tick => "tick",
+            BarType::Volume => "volume",
+            BarType::Dollar => "dollar",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Candlestick {
+    pub exchange: String,
+    pub market_type: MarketType,
+    pub symbol: String,
+    pub pair: String,
+    #[serde(default, skip_serializing_if = "BarType::is_time")]
+    pub bar_type: BarType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f64>, // only for tick, volume and dollar bars
+    pub bar_size: i64,        // in millisecond, 0 for tick, volume and dollar bars
+    pub timestamp: i64,       // bar end time, in millisecond
+    pub timestamp_start: i64, // timestamp of the fist trade
+    pub timestamp_end: i64,   // timestamp of the last trade
+
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+
+    pub mean: f64,   // mean trade price
+    pub median: f64, // median trade price
+
+    pub volume: f64,      // base volume
+    pub volume_sell: f64, // base volume at sell side
+    pub volume_buy: f64,  // base volume at buy side
+
+    pub volume_quote: f64,      // quote volume
+    pub volume_quote_sell: f64, // quote volume at sell side
+    pub volume_quote_buy: f64,  // quote volume at buy side
+
+    pub volume_usd: f64,      // volume converted to USD
+    pub volume_usd_sell: f64, // volume_usd at sell side
+    pub volume_usd_buy: f64,  // volume_usd at buy side
+
+    pub volume_btc: f64,      // volume converted to BTC
+    pub volume_btc_sell: f64, // volume_btc at sell side
+    pub volume_btc_buy: f64,  // volume_btc at buy side
+
+    pub vwap: f64,     // volume weighted average price in quote currency
+    pub vwap_usd: f64, // volume weighted average price in USD
+    pub vwap_btc: f64, // volume weighted average price in BTC
+
+    pub count: i64,      // number of trades
+    pub count_sell: i64, // number of sell trades
+    pub count_buy: i64,  // number of buy trades
+
+    trade_size_p50: f64,     // 50th percentile of trade sizes in quote currency
+    trade_size_p90: f64,     // 90th percentile of trade sizes in quote currency
+    trade_size_p99: f64,     // 99th percentile of trade sizes in quote currency
+    pub trade_size_max: f64, // max trade size in quote currency
+
+    #[serde(skip_serializing, default)]
+    pub dedup: HashSet<u64>,
+    #[serde(skip_serializing, default)]
+    pub price_sketch: QuantileSketch, // trade prices
+    #[serde(skip_serializing, default)]
+    pub size_sketch: QuantileSketch, // trade sizes in quote currency
+}
+
+impl Candlestick {
+    pub fn new(
+        exchange: String,
+        market_type: MarketType,
+        symbol: String,
+        pair: String,
+        bar_size: i64,  // in second, BTC, ETH, USD, etc.
+        timestamp: i64, // bar end time
+    ) -> Self {
+        Candlestick {
+            exchange,
+            market_type,
+            symbol,
+            pair,
+            bar_type: BarType::Time,
+            threshold: None,
+            bar_size,
+            timestamp,
+            timestamp_start: 0,
+            timestamp_end: 0,
+
+            open: 0.0,
+            high: 0.0,
+            low: 0.0,
+            close: 0.0,
+
+            mean: 0.0,
+            median: 0.0,
+
+            volume: 0.0,
+            volume_sell: 0.0,
+            volume_buy: 0.0,
+            volume_quote: 0.0,
+            volume_quote_sell: 0.0,
+            volume_quote_buy: 0.0,
+            volume_usd: 0.0,
+            volume_usd_sell: 0.0,
+            volume_usd_buy: 0.0,
+            volume_btc: 0.0,
+            volume_btc_sell: 0.0,
+            volume_btc_buy: 0.0,
+
+            vwap: 0.0,
+            vwap_usd: 0.0,
+            vwap_btc: 0.0,
+
+            count: 0,
+            count_sell: 0,
+            count_buy: 0,
+
+            trade_size_p50: 0.0,
+            trade_size_p90: 0.0,
+            trade_size_p99: 0.0,
+            trade_size_max: 0.0,
+
+            dedup: HashSet::new(),
+            price_sketch: QuantileSketch::default(),
+            size_sketch: QuantileSketch::default(),
+        }
+    }
+
+    /// Create a tick, volume or dollar bar, which is closed once its progress reaches threshold.
+    pub fn new_info_bar(
+        exchange: String,
+        market_type: MarketType,
+        symbol: String,
+        pair: String,
+        bar_type: BarType,
+        threshold: f64,
+    ) -> Self {
+        assert!(bar_type != BarType::Time);
+        let mut candlestick = Self::new(exchange, market_type, symbol, pair, 0, 0);
+        candlestick.bar_type = bar_type;
+        candlestick.threshold = Some(threshold);
+        candlestick
+    }
+
+    /// Append a trade, prices are used to calculate volume_usd and volume_btc.
+    pub fn append(&mut self, trade: &TradeMsg, prices: &dyn PriceSource) -> bool {
+        if trade.exchange != self.exchange {
+            warn!(
+                "The trade's exchange {} is not equal to candlestick exchange {}",
+                trade.exchange, self.exchange
+            );
+            return false;
+        }
+        if trade.market_type != self.market_type {
+            warn!(
+                "The trade's market_type {} is not equal to candlestick market_type {}",
+                trade.market_type, self.market_type
+            );
+            return false;
+        }
+        if trade.symbol != self.symbol {
+            warn!(
+                "The trade's symbol {} is not equal to candlestick symbol {}",
+                trade.symbol, self.symbol
+            );
+            return false;
+        }
+        if trade.pair != self.pair {
+            warn!(
+                "The trade's pair {} is not equal to candlestick pair {}",
+                trade.pair, self.pair
+            );
+            return false;
+        }
+        // Only time bars have a fixed time window
+        if self.bar_type == BarType::Time {
+            if trade.timestamp >= self.timestamp {
+                warn!(
+                    "The trade's timestamp {} is greater or equal than candlestick end timestamp {}",
+                    trade.timestamp, self.timestamp
+                );
+                return false;
+            } else if trade.timestamp < (self.timestamp - self.bar_size) {
+                warn!(
+                    "The trade's timestamp {} is less than candlestick's begin timestamp {}",
+                    trade.timestamp,
+                    self.timestamp - self.bar_size
+                );
+                return false;
+            }
+        }
+
+        let trade_hash = Self::calc_trade_hash(trade);
+        if self.dedup.contains(&trade_hash) {
+            warn!(
+                "Found duplicated trade {} ",
+                serde_json::to_string(trade).unwrap()
+            );
+            return false;
+        }
+
+        let quote = extract_quote(&trade.pair);
+        let quote_price = if let Some(price) = usd_price(quote, prices) {
+            price
+        } else {
+            warn!(
+                "The trade's quote symbol {} is neither stable coin nor in the price source",
+                quote
+            );
+            return false;
+        };
+
+        let btc_price = prices
+            .get_price("BTC")
+            .expect("BTC should always exist in the price source");
+
+        if self.count == 0 {
+            self.timestamp_start = trade.timestamp;
+            self.timestamp_end = trade.timestamp;
+
+            self.open = trade.price;
+            self.high = trade.price;
+            self.low = trade.price;
+            self.close = trade.price;
+        } else {
+            if self.timestamp_start > trade.timestamp {
+                self.timestamp_start = trade.timestamp;
+                self.open = trade.price;
+            }
+
+            if self.timestamp_end < trade.timestamp {
+                self.timestamp_end = trade.timestamp;
+                self.close = trade.price;
+            }
+
+            if self.high < trade.price {
+                self.high = trade.price;
+            }
+            if self.low > trade.price {
+                self.low = trade.price;
+            }
+        }
+
+        let volume_delta = trade.quantity_base;
+        let volume_quote_delta = trade.quantity_quote;
+        let volume_usd_delta = volume_quote_delta * quote_price;
+        let volume_btc_delta = volume_quote_delta * quote_price / btc_price;
+
+        self.volume += volume_delta;
+        self.volume_quote += volume_quote_delta;
+        self.volume_usd += volume_usd_delta;
+        self.volume_btc += volume_btc_delta;
+
+        self.count += 1;
+        if trade.side == TradeSide::Sell {
+            self.volume_sell += volume_delta;
+            self.volume_quote_sell += volume_quote_delta;
+            self.volume_usd_sell += volume_usd_delta;
+            self.volume_btc_sell += volume_btc_delta;
+
+            self.count_sell += 1;
+        } else {
+            self.volume_buy += volume_delta;
+            self.volume_quote_buy += volume_quote_delta;
+            self.volume_usd_buy += volume_usd_delta;
+            self.volume_btc_buy += volume_btc_delta;
+            self.count_buy += 1;
+        }
+        self.dedup.insert(trade_hash);
+        self.price_sketch.insert(trade.price);
+        self.size_sketch.insert(trade.quantity_quote);
+
+        true
+    }
+
+    pub fn key(&self) -> String {
+        format!(
+            "{}-{}-{}-{}-{}",
+            self.exchange, self.market_type, self.pair, self.symbol, self.timestamp
+        )
+    }
+
+    // Merge a finalized lower-resolution candlestick into this one
+    pub fn merge(&mut self, other: &Candlestick) -> bool {
+        if other.exchange != self.exchange
+            || other.market_type != self.market_type
+            || other.symbol != self.symbol
+            || other.pair != self.pair
+        {
+            warn!(
+                "Can not merge candlestick {}-{}-{}-{} into {}-{}-{}-{}",
+                other.exchange,
+                other.market_type,
+                other.pair,
+                other.symbol,
+                self.exchange,
+                self.market_type,
+                self.pair,
+                self.symbol
+            );
+            return false;
+        }
+        if other.bar_size > self.bar_size
+            || other.timestamp > self.timestamp
+            || other.timestamp - other.bar_size < self.timestamp - self.bar_size
+        {
+            warn!(
+                "The candlestick [{}, {}) is out of range [{}, {})",
+                other.timestamp - other.bar_size,
+                other.timestamp,
+                self.timestamp - self.bar_size,
+                self.timestamp
+            );
+            return false;
+        }
+        if other.count == 0 {
+            return true;
+        }
+
+        if self.count == 0 {
+            self.timestamp_start = other.timestamp_start;
+            self.timestamp_end = other.timestamp_end;
+
+            self.open = other.open;
+            self.high = other.high;
+            self.low = other.low;
+            self.close = other.close;
+        } else {
+            if self.timestamp_start > other.timestamp_start {
+                self.timestamp_start = other.timestamp_start;
+                self.open = other.open;
+            }
+
+            if self.timestamp_end < other.timestamp_end {
+                self.timestamp_end = other.timestamp_end;
+                self.close = other.close;
+            }
+
+            if self.high < other.high {
+                self.high = other.high;
+            }
+            if self.low > other.low {
+                self.low = other.low;
+            }
+        }
+
+        self.volume += other.volume;
+        self.volume_sell += other.volume_sell;
+        self.volume_buy += other.volume_buy;
+        self.volume_quote += other.volume_quote;
+        self.volume_quote_sell += other.volume_quote_sell;
+        self.volume_quote_buy += other.volume_quote_buy;
+        self.volume_usd += other.volume_usd;
+        self.volume_usd_sell += other.volume_usd_sell;
+        self.volume_usd_buy += other.volume_usd_buy;
+        self.volume_btc += other.volume_btc;
+        self.volume_btc_sell += other.volume_btc_sell;
+        self.volume_btc_buy += other.volume_btc_buy;
+
+        self.count += other.count;
+        self.count_sell += other.count_sell;
+        self.count_buy += other.count_buy;
+
+        self.price_sketch.merge(&other.price_sketch);
+        self.size_sketch.merge(&other.size_sketch);
+
+        true
+    }
+
+    pub fn finalize(&mut self) {
+        self.vwap = self.volume_quote / self.volume;
+        self.vwap_usd = self.volume_usd / self.volume;
+        self.vwap_btc = self.volume_btc / self.volume;
+
+        self.mean = self.price_sketch.mean().unwrap_or(0.0);
+        self.median = self.price_sketch.quantile(0.5).unwrap_or(0.0);
+
+        self.trade_size_p50 = self.size_sketch.quantile(0.5).unwrap_or(0.0);
+        self.trade_size_p90 = self.size_sketch.quantile(0.9).unwrap_or(0.0);
+        self.trade_size_p99 = self.size_sketch.quantile(0.99).unwrap_or(0.0);
+        self.trade_size_max = self.size_sketch.max().unwrap_or(0.0);
+    }
+
+    fn calc_trade_hash(trade: &TradeMsg) -> u64 {
+        let mut s = DefaultHasher::new();
+
+        trade.timestamp.hash(&mut s);
+        trade.trade_id.hash(&mut s);
+        trade.price.to_string().hash(&mut s);
+        trade.quantity_base.to_string().hash(&mut s);
+        trade.quantity_quote.to_string().hash(&mut s);
+        trade.side.to_string().hash(&mut s);
+
+        s.finish()
+    }
+}
+
+pub fn candlestick_key(trade: &TradeMsg, bar_time: i64) -> String {
+    format!(
+        "{}-{}-{}-{}-{}",
+        trade.exchange, trade.market_type, trade.pair, trade.symbol, bar_time
+    )
+}
@@ -6,3 +6,27 @@ pub const REDIS_TOPIC_CANDLESTICK: &str = "coinsignal:candlestick";
 pub const REDIS_TOPIC_CANDLESTICK_EXT: &str = "coinsignal:candlestick_ext";
 pub const REDIS_TOPIC_TRADE_PARSED: &str = "coinsignal:trade";
 pub const REDIS_TOPIC_FUNDING_RATE_PARSED: &str = "coinsignal:funding_rate";
+pub const REDIS_TOPIC_CANDLESTICK_EXT_AMENDED: &str = "coinsignal:candlestick_ext_amended";
+pub const REDIS_TOPIC_CANDLESTICK_INFO: &str = "coinsignal:candlestick_info";
+pub const REDIS_TOPIC_CANDLESTICK_COMPOSITE: &str = "coinsignal:candlestick_composite";
+pub const REDIS_TOPIC_INDICATOR: &str = "coinsignal:indicator";
+pub const REDIS_TOPIC_BASIS: &str = "coinsignal:basis";
+pub const REDIS_TOPIC_FUNDING_RATE_SNAPSHOT: &str = "coinsignal:funding_rate_snapshot";
+pub const REDIS_TOPIC_ORDER_FLOW: &str = "coinsignal:order_flow";
+pub const REDIS_TOPIC_ORDER_FLOW_COMPOSITE: &str = "coinsignal:order_flow_composite";
+pub const REDIS_TOPIC_VOLATILITY: &str = "coinsignal:volatility";
+pub const REDIS_TOPIC_CORRELATION: &str = "coinsignal:correlation";
+pub const REDIS_TOPIC_ANOMALY: &str = "coinsignal:anomaly";
+pub const REDIS_TOPIC_WHALE_TRADE: &str = "coinsignal:whale_trade";
+pub const REDIS_TOPIC_BBO_PARSED: &str = "coinsignal:bbo";
+pub const REDIS_TOPIC_L2_SNAPSHOT_PARSED: &str = "coinsignal:l2_snapshot";
+pub const REDIS_TOPIC_TICKER_PARSED: &str = "coinsignal:ticker";
+pub const REDIS_TOPIC_OPEN_INTEREST_PARSED: &str = "coinsignal:open_interest";
+pub const REDIS_TOPIC_BOOK_STATS: &str = "coinsignal:book_stats";
+
+// https://coinmarketcap.com/view/stablecoin/
+// https://www.stablecoinswar.com/
+pub const STABLE_COINS: [&str; 22] = [
+    "USD", "USDT", "USDC", "BUSD", "DAI", "UST", "TUSD", "USDP", "USDN", "HUSD", "FEI", "LUSD",
+    "FRAX", "SUSD", "USDX", "GUSD", "CUSD", "MUSD", "USDK", "OUSD", "MIM", "PAX",
+];
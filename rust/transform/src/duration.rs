@@ -0,0 +1,69 @@
+/// Parse a duration such as 30s, 1m, 4h or 1d into milliseconds.
+pub fn parse_duration(text: &str) -> Option<i64> {
+    let text = text.trim();
+    // The unit is the last character, which may be multi-byte in invalid input
+    let (index, _) = text.char_indices().last()?;
+    let (num, unit) = text.split_at(index);
+    let num = num.parse::<i64>().ok().filter(|n| *n >= 0)?;
+    let unit_ms = match unit {
+        "s" => 1000,
+        "m" => 60000,
+        "h" => 3600000,
+        "d" => 86400000,
+        _ => return None,
+    };
+    Some(num * unit_ms)
+}
+
+/// Parse a comma-separated list of bar sizes such as 1m,5m,1h into milliseconds,
+/// in ascending order without duplicates.
+pub fn parse_bar_sizes(text: &str) -> Result<Vec<i64>, String> {
+    let mut bar_sizes = Vec::new();
+    for item in text.split(',').filter(|x| !x.trim().is_empty()) {
+        let bar_size = parse_duration(item)
+            .filter(|bar_size| *bar_size > 0)
+            .ok_or_else(|| format!("Invalid bar size {}", item))?;
+        bar_sizes.push(bar_size);
+    }
+    bar_sizes.sort_unstable();
+    bar_sizes.dedup();
+    if bar_sizes.is_empty() {
+        return Err("Bar sizes should not be empty".to_string());
+    }
+    Ok(bar_sizes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_durations() {
+        assert_eq!(parse_duration("30s"), Some(30000));
+        assert_eq!(parse_duration(" 5m "), Some(300000));
+        assert_eq!(parse_duration("4h"), Some(14400000));
+        assert_eq!(parse_duration("1d"), Some(86400000));
+        assert_eq!(parse_duration("0s"), Some(0));
+        assert_eq!(parse_duration("5"), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("-1m"), None);
+        assert_eq!(parse_duration("1w"), None);
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("5µ"), None);
+        assert_eq!(parse_duration("µ"), None);
+    }
+
+    #[test]
+    fn parse_bar_size_lists() {
+        assert_eq!(
+            parse_bar_sizes("1h,1m, 5m,1m,"),
+            Ok(vec![60000, 300000, 3600000])
+        );
+        assert_eq!(
+            parse_bar_sizes("0s"),
+            Err("Invalid bar size 0s".to_string())
+        );
+        assert!(parse_bar_sizes("5m,x").is_err());
+        assert!(parse_bar_sizes(" ,").is_err());
+    }
+}
@@ -1 +1,8 @@
+pub mod candlestick;
 pub mod constants;
+pub mod duration;
+pub mod parser;
+pub mod price;
+pub mod rollup;
+pub mod sketch;
+pub mod worker_pool;
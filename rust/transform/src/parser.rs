@@ -0,0 +1,368 @@
+use crypto_market_type::MarketType;
+use crypto_message::{BboMsg, FundingRateMsg, OrderBookMsg, TradeMsg};
+use crypto_msg_type::MessageType;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+/// Errors while parsing messages from carbonbot.
+#[derive(Debug)]
+pub enum ParseError {
+    /// The payload is not a valid `Message`
+    InvalidPayload(String),
+    /// The message type is not supported by msg_parser
+    UnknownMessageType(MessageType),
+    /// crypto_msg_parser failed to parse the message
+    ParseFailed(String),
+    /// The symbol, pair or timestamp can't be extracted
+    NormalizeFailed(String),
+    /// crypto_msg_parser panicked
+    Panicked(String),
+}
+
+impl ParseError {
+    /// The kind of this error, used as the key of error counters.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ParseError::InvalidPayload(_) => "invalid_payload",
+            ParseError::UnknownMessageType(_) => "unknown_message_type",
+            ParseError::ParseFailed(_) => "parse_failed",
+            ParseError::NormalizeFailed(_) => "normalize_failed",
+            ParseError::Panicked(_) => "panicked",
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::InvalidPayload(reason) => write!(f, "invalid payload: {}", reason),
+            ParseError::UnknownMessageType(msg_type) => {
+                write!(f, "unknown message type {}", msg_type)
+            }
+            ParseError::ParseFailed(reason) => write!(f, "failed to parse: {}", reason),
+            ParseError::NormalizeFailed(reason) => write!(f, "failed to normalize: {}", reason),
+            ParseError::Panicked(reason) => write!(f, "parser panicked: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Message represents messages received by crawlers.
+#[derive(Serialize, Deserialize)]
+pub struct Message {
+    /// The exchange name, unique for each exchage
+    pub exchange: String,
+    /// Market type
+    pub market_type: MarketType,
+    /// Message type
+    pub msg_type: MessageType,
+    /// Unix timestamp in milliseconds
+    pub received_at: u64,
+    /// the original message
+    pub json: String,
+}
+
+/// Messages that crypto_msg_parser doesn't parse yet, such as tickers and
+/// open interest, only symbol, pair and timestamp are normalized.
+///
+/// Their fields, e.g., the last price of a ticker or the value of open interest,
+/// are left in the exchange-specific `json`, consumers have to parse them.
+#[derive(Serialize, Deserialize)]
+pub struct NormalizedMsg {
+    /// The exchange name, unique for each exchage
+    pub exchange: String,
+    /// Market type
+    pub market_type: MarketType,
+    /// Exchange-specific trading symbol or id, recognized by RESTful API
+    pub symbol: String,
+    /// Unified pair, base/quote, e.g., BTC/USDT
+    pub pair: String,
+    /// Message type
+    pub msg_type: MessageType,
+    /// Unix timestamp in milliseconds
+    pub timestamp: i64,
+    /// the original message
+    pub json: String,
+}
+
+/// A parsed message, ready to be published.
+pub enum ParsedMsg {
+    Trade(TradeMsg),
+    FundingRate(FundingRateMsg),
+    Bbo(BboMsg),
+    L2Snapshot(OrderBookMsg),
+    Ticker(NormalizedMsg),
+    OpenInterest(NormalizedMsg),
+}
+
+fn normalize(raw_msg: &Message) -> Result<NormalizedMsg, ParseError> {
+    let symbol =
+        crypto_msg_parser::extract_symbol(&raw_msg.exchange, raw_msg.market_type, &raw_msg.json)
+            .map_err(|err| ParseError::NormalizeFailed(err.to_string()))?;
+    let pair = crypto_pair::normalize_pair(&symbol, &raw_msg.exchange)
+        .ok_or_else(|| ParseError::NormalizeFailed(format!("unknown symbol {}", symbol)))?;
+    let timestamp =
+        crypto_msg_parser::extract_timestamp(&raw_msg.exchange, raw_msg.market_type, &raw_msg.json)
+            .map_err(|err| ParseError::NormalizeFailed(err.to_string()))?
+            .unwrap_or(raw_msg.received_at as i64);
+    Ok(NormalizedMsg {
+        exchange: raw_msg.exchange.clone(),
+        market_type: raw_msg.market_type,
+        symbol,
+        pair,
+        msg_type: raw_msg.msg_type,
+        timestamp,
+        json: raw_msg.json.clone(),
+    })
+}
+
+fn parse_failed<E: ToString>(err: E) -> ParseError {
+    ParseError::ParseFailed(err.to_string())
+}
+
+fn parse_unchecked(raw_msg: &Message, received_at: i64) -> Result<Vec<ParsedMsg>, ParseError> {
+    let exchange = raw_msg.exchange.as_str();
+    let market_type = raw_msg.market_type;
+    let json = raw_msg.json.as_str();
+    match raw_msg.msg_type {
+        MessageType::Trade => match crypto_msg_parser::parse_trade(exchange, market_type, json) {
+            Ok(trades) => Ok(trades.into_iter().map(ParsedMsg::Trade).collect()),
+            // bitmex has index such as .XTZBON, .XBT, etc.
+            Err(_) if exchange == "bitmex" => Ok(Vec::new()),
+            Err(err) => Err(parse_failed(err)),
+        },
+        MessageType::FundingRate => {
+            crypto_msg_parser::parse_funding_rate(exchange, market_type, json, Some(received_at))
+                .map(|rates| rates.into_iter().map(ParsedMsg::FundingRate).collect())
+                .map_err(parse_failed)
+        }
+        MessageType::BBO => {
+            crypto_msg_parser::parse_bbo(exchange, market_type, json, Some(received_at))
+                .map(|bbo_msgs| bbo_msgs.into_iter().map(ParsedMsg::Bbo).collect())
+                .map_err(parse_failed)
+        }
+        // Both are snapshots, the former is from RESTful API, the latter is from websocket
+        MessageType::L2Snapshot => {
+            crypto_msg_parser::parse_l2(exchange, market_type, json, Some(received_at))
+                .map(|orderbooks| orderbooks.into_iter().map(ParsedMsg::L2Snapshot).collect())
+                .map_err(parse_failed)
+        }
+        MessageType::L2TopK => {
+            crypto_msg_parser::parse_l2_topk(exchange, market_type, json, Some(received_at))
+                .map(|orderbooks| orderbooks.into_iter().map(ParsedMsg::L2Snapshot).collect())
+                .map_err(parse_failed)
+        }
+        // Not supported by crypto_msg_parser yet, the original json is republished as is
+        MessageType::Ticker => normalize(raw_msg).map(|msg| vec![ParsedMsg::Ticker(msg)]),
+        MessageType::OpenInterest => {
+            normalize(raw_msg).map(|msg| vec![ParsedMsg::OpenInterest(msg)])
+        }
+        msg_type => Err(ParseError::UnknownMessageType(msg_type)),
+    }
+}
+
+/// Deserialize a message published by carbonbot.
+pub fn parse_payload(payload: &str) -> Result<Message, ParseError> {
+    serde_json::from_str::<Message>(payload)
+        .map_err(|err| ParseError::InvalidPayload(err.to_string()))
+}
+
+/// Parse a message from carbonbot, panics inside crypto_msg_parser are
+/// converted to errors.
+pub fn parse(raw_msg: &Message, received_at: i64) -> Result<Vec<ParsedMsg>, ParseError> {
+    catch_panic(|| parse_unchecked(raw_msg, received_at))
+}
+
+// Runs f, a panic is converted to ParseError::Panicked
+fn catch_panic<T>(f: impl FnOnce() -> Result<T, ParseError>) -> Result<T, ParseError> {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|panic| {
+        let reason = if let Some(reason) = panic.downcast_ref::<&str>() {
+            reason.to_string()
+        } else if let Some(reason) = panic.downcast_ref::<String>() {
+            reason.clone()
+        } else {
+            "unknown".to_string()
+        };
+        Err(ParseError::Panicked(reason))
+    })
+}
+
+/// The key to shard messages by, messages of the same symbol always have the
+/// same key so that their order is preserved.
+pub fn shard_key(raw_msg: &Message) -> String {
+    let symbol = catch_unwind(AssertUnwindSafe(|| {
+        crypto_msg_parser::extract_symbol(&raw_msg.exchange, raw_msg.market_type, &raw_msg.json)
+    }))
+    .ok()
+    .and_then(|result| result.ok())
+    .unwrap_or_default();
+    format!("{}-{}-{}", raw_msg.exchange, raw_msg.market_type, symbol)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(
+        exchange: &str,
+        market_type: MarketType,
+        msg_type: MessageType,
+        json: &str,
+    ) -> Message {
+        Message {
+            exchange: exchange.to_string(),
+            market_type,
+            msg_type,
+            received_at: 1654400000000,
+            json: json.to_string(),
+        }
+    }
+
+    #[test]
+    fn invalid_payload() {
+        let err = parse_payload("not json").err().unwrap();
+        assert!(matches!(err, ParseError::InvalidPayload(_)));
+        assert_eq!(err.kind(), "invalid_payload");
+        assert!(err.to_string().starts_with("invalid payload: "));
+
+        let payload = r#"{"exchange":"binance","market_type":"spot","msg_type":"trade","received_at":1654400000000,"json":"{}"}"#;
+        let raw_msg = parse_payload(payload).unwrap();
+        assert_eq!(raw_msg.exchange, "binance");
+        assert_eq!(raw_msg.msg_type, MessageType::Trade);
+    }
+
+    #[test]
+    fn unknown_message_type() {
+        let raw_msg = message("binance", MarketType::Spot, MessageType::L2Event, "{}");
+        let err = parse(&raw_msg, 1654400000000).err().unwrap();
+        assert!(matches!(
+            err,
+            ParseError::UnknownMessageType(MessageType::L2Event)
+        ));
+        assert_eq!(err.kind(), "unknown_message_type");
+    }
+
+    #[test]
+    fn parse_failed() {
+        let raw_msg = message("binance", MarketType::Spot, MessageType::Trade, "{}");
+        let err = parse(&raw_msg, 1654400000000).err().unwrap();
+        assert!(matches!(err, ParseError::ParseFailed(_)), "{}", err);
+        assert_eq!(err.kind(), "parse_failed");
+
+        // Tickers of unknown symbols can't be normalized
+        let raw_msg = message("binance", MarketType::Spot, MessageType::Ticker, "{}");
+        let err = parse(&raw_msg, 1654400000000).err().unwrap();
+        assert!(matches!(err, ParseError::NormalizeFailed(_)), "{}", err);
+        assert_eq!(err.kind(), "normalize_failed");
+    }
+
+    #[test]
+    fn panic_to_error() {
+        let err = catch_panic::<()>(|| panic!("index out of bounds"))
+            .err()
+            .unwrap();
+        assert!(matches!(&err, ParseError::Panicked(reason) if reason == "index out of bounds"));
+        assert_eq!(err.kind(), "panicked");
+        let err = catch_panic::<()>(|| panic!("{} is not a number", "abc"))
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "parser panicked: abc is not a number");
+
+        assert!(catch_panic(|| Ok(1)).is_ok());
+    }
+
+    #[test]
+    fn parse_bbo() {
+        let raw_msg = message(
+            "binance",
+            MarketType::Spot,
+            MessageType::BBO,
+            r#"{"stream":"!bookTicker","data":{"u":19575390521,"s":"BTCUSDT","b":"29010.90000000","B":"13.94302000","a":"29010.91000000","A":"3.99953000"}}"#,
+        );
+        let msgs = parse(&raw_msg, 1654400000000).unwrap();
+        assert_eq!(msgs.len(), 1);
+        match &msgs[0] {
+            ParsedMsg::Bbo(msg) => {
+                assert_eq!(msg.pair, "BTC/USDT");
+                assert_eq!(msg.bid_price, 29010.9);
+                assert_eq!(msg.ask_price, 29010.91);
+                // The message has no timestamp, so the time it was received is used
+                assert_eq!(msg.timestamp, 1654400000000);
+            }
+            _ => panic!("not a BBO"),
+        }
+    }
+
+    #[test]
+    fn parse_l2_snapshot_and_topk() {
+        let raw_msg = message(
+            "bitget",
+            MarketType::LinearSwap,
+            MessageType::L2Snapshot,
+            r#"{"action":"partial","data":[{"asks":[["34589.0","507"],["34589.5","958"]],"bids":[["34588.0","1199"],["34587.0","1339"]],"instrument_id":"cmt_btcusdt","timestamp":"1622432420458"}],"table":"swap/depth"}"#,
+        );
+        let msgs = parse(&raw_msg, 1654400000000).unwrap();
+        assert_eq!(msgs.len(), 1);
+        match &msgs[0] {
+            ParsedMsg::L2Snapshot(msg) => {
+                assert_eq!(msg.pair, "BTC/USDT");
+                assert_eq!(msg.timestamp, 1622432420458);
+                assert_eq!((msg.asks.len(), msg.bids.len()), (2, 2));
+                assert_eq!(msg.bids[0].price, 34588.0);
+            }
+            _ => panic!("not an L2 snapshot"),
+        }
+
+        // Top-k snapshots are published as L2 snapshots too
+        let raw_msg = message(
+            "binance",
+            MarketType::Spot,
+            MessageType::L2TopK,
+            r#"{"stream":"ethusdt@depth20","data":{"lastUpdateId":17044571457,"bids":[["1782.00000000","6.48300000"],["1781.95000000","0.03000000"]],"asks":[["1782.01000000","15.46080000"],["1782.02000000","0.00780000"]]}}"#,
+        );
+        let msgs = parse(&raw_msg, 1654400000000).unwrap();
+        match &msgs[..] {
+            [ParsedMsg::L2Snapshot(msg)] => {
+                assert_eq!(msg.pair, "ETH/USDT");
+                assert_eq!(msg.asks[0].price, 1782.01);
+            }
+            _ => panic!("not an L2 snapshot"),
+        }
+    }
+
+    #[test]
+    fn normalize_ticker_and_open_interest() {
+        let json = r#"{"stream":"ethusdt@ticker","data":{"e":"24hrTicker","E":1653812650349,"s":"ETHUSDT","p":"28.23000000","P":"1.600","w":"1781.16275609","x":"1764.61000000","c":"1792.84000000","Q":"0.55720000","b":"1792.83000000","B":"1.62740000","a":"1792.84000000","A":"20.29140000","o":"1764.61000000","h":"1808.98000000","l":"1748.94000000","v":"471703.53110000","q":"840180761.51358100","O":1653726250344,"C":1653812650344,"F":841094172,"L":841646650,"n":552479}}"#;
+        let raw_msg = message("binance", MarketType::Spot, MessageType::Ticker, json);
+        match &parse(&raw_msg, 1654400000000).unwrap()[..] {
+            [ParsedMsg::Ticker(msg)] => {
+                assert_eq!(msg.symbol, "ETHUSDT");
+                assert_eq!(msg.pair, "ETH/USDT");
+                assert_eq!(msg.msg_type, MessageType::Ticker);
+                assert_eq!(msg.timestamp, 1653812650349);
+                assert_eq!(msg.json, json);
+            }
+            _ => panic!("not a ticker"),
+        }
+
+        let json = r#"{"symbol":"BTCUSDT_220624","openInterest":"1275.028","time":1654336785074}"#;
+        let raw_msg = message(
+            "binance",
+            MarketType::LinearFuture,
+            MessageType::OpenInterest,
+            json,
+        );
+        match &parse(&raw_msg, 1654400000000).unwrap()[..] {
+            [ParsedMsg::OpenInterest(msg)] => {
+                assert_eq!(msg.symbol, "BTCUSDT_220624");
+                assert_eq!(msg.pair, "BTC/USDT");
+                assert_eq!(msg.market_type, MarketType::LinearFuture);
+                assert_eq!(msg.timestamp, 1654336785074);
+                assert_eq!(msg.json, json);
+            }
+            _ => panic!("not open interest"),
+        }
+    }
+}
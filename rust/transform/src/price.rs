@@ -0,0 +1,55 @@
+use lazy_static::lazy_static;
+use std::collections::{HashMap, HashSet};
+use utils::PriceCache;
+
+use crate::constants::STABLE_COINS;
+
+lazy_static! {
+    static ref STABLE_COINS_SET: HashSet<&'static str> = STABLE_COINS.iter().copied().collect();
+}
+
+/// A source of currency prices in USD.
+pub trait PriceSource {
+    fn get_price(&self, currency: &str) -> Option<f64>;
+}
+
+impl PriceSource for PriceCache {
+    fn get_price(&self, currency: &str) -> Option<f64> {
+        PriceCache::get_price(self, currency)
+    }
+}
+
+/// Fixed prices, e.g., for offline jobs and tests.
+#[derive(Clone, Default)]
+pub struct StaticPrices {
+    prices: HashMap<String, f64>,
+}
+
+impl StaticPrices {
+    pub fn new(prices: HashMap<String, f64>) -> Self {
+        StaticPrices { prices }
+    }
+
+    pub fn set_price(&mut self, currency: &str, price: f64) {
+        self.prices.insert(currency.to_string(), price);
+    }
+}
+
+impl PriceSource for StaticPrices {
+    fn get_price(&self, currency: &str) -> Option<f64> {
+        self.prices.get(currency).copied()
+    }
+}
+
+pub fn is_stable_coin(currency: &str) -> bool {
+    STABLE_COINS_SET.contains(currency)
+}
+
+/// The USD price of a currency, stable coins are always 1.0.
+pub fn usd_price(currency: &str, prices: &dyn PriceSource) -> Option<f64> {
+    if is_stable_coin(currency) {
+        Some(1.0)
+    } else {
+        prices.get_price(currency)
+    }
+}
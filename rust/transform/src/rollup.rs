@@ -0,0 +1,375 @@
+use crypto_message::TradeMsg;
+use log::*;
+use std::collections::HashMap;
+
+use crate::candlestick::{candlestick_key, Candlestick};
+use crate::price::PriceSource;
+
+/// Builds higher timeframe candlesticks by merging finalized lower-resolution ones,
+/// so that only the smallest bar size is aggregated from raw trades.
+pub struct Rollup {
+    base_bar_size: i64,
+    // (bar_size, the bar_size it is rolled up from), in ascending order
+    sources: Vec<(i64, i64)>,
+    // bar_size -> open candlesticks of this bar_size
+    candlesticks: HashMap<i64, HashMap<String, Candlestick>>,
+    // bar_size -> published candlesticks kept for one more base bar so that late trades can amend them
+    published: HashMap<i64, HashMap<String, Candlestick>>,
+}
+
+impl Rollup {
+    /// Every bar size must be a multiple of the smallest one, which is built from raw trades.
+    pub fn new(bar_sizes: &[i64]) -> Self {
+        let mut bar_sizes = bar_sizes.to_vec();
+        bar_sizes.sort_unstable();
+        bar_sizes.dedup();
+        assert!(!bar_sizes.is_empty());
+        let base_bar_size = bar_sizes[0];
+
+        // Each bar size is built from the largest smaller bar size which divides it
+        let mut sources = Vec::new();
+        for (i, &bar_size) in bar_sizes.iter().enumerate().skip(1) {
+            let source = bar_sizes[..i]
+                .iter()
+                .rev()
+                .find(|&&x| bar_size % x == 0)
+                .copied()
+                .unwrap_or_else(|| {
+                    panic!(
+                        "The bar size {} is not a multiple of the base bar size {}",
+                        bar_size, base_bar_size
+                    )
+                });
+            sources.push((bar_size, source));
+        }
+
+        let candlesticks: HashMap<i64, HashMap<String, Candlestick>> = sources
+            .iter()
+            .map(|(bar_size, _)| (*bar_size, HashMap::new()))
+            .collect();
+        let published = candlesticks.clone();
+
+        Rollup {
+            base_bar_size,
+            sources,
+            candlesticks,
+            published,
+        }
+    }
+
+    /// The bar size which should be aggregated from raw trades.
+    pub fn base_bar_size(&self) -> i64 {
+        self.base_bar_size
+    }
+
+    /// Merge a finalized candlestick into all bar sizes rolled up from it.
+    pub fn push(&mut self, bar: &Candlestick) {
+        for &(bar_size, source) in self.sources.iter() {
+            if source != bar.bar_size {
+                continue;
+            }
+            // bar.timestamp is the exclusive end time of the source bar
+            let bar_time = (bar.timestamp - 1) / bar_size * bar_size + bar_size;
+            let key = format!(
+                "{}-{}-{}-{}-{}",
+                bar.exchange, bar.market_type, bar.pair, bar.symbol, bar_time
+            );
+            let candlesticks = self.candlesticks.get_mut(&bar_size).unwrap();
+            let candlestick = candlesticks.entry(key).or_insert_with(|| {
+                Candlestick::new(
+                    bar.exchange.clone(),
+                    bar.market_type,
+                    bar.symbol.clone(),
+                    bar.pair.clone(),
+                    bar_size,
+                    bar_time,
+                )
+            });
+            candlestick.merge(bar);
+        }
+    }
+
+    /// Open candlesticks of all rolled-up bar sizes.
+    pub fn candlesticks(&self) -> impl Iterator<Item = &Candlestick> {
+        self.candlesticks.values().flat_map(|x| x.values())
+    }
+
+    /// Published candlesticks of all rolled-up bar sizes which can still be amended.
+    pub fn published(&self) -> impl Iterator<Item = &Candlestick> {
+        self.published.values().flat_map(|x| x.values())
+    }
+
+    /// Put back an open candlestick restored from a checkpoint.
+    pub fn restore(&mut self, candlestick: Candlestick) {
+        Self::restore_into(&mut self.candlesticks, candlestick);
+    }
+
+    /// Put back a published candlestick restored from a checkpoint.
+    pub fn restore_published(&mut self, candlestick: Candlestick) {
+        Self::restore_into(&mut self.published, candlestick);
+    }
+
+    fn restore_into(
+        candlesticks: &mut HashMap<i64, HashMap<String, Candlestick>>,
+        candlestick: Candlestick,
+    ) {
+        if let Some(candlesticks) = candlesticks.get_mut(&candlestick.bar_size) {
+            candlesticks.insert(candlestick.key(), candlestick);
+        } else {
+            warn!(
+                "Discarded restored candlestick {} of bar size {}",
+                candlestick.key(),
+                candlestick.bar_size
+            );
+        }
+    }
+
+    /// Append a late trade, whose base bar has been published, to the candlesticks covering it,
+    /// returns the amended candlesticks which have been published already.
+    pub fn amend(&mut self, trade: &TradeMsg, prices: &dyn PriceSource) -> Vec<Candlestick> {
+        let mut amended = Vec::new();
+        for &(bar_size, _) in self.sources.iter() {
+            let bar_time = trade.timestamp / bar_size * bar_size + bar_size;
+            let key = candlestick_key(trade, bar_time);
+            if let Some(candlestick) = self.candlesticks.get_mut(&bar_size).unwrap().get_mut(&key) {
+                candlestick.append(trade, prices);
+            } else if let Some(candlestick) =
+                self.published.get_mut(&bar_size).unwrap().get_mut(&key)
+            {
+                if candlestick.append(trade, prices) {
+                    candlestick.finalize();
+                    amended.push(candlestick.clone());
+                }
+            }
+        }
+        amended
+    }
+
+    /// Finalize all candlesticks which end at or before the watermark of their exchange,
+    /// in ascending order of bar size.
+    ///
+    /// Finalized candlesticks are merged into the next higher timeframe before it is checked,
+    /// so one call cascades from the lowest to the highest bar size.
+    pub fn flush<F>(&mut self, watermark: F) -> Vec<Candlestick>
+    where
+        F: Fn(&str) -> i64,
+    {
+        // Discard published candlesticks which can not be amended any more
+        let base_bar_size = self.base_bar_size;
+        for candlesticks in self.published.values_mut() {
+            candlesticks.retain(|_, candlestick| {
+                candlestick.timestamp + base_bar_size > watermark(&candlestick.exchange)
+            });
+        }
+
+        let mut finished = Vec::new();
+        for i in 0..self.sources.len() {
+            let bar_size = self.sources[i].0;
+            let candlesticks = self.candlesticks.get_mut(&bar_size).unwrap();
+            let keys: Vec<String> = candlesticks
+                .iter()
+                .filter(|(_, candlestick)| {
+                    candlestick.timestamp <= watermark(&candlestick.exchange)
+                })
+                .map(|(key, _)| key.clone())
+                .collect();
+            let ended: Vec<Candlestick> = keys
+                .iter()
+                .map(|key| candlesticks.remove(key).unwrap())
+                .collect();
+            for mut candlestick in ended {
+                candlestick.finalize();
+                self.push(&candlestick);
+                self.published
+                    .get_mut(&bar_size)
+                    .unwrap()
+                    .insert(candlestick.key(), candlestick.clone());
+                finished.push(candlestick);
+            }
+        }
+        finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::price::StaticPrices;
+    use crypto_market_type::MarketType;
+    use crypto_message::TradeSide;
+    use crypto_msg_type::MessageType;
+
+    const MINUTE: i64 = 60000;
+    const HOUR: i64 = 60 * MINUTE;
+    const START: i64 = 1599998400000; // 2020-09-13T12:00:00Z, aligned to an hour
+
+    // One trade every 15 seconds within [START, START + 1h)
+    fn trades() -> Vec<TradeMsg> {
+        (0..240)
+            .map(|i| TradeMsg {
+                exchange: "binance".to_string(),
+                market_type: MarketType::Spot,
+                msg_type: MessageType::Trade,
+                pair: "BTC/USDT".to_string(),
+                symbol: "BTCUSDT".to_string(),
+                timestamp: START + i * 15000,
+                side: if i % 3 == 0 {
+                    TradeSide::Sell
+                } else {
+                    TradeSide::Buy
+                },
+                price: 10000.0 + ((i * 37) % 23) as f64 * 0.5,
+                quantity_base: 0.1 + (i % 7) as f64 * 0.3,
+                quantity_quote: (10000.0 + ((i * 37) % 23) as f64 * 0.5)
+                    * (0.1 + (i % 7) as f64 * 0.3),
+                quantity_contract: None,
+                trade_id: i.to_string(),
+                json: String::new(),
+            })
+            .collect()
+    }
+
+    fn prices() -> StaticPrices {
+        let mut prices = StaticPrices::default();
+        prices.set_price("BTC", 10000.0);
+        prices
+    }
+
+    // Build candlesticks of bar_size directly from raw trades
+    fn build(trades: &[TradeMsg], bar_size: i64) -> Vec<Candlestick> {
+        let prices = prices();
+        let mut candlesticks: Vec<Candlestick> = Vec::new();
+        for trade in trades {
+            let bar_time = trade.timestamp / bar_size * bar_size + bar_size;
+            if candlesticks.last().map(|x| x.timestamp) != Some(bar_time) {
+                candlesticks.push(Candlestick::new(
+                    trade.exchange.clone(),
+                    trade.market_type,
+                    trade.symbol.clone(),
+                    trade.pair.clone(),
+                    bar_size,
+                    bar_time,
+                ));
+            }
+            assert!(candlesticks.last_mut().unwrap().append(trade, &prices));
+        }
+        for candlestick in candlesticks.iter_mut() {
+            candlestick.finalize();
+        }
+        candlesticks
+    }
+
+    fn assert_close(x: f64, y: f64) {
+        assert!((x - y).abs() <= 1e-9 * x.abs().max(1.0), "{} != {}", x, y);
+    }
+
+    fn assert_same(x: &Candlestick, y: &Candlestick) {
+        assert_eq!(x.key(), y.key());
+        assert_eq!(x.bar_size, y.bar_size);
+        assert_eq!(x.timestamp_start, y.timestamp_start);
+        assert_eq!(x.timestamp_end, y.timestamp_end);
+        assert_eq!(x.open, y.open);
+        assert_eq!(x.high, y.high);
+        assert_eq!(x.low, y.low);
+        assert_eq!(x.close, y.close);
+        assert_close(x.volume, y.volume);
+        assert_close(x.volume_sell, y.volume_sell);
+        assert_close(x.volume_buy, y.volume_buy);
+        assert_close(x.volume_quote, y.volume_quote);
+        assert_close(x.volume_usd, y.volume_usd);
+        assert_close(x.volume_usd_sell, y.volume_usd_sell);
+        assert_close(x.volume_usd_buy, y.volume_usd_buy);
+        assert_close(x.volume_btc, y.volume_btc);
+        assert_close(x.vwap, y.vwap);
+        assert_eq!(x.count, y.count);
+        assert_eq!(x.count_sell, y.count_sell);
+        assert_eq!(x.count_buy, y.count_buy);
+        assert_close(x.mean, y.mean);
+        assert_eq!(x.median, y.median);
+        assert_eq!(x.trade_size_max, y.trade_size_max);
+        for q in [0.0, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99, 1.0] {
+            assert_eq!(x.price_sketch.quantile(q), y.price_sketch.quantile(q));
+            assert_eq!(x.size_sketch.quantile(q), y.size_sketch.quantile(q));
+        }
+    }
+
+    #[test]
+    fn rolled_up_bar_equals_bar_built_from_trades() {
+        let trades = trades();
+        let bars_5m = build(&trades, 5 * MINUTE);
+        assert_eq!(bars_5m.len(), 12);
+        let bar_1h = build(&trades, HOUR).pop().unwrap();
+
+        let mut rollup = Rollup::new(&[5 * MINUTE, HOUR]);
+        assert_eq!(rollup.base_bar_size(), 5 * MINUTE);
+        for bar in bars_5m.iter() {
+            rollup.push(bar);
+        }
+        // The 1h bar is still open before the watermark reaches its end
+        assert!(rollup.flush(|_| START + HOUR - 1).is_empty());
+        let mut finished = rollup.flush(|_| START + HOUR);
+        assert_eq!(finished.len(), 1);
+        let rolled_up = finished.pop().unwrap();
+        assert_same(&rolled_up, &bar_1h);
+
+        // Merging the twelve 5m bars by hand gives the same bar
+        let mut merged = Candlestick::new(
+            "binance".to_string(),
+            MarketType::Spot,
+            "BTCUSDT".to_string(),
+            "BTC/USDT".to_string(),
+            HOUR,
+            START + HOUR,
+        );
+        for bar in bars_5m.iter() {
+            assert!(merged.merge(bar));
+        }
+        merged.finalize();
+        assert_same(&merged, &bar_1h);
+        assert_eq!(rollup.candlesticks().count(), 0);
+    }
+
+    #[test]
+    fn rollup_cascades_through_intermediate_bar_sizes() {
+        let trades = trades();
+        let mut rollup = Rollup::new(&[HOUR, 5 * MINUTE, 15 * MINUTE]);
+        for bar in build(&trades, 5 * MINUTE) {
+            rollup.push(&bar);
+        }
+        let mut finished = rollup.flush(|_| START + HOUR);
+        // four 15m bars, then the 1h bar rolled up from them
+        assert_eq!(finished.len(), 5);
+        finished[..4].sort_by_key(|x| x.timestamp);
+        for (bar, expected) in finished[..4].iter().zip(build(&trades, 15 * MINUTE).iter()) {
+            assert_same(bar, expected);
+        }
+        assert_same(&finished[4], &build(&trades, HOUR)[0]);
+    }
+
+    #[test]
+    fn late_trade_amends_published_bars() {
+        let all_trades = trades();
+        let (late, trades) = all_trades.split_last().unwrap();
+        let mut rollup = Rollup::new(&[5 * MINUTE, HOUR]);
+        for bar in build(trades, 5 * MINUTE) {
+            rollup.push(&bar);
+        }
+        assert_eq!(rollup.flush(|_| START + HOUR).len(), 1);
+
+        // the late trade belongs to the last 5m bar, which ends with the 1h bar
+        let amended = rollup.amend(late, &prices());
+        assert_eq!(amended.len(), 1);
+        assert_same(&amended[0], &build(&all_trades, HOUR)[0]);
+
+        // published bars can be amended for one more base bar
+        assert_eq!(rollup.flush(|_| START + HOUR + 5 * MINUTE).len(), 0);
+        assert_eq!(rollup.published().count(), 0);
+        assert!(rollup.amend(late, &prices()).is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn bar_sizes_must_be_multiples_of_the_base() {
+        Rollup::new(&[5 * MINUTE, 7 * MINUTE]);
+    }
+}
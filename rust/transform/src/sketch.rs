@@ -0,0 +1,268 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const DEFAULT_RELATIVE_ACCURACY: f64 = 0.01;
+const DEFAULT_MAX_BUCKETS: usize = 2048;
+
+/// A mergeable quantile sketch with bounded memory and relative error, see DDSketch.
+///
+/// Positive values are counted in logarithmic buckets, so a quantile is within
+/// `relative_accuracy` of the exact value until more than `max_buckets` buckets are used,
+/// then the lowest buckets are collapsed.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct QuantileSketch {
+    gamma: f64,
+    max_buckets: usize,
+    buckets: BTreeMap<i32, u64>,
+    zero_count: u64, // number of values <= 0
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl QuantileSketch {
+    pub fn new(relative_accuracy: f64, max_buckets: usize) -> Self {
+        assert!(relative_accuracy > 0.0 && relative_accuracy < 1.0);
+        assert!(max_buckets > 0);
+        QuantileSketch {
+            gamma: (1.0 + relative_accuracy) / (1.0 - relative_accuracy),
+            max_buckets,
+            buckets: BTreeMap::new(),
+            zero_count: 0,
+            count: 0,
+            sum: 0.0,
+            min: 0.0,
+            max: 0.0,
+        }
+    }
+
+    pub fn insert(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        if value > 0.0 {
+            *self.buckets.entry(self.index(value)).or_insert(0) += 1;
+            self.collapse();
+        } else {
+            self.zero_count += 1;
+        }
+
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+
+    /// Merge another sketch, which must have the same relative accuracy.
+    pub fn merge(&mut self, other: &QuantileSketch) {
+        assert!(
+            (self.gamma - other.gamma).abs() < f64::EPSILON,
+            "Can not merge sketches of different relative accuracy"
+        );
+        if other.count == 0 {
+            return;
+        }
+        for (index, count) in other.buckets.iter() {
+            *self.buckets.entry(*index).or_insert(0) += count;
+        }
+        self.collapse();
+        self.zero_count += other.zero_count;
+
+        if self.count == 0 {
+            self.min = other.min;
+            self.max = other.max;
+        } else {
+            self.min = self.min.min(other.min);
+            self.max = self.max.max(other.max);
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+    }
+
+    /// The q-quantile, q in [0, 1], returns None if the sketch is empty.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 || !(0.0..=1.0).contains(&q) {
+            return None;
+        }
+        if q == 0.0 {
+            return Some(self.min);
+        }
+        if q == 1.0 {
+            return Some(self.max);
+        }
+
+        let rank = (q * (self.count - 1) as f64) as u64;
+        let mut seen = self.zero_count;
+        if rank < seen {
+            return Some(self.min.min(0.0));
+        }
+        for (index, count) in self.buckets.iter() {
+            seen += count;
+            if rank < seen {
+                let value = 2.0 * self.gamma.powi(*index) / (self.gamma + 1.0);
+                return Some(value.max(self.min).min(self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.sum / self.count as f64)
+        }
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.min)
+        }
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.max)
+        }
+    }
+
+    fn index(&self, value: f64) -> i32 {
+        (value.ln() / self.gamma.ln()).ceil() as i32
+    }
+
+    // Merge the lowest buckets until there are at most max_buckets buckets
+    fn collapse(&mut self) {
+        while self.buckets.len() > self.max_buckets {
+            let (lowest, count) = self.buckets.pop_first().unwrap();
+            let (_, next) = self.buckets.range_mut(lowest..).next().unwrap();
+            *next += count;
+        }
+    }
+}
+
+impl Default for QuantileSketch {
+    fn default() -> Self {
+        QuantileSketch::new(DEFAULT_RELATIVE_ACCURACY, DEFAULT_MAX_BUCKETS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The exact q-quantile with the same rank as QuantileSketch::quantile
+    fn exact_quantile(sorted: &[f64], q: f64) -> f64 {
+        sorted[(q * (sorted.len() - 1) as f64) as usize]
+    }
+
+    #[test]
+    fn empty_sketch() {
+        let sketch = QuantileSketch::default();
+        assert_eq!(sketch.count(), 0);
+        assert_eq!(sketch.quantile(0.5), None);
+        assert_eq!(sketch.mean(), None);
+        assert_eq!(sketch.min(), None);
+        assert_eq!(sketch.max(), None);
+    }
+
+    #[test]
+    fn quantiles_within_relative_accuracy() {
+        let mut values: Vec<f64> = (1..=10000).map(|i| (i as f64).powf(1.5) / 7.0).collect();
+        let mut sketch = QuantileSketch::new(0.01, 2048);
+        // insertion order doesn't matter
+        for i in 0..values.len() {
+            sketch.insert(values[(i * 7919) % values.len()]);
+        }
+        values.sort_by(|x, y| x.partial_cmp(y).unwrap());
+
+        assert_eq!(sketch.count(), 10000);
+        assert_eq!(sketch.min(), Some(values[0]));
+        assert_eq!(sketch.max(), Some(values[9999]));
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        assert!((sketch.mean().unwrap() - mean).abs() < 1e-9 * mean);
+        for q in [0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99] {
+            let exact = exact_quantile(&values, q);
+            let estimate = sketch.quantile(q).unwrap();
+            assert!(
+                (estimate - exact).abs() <= 0.01 * exact,
+                "q={}, {} != {}",
+                q,
+                estimate,
+                exact
+            );
+        }
+        assert_eq!(sketch.quantile(0.0), Some(values[0]));
+        assert_eq!(sketch.quantile(1.0), Some(values[9999]));
+        assert_eq!(sketch.quantile(1.5), None);
+    }
+
+    #[test]
+    fn merged_sketch_equals_single_sketch() {
+        let values: Vec<f64> = (0..1000).map(|i| 1.0 + ((i * 37) % 101) as f64).collect();
+        let mut single = QuantileSketch::default();
+        let mut parts = vec![QuantileSketch::default(); 4];
+        for (i, value) in values.iter().enumerate() {
+            single.insert(*value);
+            parts[i % 4].insert(*value);
+        }
+        let mut merged = QuantileSketch::default();
+        for part in parts.iter() {
+            merged.merge(part);
+        }
+        merged.merge(&QuantileSketch::default());
+
+        assert_eq!(merged.count(), single.count());
+        assert_eq!(merged.min(), single.min());
+        assert_eq!(merged.max(), single.max());
+        for q in [0.0, 0.1, 0.5, 0.9, 0.99, 1.0] {
+            assert_eq!(merged.quantile(q), single.quantile(q));
+        }
+    }
+
+    #[test]
+    fn zero_values() {
+        let mut sketch = QuantileSketch::default();
+        for value in [0.0, 0.0, f64::NAN, 10.0, 20.0] {
+            sketch.insert(value);
+        }
+        // NaN is ignored
+        assert_eq!(sketch.count(), 4);
+        assert_eq!(sketch.min(), Some(0.0));
+        assert_eq!(sketch.quantile(0.25), Some(0.0));
+        let p90 = sketch.quantile(0.9).unwrap();
+        assert!((p90 - 10.0).abs() <= 0.01 * 10.0);
+        assert_eq!(sketch.mean(), Some(7.5));
+    }
+
+    #[test]
+    fn lowest_buckets_are_collapsed() {
+        let mut sketch = QuantileSketch::new(0.01, 16);
+        for i in 0..1000 {
+            sketch.insert(1.1f64.powi(i));
+        }
+        assert_eq!(sketch.buckets.len(), 16);
+        assert_eq!(sketch.count(), 1000);
+        // high quantiles keep their accuracy
+        let exact = 1.1f64.powi(989);
+        let estimate = sketch.quantile(0.99).unwrap();
+        assert!((estimate - exact).abs() <= 0.01 * exact);
+    }
+}
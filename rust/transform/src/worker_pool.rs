@@ -0,0 +1,204 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/// Backpressure metrics of a worker pool.
+pub struct PoolMetrics {
+    dispatched: AtomicU64,
+    blocked: AtomicU64,
+    queue_lens: Vec<AtomicUsize>,
+}
+
+impl PoolMetrics {
+    fn new(num_workers: usize) -> Self {
+        PoolMetrics {
+            dispatched: AtomicU64::new(0),
+            blocked: AtomicU64::new(0),
+            queue_lens: (0..num_workers).map(|_| AtomicUsize::new(0)).collect(),
+        }
+    }
+
+    /// Number of messages dispatched to workers.
+    pub fn dispatched(&self) -> u64 {
+        self.dispatched.load(Ordering::Relaxed)
+    }
+
+    /// Number of dispatches that blocked because the queue of a worker was full.
+    pub fn blocked(&self) -> u64 {
+        self.blocked.load(Ordering::Relaxed)
+    }
+
+    /// Number of messages waiting in the queue of each worker.
+    pub fn queue_lens(&self) -> Vec<usize> {
+        self.queue_lens
+            .iter()
+            .map(|len| len.load(Ordering::Relaxed))
+            .collect()
+    }
+}
+
+/// A pool of worker threads, each with a bounded queue.
+///
+/// Messages with the same key are always handled by the same worker, in the
+/// order they are dispatched.
+pub struct WorkerPool<T: Send + 'static> {
+    senders: Vec<SyncSender<T>>,
+    handles: Vec<JoinHandle<()>>,
+    metrics: Arc<PoolMetrics>,
+}
+
+impl<T: Send + 'static> WorkerPool<T> {
+    /// Spawn `num_workers` threads, `create_handler` is called once per worker
+    /// with the worker index.
+    pub fn new<F, H>(name: &str, num_workers: usize, capacity: usize, create_handler: F) -> Self
+    where
+        F: Fn(usize) -> H,
+        H: FnMut(T) + Send + 'static,
+    {
+        assert!(num_workers > 0);
+        let metrics = Arc::new(PoolMetrics::new(num_workers));
+        let mut senders = Vec::with_capacity(num_workers);
+        let mut handles = Vec::with_capacity(num_workers);
+        for index in 0..num_workers {
+            let (tx, rx) = sync_channel::<T>(capacity);
+            let mut handler = create_handler(index);
+            let metrics_clone = metrics.clone();
+            let handle = std::thread::Builder::new()
+                .name(format!("{}-{}", name, index))
+                .spawn(move || {
+                    for msg in rx {
+                        metrics_clone.queue_lens[index].fetch_sub(1, Ordering::Relaxed);
+                        handler(msg);
+                    }
+                })
+                .unwrap();
+            senders.push(tx);
+            handles.push(handle);
+        }
+        WorkerPool {
+            senders,
+            handles,
+            metrics,
+        }
+    }
+
+    pub fn num_workers(&self) -> usize {
+        self.senders.len()
+    }
+
+    /// Send a message to the worker of `key`, blocks if its queue is full.
+    pub fn dispatch<K: Hash>(&self, key: &K, msg: T) {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let index = (hasher.finish() % self.senders.len() as u64) as usize;
+
+        self.metrics.queue_lens[index].fetch_add(1, Ordering::Relaxed);
+        self.metrics.dispatched.fetch_add(1, Ordering::Relaxed);
+        match self.senders[index].try_send(msg) {
+            Ok(()) => {}
+            Err(TrySendError::Full(msg)) => {
+                self.metrics.blocked.fetch_add(1, Ordering::Relaxed);
+                self.senders[index]
+                    .send(msg)
+                    .expect("worker thread exited unexpectedly");
+            }
+            Err(TrySendError::Disconnected(_)) => panic!("worker thread exited unexpectedly"),
+        }
+    }
+
+    pub fn metrics(&self) -> &PoolMetrics {
+        &self.metrics
+    }
+
+    /// Wait until all queued messages have been handled.
+    pub fn join(self) {
+        drop(self.senders);
+        for handle in self.handles {
+            handle.join().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{mpsc::channel, Mutex};
+    use std::time::Duration;
+
+    #[test]
+    fn keep_order_per_key() {
+        // (worker index, key, sequence number) in the order they are handled
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let pool = WorkerPool::new("test", 4, 2, |index| {
+            let handled = handled.clone();
+            move |(key, seq): (u32, u32)| handled.lock().unwrap().push((index, key, seq))
+        });
+        assert_eq!(pool.num_workers(), 4);
+        for seq in 0..100 {
+            for key in 0..10 {
+                pool.dispatch(&key, (key, seq));
+            }
+        }
+        assert_eq!(pool.metrics().dispatched(), 1000);
+        pool.join();
+
+        let handled = handled.lock().unwrap();
+        assert_eq!(handled.len(), 1000);
+        let mut last: HashMap<u32, (usize, u32)> = HashMap::new();
+        for (index, key, seq) in handled.iter() {
+            if let Some((last_index, last_seq)) = last.insert(*key, (*index, *seq)) {
+                assert_eq!(last_index, *index, "key {} moved to another worker", key);
+                assert_eq!(last_seq + 1, *seq, "key {} is out of order", key);
+            }
+        }
+        assert_eq!(last.len(), 10);
+        assert!(last.values().all(|(_, seq)| *seq == 99));
+    }
+
+    #[test]
+    fn block_on_full_queue() {
+        let (started_tx, started_rx) = channel::<u32>();
+        let (gate_tx, gate_rx) = channel::<()>();
+        let started_tx = Mutex::new(started_tx);
+        let gate_rx = Mutex::new(Some(gate_rx));
+        let pool = WorkerPool::new("test", 1, 1, |_| {
+            let started_tx = started_tx.lock().unwrap().clone();
+            let gate_rx = gate_rx.lock().unwrap().take().unwrap();
+            // Every message waits for the gate
+            move |msg: u32| {
+                started_tx.send(msg).unwrap();
+                gate_rx.recv().unwrap();
+            }
+        });
+
+        pool.dispatch(&0, 1);
+        assert_eq!(started_rx.recv().unwrap(), 1);
+        assert_eq!(pool.metrics().queue_lens(), [0]);
+        // The worker is busy, the second message waits in the queue
+        pool.dispatch(&0, 2);
+        assert_eq!(pool.metrics().queue_lens(), [1]);
+        assert_eq!(pool.metrics().blocked(), 0);
+
+        // The queue is full, so the third message blocks until the worker takes the second one
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                while pool.metrics().blocked() == 0 {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                for _ in 0..3 {
+                    gate_tx.send(()).unwrap();
+                }
+            });
+            pool.dispatch(&0, 3);
+        });
+        assert_eq!(pool.metrics().blocked(), 1);
+        assert_eq!(pool.metrics().dispatched(), 3);
+
+        pool.join();
+        assert_eq!(started_rx.try_iter().collect::<Vec<u32>>(), [2, 3]);
+    }
+}
@@ -1,6 +1,5 @@
 use log::*;
 use redis::Commands;
-use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     sync::{Arc, Mutex},
@@ -13,12 +12,6 @@ pub struct PriceCache {
     prices: Arc<Mutex<HashMap<String, f64>>>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct MarkPrice {
-    currency: String,
-    price: f64,
-}
-
 impl PriceCache {
     pub fn new(redis_url: &str) -> Self {
         let cache = PriceCache {
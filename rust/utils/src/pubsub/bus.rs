@@ -0,0 +1,100 @@
+use log::*;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use super::{routed::RoutedBus, RedisPubSub, RedisStreams, StreamConfig};
+
+// indexes of buses in create_bus()
+const PUBSUB: usize = 0;
+const STREAMS: usize = 1;
+
+/// A message received from a channel.
+#[derive(Clone, Debug)]
+pub struct BusMessage {
+    pub channel: String,
+    pub payload: String,
+}
+
+/// A transport which carries JSON payloads between binaries.
+pub trait MessageBus: Send + Sync {
+    /// Creates a publisher with its own connection.
+    fn publisher(&self) -> Box<dyn BusPublisher>;
+
+    /// Subscribes to channels, messages published after this call are received.
+    fn subscribe(&self, channels: &[&str]) -> Box<dyn BusSubscriber>;
+}
+
+/// Why a message was not published.
+#[derive(Debug)]
+pub enum PublishError {
+    /// Redis is down, waiting for the next reconnect attempt
+    Disconnected,
+    /// Redis is down and the buffer is full, so the oldest buffered message was dropped
+    BufferFull,
+    Redis(redis::RedisError),
+}
+
+impl std::fmt::Display for PublishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PublishError::Disconnected => write!(f, "Redis is disconnected"),
+            PublishError::BufferFull => {
+                write!(f, "The publish buffer is full, dropped the oldest message")
+            }
+            PublishError::Redis(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for PublishError {}
+
+pub trait BusPublisher: Send {
+    fn publish(&mut self, channel: &str, payload: &str) -> Result<(), PublishError>;
+}
+
+pub trait BusSubscriber: Send {
+    /// Waits for the next message, returns None if nothing arrives within the timeout.
+    ///
+    /// A None timeout blocks until a message arrives.
+    fn next_message(&mut self, timeout: Option<Duration>) -> Option<BusMessage>;
+}
+
+/// Creates the bus selected by the MESSAGE_BUS environment variable.
+///
+/// MESSAGE_BUS is either pubsub(default) or streams, and can be overridden per
+/// channel, e.g., MESSAGE_BUS=pubsub,coinsignal:trade=streams
+pub fn create_bus(redis_url: &str) -> Arc<dyn MessageBus> {
+    let text = if let Ok(text) = std::env::var("MESSAGE_BUS") {
+        text
+    } else {
+        info!("The MESSAGE_BUS environment variable is empty, using pubsub by default");
+        "pubsub".to_string()
+    };
+    let transport = |name: &str| match name.trim() {
+        "pubsub" => PUBSUB,
+        "streams" => STREAMS,
+        _ => panic!("Unknown message bus {}, should be pubsub or streams", name),
+    };
+    let mut default_bus = PUBSUB;
+    let mut routes = HashMap::new();
+    for item in text.split(',').filter(|x| !x.trim().is_empty()) {
+        if let Some((channel, name)) = item.split_once('=') {
+            routes.insert(channel.trim().to_string(), transport(name));
+        } else {
+            default_bus = transport(item);
+        }
+    }
+    routes.retain(|_, bus| *bus != default_bus);
+
+    let mut buses: Vec<Box<dyn MessageBus>> = vec![Box::new(RedisPubSub::new(redis_url))];
+    if default_bus == STREAMS || !routes.is_empty() {
+        buses.push(Box::new(RedisStreams::new(
+            redis_url,
+            StreamConfig::from_env(),
+        )));
+    }
+    if routes.is_empty() {
+        Arc::from(buses.swap_remove(default_bus))
+    } else {
+        Arc::new(RoutedBus::new(buses, default_bus, routes))
+    }
+}
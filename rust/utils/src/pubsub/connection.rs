@@ -0,0 +1,239 @@
+use log::*;
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::{Duration, Instant},
+};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Exponential backoff with jitter.
+struct Backoff {
+    failures: u32,
+    next_attempt: Instant,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Backoff {
+            failures: 0,
+            next_attempt: Instant::now(),
+        }
+    }
+
+    fn failed(&mut self) -> Duration {
+        let delay = INITIAL_BACKOFF
+            .saturating_mul(1 << self.failures.min(16))
+            .min(MAX_BACKOFF);
+        // Half of the delay is random, so that clients don't reconnect at the same time
+        let half = delay.as_millis() as u64 / 2;
+        let jitter = RandomState::new().build_hasher().finish() % (half + 1);
+        let delay = Duration::from_millis(half + jitter);
+        self.failures += 1;
+        self.next_attempt = Instant::now() + delay;
+        delay
+    }
+
+    fn reset(&mut self) {
+        self.failures = 0;
+        self.next_attempt = Instant::now();
+    }
+}
+
+/// Opens connections to Redis, implemented by a fake Redis in tests.
+pub(super) trait Connect: Send {
+    type Connection: Connection;
+
+    fn connect(&self) -> redis::RedisResult<Self::Connection>;
+}
+
+/// Methods of `redis::Connection` used by buses besides commands.
+pub(super) trait Connection: redis::ConnectionLike + Send {
+    fn set_read_timeout(&self, dur: Option<Duration>) -> redis::RedisResult<()>;
+
+    fn send_packed_command(&mut self, cmd: &[u8]) -> redis::RedisResult<()>;
+
+    fn recv_response(&mut self) -> redis::RedisResult<redis::Value>;
+}
+
+impl Connect for redis::Client {
+    type Connection = redis::Connection;
+
+    fn connect(&self) -> redis::RedisResult<redis::Connection> {
+        self.get_connection()
+    }
+}
+
+impl Connection for redis::Connection {
+    fn set_read_timeout(&self, dur: Option<Duration>) -> redis::RedisResult<()> {
+        redis::Connection::set_read_timeout(self, dur)
+    }
+
+    fn send_packed_command(&mut self, cmd: &[u8]) -> redis::RedisResult<()> {
+        redis::Connection::send_packed_command(self, cmd)
+    }
+
+    fn recv_response(&mut self) -> redis::RedisResult<redis::Value> {
+        redis::Connection::recv_response(self)
+    }
+}
+
+/// A Redis connection which is reopened with backoff after it is lost.
+pub(super) struct ManagedConnection<C: Connect = redis::Client> {
+    client: C,
+    connection: Option<C::Connection>,
+    backoff: Backoff,
+}
+
+impl ManagedConnection {
+    pub(super) fn new(redis_url: &str) -> Self {
+        let client = redis::Client::open(redis_url)
+            .unwrap_or_else(|err| panic!("Invalid Redis URL {}, {}", redis_url, err));
+        ManagedConnection::with_client(client)
+    }
+}
+
+impl<C: Connect> ManagedConnection<C> {
+    pub(super) fn with_client(client: C) -> Self {
+        ManagedConnection {
+            client,
+            connection: None,
+            backoff: Backoff::new(),
+        }
+    }
+
+    /// Returns the connection, or None if Redis is down.
+    ///
+    /// A lost connection is reopened once its backoff has elapsed.
+    pub(super) fn get(&mut self) -> Option<&mut C::Connection> {
+        if self.connection.is_none() && Instant::now() >= self.backoff.next_attempt {
+            match self.client.connect() {
+                Ok(connection) => {
+                    if self.backoff.failures > 0 {
+                        info!("Reconnected to Redis");
+                    }
+                    self.backoff.reset();
+                    self.connection = Some(connection);
+                }
+                Err(err) => {
+                    let delay = self.backoff.failed();
+                    warn!("{}, reconnecting in {:?}", err, delay);
+                }
+            }
+        }
+        self.connection.as_mut()
+    }
+
+    /// Drops the connection if the error means it is lost, returns true if so.
+    pub(super) fn check(&mut self, err: &redis::RedisError) -> bool {
+        if err.is_timeout() || !(err.is_io_error() || err.is_connection_dropped()) {
+            return false;
+        }
+        let delay = self.backoff.failed();
+        warn!(
+            "Lost connection to Redis, {}, reconnecting in {:?}",
+            err, delay
+        );
+        self.connection = None;
+        true
+    }
+
+    /// Sleeps until the next reconnect attempt or the deadline, returns false if the deadline is reached.
+    pub(super) fn wait(&self, deadline: Option<Instant>) -> bool {
+        let wake_up = match deadline {
+            Some(deadline) => deadline.min(self.backoff.next_attempt),
+            None => self.backoff.next_attempt,
+        };
+        let now = Instant::now();
+        if wake_up > now {
+            std::thread::sleep(wake_up - now);
+        }
+        deadline.is_none_or(|deadline| Instant::now() < deadline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pubsub::fake_redis::FakeRedis;
+    use redis::Commands;
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let mut backoff = Backoff::new();
+        for failures in 0..20 {
+            let full = INITIAL_BACKOFF
+                .saturating_mul(1 << failures)
+                .min(MAX_BACKOFF);
+            let before = Instant::now();
+            let delay = backoff.failed();
+            assert!(delay >= full / 2 && delay <= full, "{:?} {:?}", delay, full);
+            assert!(backoff.next_attempt >= before + delay);
+        }
+        assert_eq!(backoff.failures, 20);
+        assert!(backoff.failed() <= MAX_BACKOFF);
+
+        backoff.reset();
+        assert_eq!(backoff.failures, 0);
+        assert!(backoff.next_attempt <= Instant::now());
+        assert!(backoff.failed() <= INITIAL_BACKOFF);
+    }
+
+    #[test]
+    fn reconnect_after_backoff() {
+        let redis = FakeRedis::new();
+        let mut connection = ManagedConnection::with_client(redis.clone());
+        assert!(connection.get().is_some());
+        assert_eq!(redis.connects(), 1);
+
+        redis.set_down(true);
+        let err = connection
+            .get()
+            .unwrap()
+            .publish::<&str, &str, i64>("coinsignal:a", "1")
+            .unwrap_err();
+        assert!(connection.check(&err));
+        assert_eq!(connection.backoff.failures, 1);
+        assert!(connection.get().is_none());
+
+        // No attempt is made before the backoff has elapsed
+        redis.set_down(false);
+        assert!(connection.get().is_none());
+        assert_eq!(redis.connects(), 1);
+        assert!(!connection.wait(Some(Instant::now())));
+
+        assert!(connection.wait(None));
+        assert!(connection.get().is_some());
+        assert_eq!(redis.connects(), 2);
+        assert_eq!(connection.backoff.failures, 0);
+    }
+
+    #[test]
+    fn backoff_grows_while_redis_is_down() {
+        let redis = FakeRedis::new();
+        redis.set_down(true);
+        let mut connection = ManagedConnection::with_client(redis.clone());
+        for failures in 1..4 {
+            assert!(connection.get().is_none());
+            assert_eq!(connection.backoff.failures, failures);
+            connection.backoff.next_attempt = Instant::now();
+        }
+        assert_eq!(redis.connects(), 0);
+    }
+
+    #[test]
+    fn keep_connection_on_command_errors() {
+        let redis = FakeRedis::new();
+        let mut connection = ManagedConnection::with_client(redis.clone());
+        let err = redis::cmd("UNKNOWN")
+            .query::<()>(connection.get().unwrap())
+            .unwrap_err();
+        assert!(!connection.check(&err));
+        let timeout = redis::RedisError::from(std::io::Error::from(std::io::ErrorKind::TimedOut));
+        assert!(!connection.check(&timeout));
+        assert!(connection.get().is_some());
+        assert_eq!(redis.connects(), 1);
+        assert_eq!(connection.backoff.failures, 0);
+    }
+}
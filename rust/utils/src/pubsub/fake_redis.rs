@@ -0,0 +1,454 @@
+//! An in-memory Redis which can be taken down, so that reconnects, pub/sub and
+//! streams can be tested without redis-server.
+//!
+//! Only the commands used by buses are supported, and blocking reads return
+//! immediately.
+
+use redis::{RedisError, RedisResult, Value};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::connection::{Connect, Connection};
+
+#[derive(Clone, Default)]
+pub(super) struct FakeRedis {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    down: bool,
+    epoch: u64, // incremented when Redis goes down, connections of older epochs are lost
+    connects: usize,
+    next_connection_id: u64,
+    // connection ID -> (channels, replies)
+    subscribers: HashMap<u64, (Vec<String>, VecDeque<Value>)>,
+    streams: HashMap<String, Stream>,
+}
+
+#[derive(Default)]
+struct Stream {
+    last_id: u64,
+    entries: BTreeMap<u64, String>, // entry ID -> payload
+    groups: HashMap<String, Group>,
+}
+
+#[derive(Default)]
+struct Group {
+    last_delivered: u64,
+    pending: BTreeMap<u64, Pending>,
+}
+
+struct Pending {
+    consumer: String,
+    delivery_time: Instant,
+    delivery_count: usize,
+}
+
+fn io_error(kind: io::ErrorKind) -> RedisError {
+    RedisError::from(io::Error::from(kind))
+}
+
+fn error(text: &str) -> RedisError {
+    redis::parse_redis_value(format!("-{}\r\n", text).as_bytes()).unwrap_err()
+}
+
+fn data(text: &str) -> Value {
+    Value::Data(text.as_bytes().to_vec())
+}
+
+fn format_id(id: u64) -> String {
+    format!("{}-0", id)
+}
+
+// Parses an entry ID, a leading ( makes it exclusive
+fn parse_id(text: &str) -> u64 {
+    match text {
+        "-" => 0,
+        "+" => u64::MAX,
+        _ => {
+            let (exclusive, text) = match text.strip_prefix('(') {
+                Some(text) => (true, text),
+                None => (false, text),
+            };
+            let id = text.split('-').next().unwrap().parse::<u64>().unwrap();
+            if exclusive {
+                id + 1
+            } else {
+                id
+            }
+        }
+    }
+}
+
+// Parses a packed command into its arguments
+fn parse_command(packed: &[u8]) -> Vec<String> {
+    let text = String::from_utf8_lossy(packed);
+    let mut lines = text.split("\r\n");
+    let count = lines.next().unwrap()[1..].parse::<usize>().unwrap();
+    (0..count)
+        .map(|_| {
+            lines.next(); // $length
+            lines.next().unwrap().to_string()
+        })
+        .collect()
+}
+
+impl FakeRedis {
+    pub(super) fn new() -> Self {
+        FakeRedis::default()
+    }
+
+    /// Takes Redis down and drops all connections, or brings it back.
+    pub(super) fn set_down(&self, down: bool) {
+        let mut state = self.state.lock().unwrap();
+        if down && !state.down {
+            state.epoch += 1;
+            state.subscribers.clear();
+        }
+        state.down = down;
+    }
+
+    /// Number of connections opened so far.
+    pub(super) fn connects(&self) -> usize {
+        self.state.lock().unwrap().connects
+    }
+
+    /// Removes all entries of a stream, as MAXLEN does.
+    pub(super) fn trim(&self, key: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(stream) = state.streams.get_mut(key) {
+            stream.entries.clear();
+        }
+    }
+
+    /// Number of entries of a stream.
+    pub(super) fn len(&self, key: &str) -> usize {
+        let state = self.state.lock().unwrap();
+        state.streams.get(key).map_or(0, |x| x.entries.len())
+    }
+
+    /// IDs of pending entries of a group.
+    pub(super) fn pending(&self, key: &str, group: &str) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .streams
+            .get(key)
+            .and_then(|x| x.groups.get(group))
+            .map_or(Vec::new(), |x| {
+                x.pending.keys().map(|id| format_id(*id)).collect()
+            })
+    }
+}
+
+impl Connect for FakeRedis {
+    type Connection = FakeConnection;
+
+    fn connect(&self) -> RedisResult<FakeConnection> {
+        let mut state = self.state.lock().unwrap();
+        if state.down {
+            return Err(io_error(io::ErrorKind::ConnectionRefused));
+        }
+        state.connects += 1;
+        state.next_connection_id += 1;
+        Ok(FakeConnection {
+            id: state.next_connection_id,
+            epoch: state.epoch,
+            state: self.state.clone(),
+        })
+    }
+}
+
+pub(super) struct FakeConnection {
+    id: u64,
+    epoch: u64,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeConnection {
+    // Runs a function on the state, fails if the connection has been lost
+    fn with_state<T>(&self, f: impl FnOnce(&mut State) -> RedisResult<T>) -> RedisResult<T> {
+        let mut state = self.state.lock().unwrap();
+        if state.down || state.epoch != self.epoch {
+            return Err(io_error(io::ErrorKind::ConnectionReset));
+        }
+        f(&mut state)
+    }
+}
+
+impl State {
+    fn execute(&mut self, args: &[String]) -> RedisResult<Value> {
+        match args[0].to_uppercase().as_str() {
+            "PUBLISH" => Ok(Value::Int(self.publish(&args[1], &args[2]))),
+            "XADD" => self.xadd(args),
+            "XGROUP" => self.xgroup(args),
+            "XREADGROUP" => self.xreadgroup(args),
+            "XACK" => self.xack(args),
+            "XPENDING" => self.xpending(args),
+            "XCLAIM" => self.xclaim(args),
+            command => Err(error(&format!("ERR unknown command '{}'", command))),
+        }
+    }
+
+    fn publish(&mut self, channel: &str, payload: &str) -> i64 {
+        let mut count = 0;
+        for (channels, replies) in self.subscribers.values_mut() {
+            if channels.iter().any(|x| x == channel) {
+                replies.push_back(Value::Bulk(vec![
+                    data("message"),
+                    data(channel),
+                    data(payload),
+                ]));
+                count += 1;
+            }
+        }
+        count
+    }
+
+    // XADD key MAXLEN ~ maxlen * payload value
+    fn xadd(&mut self, args: &[String]) -> RedisResult<Value> {
+        let maxlen = args[4].parse::<usize>().unwrap();
+        let stream = self.streams.entry(args[1].clone()).or_default();
+        stream.last_id += 1;
+        stream.entries.insert(stream.last_id, args[7].clone());
+        while stream.entries.len() > maxlen {
+            stream.entries.pop_first();
+        }
+        Ok(data(&format_id(stream.last_id)))
+    }
+
+    // XGROUP CREATE key group $ MKSTREAM
+    fn xgroup(&mut self, args: &[String]) -> RedisResult<Value> {
+        let stream = self.streams.entry(args[2].clone()).or_default();
+        if stream.groups.contains_key(&args[3]) {
+            return Err(error("BUSYGROUP Consumer Group name already exists"));
+        }
+        let group = Group {
+            last_delivered: stream.last_id,
+            pending: BTreeMap::new(),
+        };
+        stream.groups.insert(args[3].clone(), group);
+        Ok(Value::Okay)
+    }
+
+    // XREADGROUP [BLOCK ms] [COUNT count] GROUP group consumer STREAMS key... id...
+    fn xreadgroup(&mut self, args: &[String]) -> RedisResult<Value> {
+        let (mut group_name, mut consumer) = (&args[0], &args[0]);
+        let mut count = usize::MAX;
+        let mut i = 1;
+        loop {
+            match args[i].to_uppercase().as_str() {
+                "STREAMS" => break,
+                "COUNT" => count = args[i + 1].parse().unwrap(),
+                "GROUP" => {
+                    group_name = &args[i + 1];
+                    consumer = &args[i + 2];
+                    i += 1;
+                }
+                _ => (), // BLOCK
+            }
+            i += 2;
+        }
+        let n = (args.len() - i - 1) / 2;
+        let (keys, ids) = args[i + 1..].split_at(n);
+
+        let mut reply = Vec::new();
+        for (key, id) in keys.iter().zip(ids) {
+            let stream = self.streams.get_mut(key);
+            let (entries, group) = match stream {
+                Some(stream) if stream.groups.contains_key(group_name) => {
+                    (&stream.entries, stream.groups.get_mut(group_name).unwrap())
+                }
+                _ => return Err(error("NOGROUP No such key or consumer group")),
+            };
+            let mut items = Vec::new();
+            if id == ">" {
+                // New entries
+                for (entry_id, payload) in entries.range(group.last_delivered + 1..).take(count) {
+                    group.last_delivered = *entry_id;
+                    group.pending.insert(
+                        *entry_id,
+                        Pending {
+                            consumer: consumer.clone(),
+                            delivery_time: Instant::now(),
+                            delivery_count: 1,
+                        },
+                    );
+                    items.push(Value::Bulk(vec![
+                        data(&format_id(*entry_id)),
+                        Value::Bulk(vec![data("payload"), data(payload)]),
+                    ]));
+                }
+                if items.is_empty() {
+                    continue;
+                }
+            } else {
+                // Pending entries of the consumer, without fields if they have been trimmed
+                let pending = group
+                    .pending
+                    .range_mut(parse_id(id) + 1..)
+                    .filter(|(_, x)| x.consumer == *consumer)
+                    .take(count);
+                for (entry_id, x) in pending {
+                    x.delivery_time = Instant::now();
+                    x.delivery_count += 1;
+                    let fields = match entries.get(entry_id) {
+                        Some(payload) => Value::Bulk(vec![data("payload"), data(payload)]),
+                        None => Value::Nil,
+                    };
+                    items.push(Value::Bulk(vec![data(&format_id(*entry_id)), fields]));
+                }
+            }
+            reply.push(Value::Bulk(vec![data(key), Value::Bulk(items)]));
+        }
+        if reply.is_empty() {
+            // Timeout of BLOCK
+            Ok(Value::Nil)
+        } else {
+            Ok(Value::Bulk(reply))
+        }
+    }
+
+    // XACK key group id...
+    fn xack(&mut self, args: &[String]) -> RedisResult<Value> {
+        let group = self
+            .streams
+            .get_mut(&args[1])
+            .and_then(|x| x.groups.get_mut(&args[2]));
+        let count = match group {
+            Some(group) => args[3..]
+                .iter()
+                .filter(|id| group.pending.remove(&parse_id(id)).is_some())
+                .count(),
+            None => 0,
+        };
+        Ok(Value::Int(count as i64))
+    }
+
+    // XPENDING key group IDLE min_idle start end count
+    fn xpending(&mut self, args: &[String]) -> RedisResult<Value> {
+        let group = match self
+            .streams
+            .get(&args[1])
+            .and_then(|x| x.groups.get(&args[2]))
+        {
+            Some(group) => group,
+            None => return Err(error("NOGROUP No such key or consumer group")),
+        };
+        let min_idle = Duration::from_millis(args[4].parse().unwrap());
+        let (start, end) = (parse_id(&args[5]), parse_id(&args[6]));
+        let count = args[7].parse::<usize>().unwrap();
+        let pending = group
+            .pending
+            .range(start..=end)
+            .filter(|(_, x)| x.delivery_time.elapsed() >= min_idle)
+            .take(count)
+            .map(|(id, x)| {
+                Value::Bulk(vec![
+                    data(&format_id(*id)),
+                    data(&x.consumer),
+                    Value::Int(x.delivery_time.elapsed().as_millis() as i64),
+                    Value::Int(x.delivery_count as i64),
+                ])
+            })
+            .collect();
+        Ok(Value::Bulk(pending))
+    }
+
+    // XCLAIM key group consumer min_idle id... JUSTID
+    fn xclaim(&mut self, args: &[String]) -> RedisResult<Value> {
+        let group = match self
+            .streams
+            .get_mut(&args[1])
+            .and_then(|x| x.groups.get_mut(&args[2]))
+        {
+            Some(group) => group,
+            None => return Err(error("NOGROUP No such key or consumer group")),
+        };
+        let min_idle = Duration::from_millis(args[4].parse().unwrap());
+        let mut claimed = Vec::new();
+        for id in args[5..].iter().filter(|x| x.to_uppercase() != "JUSTID") {
+            if let Some(x) = group.pending.get_mut(&parse_id(id)) {
+                if x.delivery_time.elapsed() >= min_idle {
+                    x.consumer = args[3].clone();
+                    x.delivery_time = Instant::now();
+                    claimed.push(data(id));
+                }
+            }
+        }
+        Ok(Value::Bulk(claimed))
+    }
+}
+
+impl redis::ConnectionLike for FakeConnection {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        let args = parse_command(cmd);
+        self.with_state(|state| state.execute(&args))
+    }
+
+    fn req_packed_commands(
+        &mut self,
+        _cmd: &[u8],
+        _offset: usize,
+        _count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        unimplemented!("pipelines are not supported")
+    }
+
+    fn get_db(&self) -> i64 {
+        0
+    }
+
+    fn check_connection(&mut self) -> bool {
+        self.with_state(|_| Ok(())).is_ok()
+    }
+
+    fn is_open(&self) -> bool {
+        self.with_state(|_| Ok(())).is_ok()
+    }
+}
+
+impl Connection for FakeConnection {
+    fn set_read_timeout(&self, _dur: Option<Duration>) -> RedisResult<()> {
+        Ok(())
+    }
+
+    // Only SUBSCRIBE is sent without reading its reply
+    fn send_packed_command(&mut self, cmd: &[u8]) -> RedisResult<()> {
+        let args = parse_command(cmd);
+        assert_eq!(args[0].to_uppercase(), "SUBSCRIBE");
+        let id = self.id;
+        self.with_state(|state| {
+            let channels = args[1..].to_vec();
+            let replies = channels
+                .iter()
+                .enumerate()
+                .map(|(i, channel)| {
+                    Value::Bulk(vec![
+                        data("subscribe"),
+                        data(channel),
+                        Value::Int(i as i64 + 1),
+                    ])
+                })
+                .collect();
+            state.subscribers.insert(id, (channels, replies));
+            Ok(())
+        })
+    }
+
+    // Returns a timeout immediately if there are no replies
+    fn recv_response(&mut self) -> RedisResult<Value> {
+        let id = self.id;
+        self.with_state(|state| {
+            state
+                .subscribers
+                .get_mut(&id)
+                .and_then(|(_, replies)| replies.pop_front())
+                .ok_or_else(|| io_error(io::ErrorKind::TimedOut))
+        })
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use super::{BusMessage, BusPublisher, BusSubscriber, MessageBus, PublishError};
+
+type Subscriptions = Arc<Mutex<HashMap<String, Vec<Sender<BusMessage>>>>>;
+
+/// Channels within a process, so that publishers and subscribers can be tested without Redis.
+#[derive(Clone, Default)]
+pub struct InProcessBus {
+    subscriptions: Subscriptions,
+}
+
+impl InProcessBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl MessageBus for InProcessBus {
+    fn publisher(&self) -> Box<dyn BusPublisher> {
+        Box::new(InProcessPublisher {
+            subscriptions: self.subscriptions.clone(),
+        })
+    }
+
+    fn subscribe(&self, channels: &[&str]) -> Box<dyn BusSubscriber> {
+        let (tx, rx) = mpsc::channel();
+        let mut guard = self.subscriptions.lock().unwrap();
+        for channel in channels {
+            guard
+                .entry(channel.to_string())
+                .or_default()
+                .push(tx.clone());
+        }
+        Box::new(InProcessSubscriber { rx })
+    }
+}
+
+struct InProcessPublisher {
+    subscriptions: Subscriptions,
+}
+
+impl BusPublisher for InProcessPublisher {
+    fn publish(&mut self, channel: &str, payload: &str) -> Result<(), PublishError> {
+        let mut guard = self.subscriptions.lock().unwrap();
+        if let Some(senders) = guard.get_mut(channel) {
+            let msg = BusMessage {
+                channel: channel.to_string(),
+                payload: payload.to_string(),
+            };
+            // Dropped subscribers are removed
+            senders.retain(|tx| tx.send(msg.clone()).is_ok());
+        }
+        Ok(())
+    }
+}
+
+struct InProcessSubscriber {
+    rx: Receiver<BusMessage>,
+}
+
+impl BusSubscriber for InProcessSubscriber {
+    fn next_message(&mut self, timeout: Option<Duration>) -> Option<BusMessage> {
+        if let Some(timeout) = timeout {
+            self.rx.recv_timeout(timeout).ok()
+        } else {
+            self.rx.recv().ok()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pubsub::{Publisher, Subscriber};
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct Msg {
+        seq: u32,
+    }
+
+    #[test]
+    fn publisher_and_subscribers_through_in_process_bus() {
+        let bus = InProcessBus::new();
+        let mut multi = bus.subscribe(&["coinsignal:a", "coinsignal:b"]);
+
+        // Subscriber::run() never returns, so it runs in its own thread
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let (payload_tx, payload_rx) = mpsc::channel();
+        let thread_bus = bus.clone();
+        std::thread::spawn(move || {
+            let mut subscriber = Subscriber::new(
+                &thread_bus,
+                "coinsignal:b",
+                Box::new(move |payload: String| payload_tx.send(payload).unwrap()),
+            );
+            ready_tx.send(()).unwrap();
+            subscriber.run();
+        });
+        ready_rx.recv().unwrap();
+
+        let mut publisher = Publisher::with_buffer(&bus, 0);
+        for seq in 0..3 {
+            publisher.publish("coinsignal:a", &Msg { seq }).unwrap();
+            publisher.publish("coinsignal:b", &Msg { seq }).unwrap();
+            // Nobody subscribes to this channel
+            publisher.publish("coinsignal:c", &Msg { seq }).unwrap();
+        }
+
+        let timeout = Some(Duration::from_secs(1));
+        let received: Vec<(String, String)> = (0..6)
+            .map(|_| {
+                let msg = multi.next_message(timeout).unwrap();
+                (msg.channel, msg.payload)
+            })
+            .collect();
+        let expected: Vec<(String, String)> = (0..3)
+            .flat_map(|seq| {
+                ["coinsignal:a", "coinsignal:b"]
+                    .iter()
+                    .map(move |channel| (channel.to_string(), format!("{{\"seq\":{}}}", seq)))
+            })
+            .collect();
+        assert_eq!(received, expected);
+        assert!(multi
+            .next_message(Some(Duration::from_millis(10)))
+            .is_none());
+
+        let payloads: Vec<String> = (0..3)
+            .map(|_| payload_rx.recv_timeout(Duration::from_secs(1)).unwrap())
+            .collect();
+        assert_eq!(payloads, ["{\"seq\":0}", "{\"seq\":1}", "{\"seq\":2}"]);
+    }
+
+    #[test]
+    fn dropped_subscribers_are_removed() {
+        let bus = InProcessBus::new();
+        let subscriber = bus.subscribe(&["coinsignal:a"]);
+        let mut publisher = bus.publisher();
+        publisher.publish("coinsignal:a", "1").unwrap();
+        drop(subscriber);
+        publisher.publish("coinsignal:a", "2").unwrap();
+        assert!(bus.subscriptions.lock().unwrap()["coinsignal:a"].is_empty());
+    }
+}
@@ -1,5 +1,17 @@
+mod bus;
+mod connection;
+#[cfg(test)]
+mod fake_redis;
+mod in_process;
 mod publisher;
+mod redis_pubsub;
+mod redis_streams;
+mod routed;
 mod subscriber;
 
+pub use bus::{create_bus, BusMessage, BusPublisher, BusSubscriber, MessageBus, PublishError};
+pub use in_process::InProcessBus;
 pub use publisher::Publisher;
+pub use redis_pubsub::RedisPubSub;
+pub use redis_streams::{RedisStreams, StreamConfig};
 pub use subscriber::Subscriber;
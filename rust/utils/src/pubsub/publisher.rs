@@ -1,23 +1,242 @@
-use redis::{self, Commands};
+use log::*;
 use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use super::{BusPublisher, MessageBus, PublishError};
+
+// How often dropped messages are reported while Redis is down
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
 
 pub struct Publisher {
-    connection: redis::Connection,
+    inner: Box<dyn BusPublisher>,
+    // (topic, message) published while Redis is down
+    buffer: VecDeque<(String, String)>,
+    capacity: usize,
+    disconnected: bool,
+    dropped: u64, // messages dropped since the last report
+    last_report_time: Instant,
 }
 
 impl Publisher {
-    pub fn new(redis_url: &str) -> Self {
-        let client = redis::Client::open(redis_url).unwrap();
-        let connection = client.get_connection().unwrap();
+    /// Creates a publisher, which buffers up to PUBLISH_BUFFER_SIZE (default 0)
+    /// messages while Redis is down.
+    pub fn new(bus: &dyn MessageBus) -> Self {
+        let capacity = std::env::var("PUBLISH_BUFFER_SIZE")
+            .map(|x| {
+                x.parse::<usize>()
+                    .unwrap_or_else(|_| panic!("Invalid PUBLISH_BUFFER_SIZE {}", x))
+            })
+            .unwrap_or(0);
+        Self::with_buffer(bus, capacity)
+    }
 
-        Self { connection }
+    /// Creates a publisher which buffers up to `capacity` messages while Redis is down,
+    /// and publishes them in order once it is back.
+    pub fn with_buffer(bus: &dyn MessageBus, capacity: usize) -> Self {
+        Self {
+            inner: bus.publisher(),
+            buffer: VecDeque::new(),
+            capacity,
+            disconnected: false,
+            dropped: 0,
+            last_report_time: Instant::now(),
+        }
     }
 
-    pub fn publish<T>(&mut self, topic: &str, msg: &T)
+    /// Publishes a message, or buffers it while Redis is down.
+    ///
+    /// Outages are logged here, once when Redis goes down and then periodically with the
+    /// number of dropped messages, so callers only need to log `PublishError::Redis`.
+    pub fn publish<T>(&mut self, topic: &str, msg: &T) -> Result<(), PublishError>
     where
         T: Sized + Serialize,
     {
         let msg_str = serde_json::to_string(msg).unwrap();
-        let _ = self.connection.publish::<&str, String, i64>(topic, msg_str);
+        self.flush();
+        let result = if self.buffer.is_empty() {
+            self.inner.publish(topic, &msg_str)
+        } else {
+            // Keep messages in order
+            Err(PublishError::Disconnected)
+        };
+        let result = match result {
+            Err(PublishError::Disconnected) if self.capacity > 0 => {
+                self.buffer.push_back((topic.to_string(), msg_str));
+                if self.buffer.len() > self.capacity {
+                    self.buffer.pop_front();
+                    Err(PublishError::BufferFull)
+                } else {
+                    Ok(())
+                }
+            }
+            _ => result,
+        };
+        self.report(&result);
+        result
+    }
+
+    // Log transitions between connected and disconnected, and count dropped messages
+    fn report(&mut self, result: &Result<(), PublishError>) {
+        let disconnected = match result {
+            Err(PublishError::Disconnected) | Err(PublishError::BufferFull) => {
+                self.dropped += 1;
+                true
+            }
+            Ok(()) => !self.buffer.is_empty(),
+            Err(PublishError::Redis(_)) => self.disconnected,
+        };
+        if disconnected && !self.disconnected {
+            if self.capacity > 0 {
+                error!(
+                    "Redis is disconnected, buffering up to {} messages",
+                    self.capacity
+                );
+            } else {
+                error!("Redis is disconnected, dropping messages until it is back");
+            }
+            self.last_report_time = Instant::now();
+        } else if disconnected && self.last_report_time.elapsed() >= REPORT_INTERVAL {
+            if self.dropped > 0 {
+                warn!(
+                    "Dropped {} messages in the last {:?} while Redis is disconnected",
+                    self.dropped, REPORT_INTERVAL
+                );
+                self.dropped = 0;
+            }
+            self.last_report_time = Instant::now();
+        } else if !disconnected && self.disconnected {
+            info!(
+                "Redis is back, dropped {} messages since the last report",
+                self.dropped
+            );
+            self.dropped = 0;
+        }
+        self.disconnected = disconnected;
+    }
+
+    // Publish buffered messages, stop if Redis is still down
+    fn flush(&mut self) {
+        while let Some((topic, msg_str)) = self.buffer.front() {
+            match self.inner.publish(topic, msg_str) {
+                Err(PublishError::Disconnected) => break,
+                Ok(()) => {
+                    self.buffer.pop_front();
+                }
+                // Other errors won't go away by retrying
+                Err(err) => {
+                    error!("{}", err);
+                    self.buffer.pop_front();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pubsub::{BusSubscriber, InProcessBus};
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    // InProcessBus which can be taken down
+    struct FlakyBus {
+        bus: InProcessBus,
+        down: Arc<AtomicBool>,
+    }
+
+    struct FlakyPublisher {
+        inner: Box<dyn BusPublisher>,
+        down: Arc<AtomicBool>,
+    }
+
+    impl BusPublisher for FlakyPublisher {
+        fn publish(&mut self, channel: &str, payload: &str) -> Result<(), PublishError> {
+            if self.down.load(Ordering::SeqCst) {
+                Err(PublishError::Disconnected)
+            } else {
+                self.inner.publish(channel, payload)
+            }
+        }
+    }
+
+    impl MessageBus for FlakyBus {
+        fn publisher(&self) -> Box<dyn BusPublisher> {
+            Box::new(FlakyPublisher {
+                inner: self.bus.publisher(),
+                down: self.down.clone(),
+            })
+        }
+
+        fn subscribe(&self, channels: &[&str]) -> Box<dyn BusSubscriber> {
+            self.bus.subscribe(channels)
+        }
+    }
+
+    fn flaky_bus() -> FlakyBus {
+        FlakyBus {
+            bus: InProcessBus::new(),
+            down: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn received(subscriber: &mut Box<dyn BusSubscriber>) -> Vec<String> {
+        std::iter::from_fn(|| subscriber.next_message(Some(Duration::from_millis(10))))
+            .map(|msg| msg.payload)
+            .collect()
+    }
+
+    #[test]
+    fn messages_are_dropped_without_buffer() {
+        let bus = flaky_bus();
+        let mut subscriber = bus.subscribe(&["coinsignal:a"]);
+        let mut publisher = Publisher::with_buffer(&bus, 0);
+        publisher.publish("coinsignal:a", &1).unwrap();
+
+        bus.down.store(true, Ordering::SeqCst);
+        for i in 2..5 {
+            assert!(matches!(
+                publisher.publish("coinsignal:a", &i),
+                Err(PublishError::Disconnected)
+            ));
+        }
+        assert!(publisher.disconnected);
+        assert_eq!(publisher.dropped, 3);
+
+        bus.down.store(false, Ordering::SeqCst);
+        publisher.publish("coinsignal:a", &5).unwrap();
+        assert!(!publisher.disconnected);
+        assert_eq!(publisher.dropped, 0);
+        assert_eq!(received(&mut subscriber), ["1", "5"]);
+    }
+
+    #[test]
+    fn buffered_messages_are_published_in_order() {
+        let bus = flaky_bus();
+        let mut subscriber = bus.subscribe(&["coinsignal:a"]);
+        let mut publisher = Publisher::with_buffer(&bus, 3);
+
+        bus.down.store(true, Ordering::SeqCst);
+        for i in 1..4 {
+            publisher.publish("coinsignal:a", &i).unwrap();
+        }
+        // The oldest message is dropped
+        assert!(matches!(
+            publisher.publish("coinsignal:a", &4),
+            Err(PublishError::BufferFull)
+        ));
+        assert!(publisher.disconnected);
+        assert_eq!(publisher.dropped, 1);
+        assert!(received(&mut subscriber).is_empty());
+
+        bus.down.store(false, Ordering::SeqCst);
+        publisher.publish("coinsignal:a", &5).unwrap();
+        assert!(!publisher.disconnected);
+        assert_eq!(received(&mut subscriber), ["2", "3", "4", "5"]);
     }
 }
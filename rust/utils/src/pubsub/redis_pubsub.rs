@@ -0,0 +1,263 @@
+use log::*;
+use redis::{self, Commands};
+use std::time::{Duration, Instant};
+
+use super::{
+    connection::{Connect, Connection, ManagedConnection},
+    BusMessage, BusPublisher, BusSubscriber, MessageBus, PublishError,
+};
+
+/// Redis pub/sub, messages published while a subscriber is offline are lost.
+pub struct RedisPubSub {
+    redis_url: String,
+}
+
+impl RedisPubSub {
+    pub fn new(redis_url: &str) -> Self {
+        Self {
+            redis_url: redis_url.to_string(),
+        }
+    }
+}
+
+impl MessageBus for RedisPubSub {
+    fn publisher(&self) -> Box<dyn BusPublisher> {
+        Box::new(RedisPubSubPublisher {
+            connection: ManagedConnection::new(&self.redis_url),
+        })
+    }
+
+    fn subscribe(&self, channels: &[&str]) -> Box<dyn BusSubscriber> {
+        Box::new(RedisPubSubSubscriber::new(
+            ManagedConnection::new(&self.redis_url),
+            channels,
+        ))
+    }
+}
+
+struct RedisPubSubPublisher<C: Connect> {
+    connection: ManagedConnection<C>,
+}
+
+impl<C: Connect> BusPublisher for RedisPubSubPublisher<C> {
+    fn publish(&mut self, channel: &str, payload: &str) -> Result<(), PublishError> {
+        let connection = self.connection.get().ok_or(PublishError::Disconnected)?;
+        match connection.publish::<&str, &str, i64>(channel, payload) {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                if self.connection.check(&err) {
+                    Err(PublishError::Disconnected)
+                } else {
+                    Err(PublishError::Redis(err))
+                }
+            }
+        }
+    }
+}
+
+struct RedisPubSubSubscriber<C: Connect> {
+    connection: ManagedConnection<C>,
+    channels: Vec<String>,
+    subscribed: bool, // whether the current connection has subscribed
+}
+
+impl<C: Connect> RedisPubSubSubscriber<C> {
+    fn new(connection: ManagedConnection<C>, channels: &[&str]) -> Self {
+        let mut subscriber = RedisPubSubSubscriber {
+            connection,
+            channels: channels.iter().map(|x| x.to_string()).collect(),
+            subscribed: false,
+        };
+        subscriber.subscribe();
+        subscriber
+    }
+
+    // Subscribe on a new connection, returns false if Redis is down
+    fn subscribe(&mut self) -> bool {
+        if self.subscribed {
+            return true;
+        }
+        let connection = match self.connection.get() {
+            Some(connection) => connection,
+            None => return false,
+        };
+        // redis::PubSub borrows the connection, so the subscriber sends
+        // SUBSCRIBE and reads replies on the connection directly
+        let command = redis::cmd("SUBSCRIBE")
+            .arg(&self.channels)
+            .get_packed_command();
+        match connection.send_packed_command(&command) {
+            Ok(()) => self.subscribed = true,
+            Err(err) => {
+                if !self.connection.check(&err) {
+                    error!("{}", err);
+                }
+            }
+        }
+        self.subscribed
+    }
+}
+
+impl<C: Connect> BusSubscriber for RedisPubSubSubscriber<C> {
+    fn next_message(&mut self, timeout: Option<Duration>) -> Option<BusMessage> {
+        let deadline = timeout.map(|x| Instant::now() + x);
+        loop {
+            if !self.subscribe() {
+                // Redis is down
+                if self.connection.wait(deadline) {
+                    continue;
+                } else {
+                    return None;
+                }
+            }
+
+            let read_timeout = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    // zero is not a valid timeout
+                    Some((deadline - now).max(Duration::from_millis(1)))
+                }
+                None => None,
+            };
+            let connection = self.connection.get().unwrap();
+            let result = connection
+                .set_read_timeout(read_timeout)
+                .and_then(|_| connection.recv_response());
+            match result {
+                // replies of SUBSCRIBE are skipped
+                Ok(value) => {
+                    if let Some(msg) = redis::Msg::from_value(&value) {
+                        match msg.get_payload::<String>() {
+                            Ok(payload) => {
+                                return Some(BusMessage {
+                                    channel: msg.get_channel_name().to_string(),
+                                    payload,
+                                })
+                            }
+                            Err(err) => error!("{}", err),
+                        }
+                    }
+                }
+                Err(err) => {
+                    if err.is_timeout() {
+                        return None;
+                    }
+                    if self.connection.check(&err) {
+                        // Subscribe again after reconnecting
+                        self.subscribed = false;
+                    } else {
+                        error!("{}", err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pubsub::{fake_redis::FakeRedis, Publisher};
+
+    const DATA: &str = "coinsignal:data";
+    const TIMEOUT: Option<Duration> = Some(Duration::from_secs(2));
+
+    // Redis pub/sub over a fake Redis
+    struct FakeBus(FakeRedis);
+
+    impl MessageBus for FakeBus {
+        fn publisher(&self) -> Box<dyn BusPublisher> {
+            Box::new(RedisPubSubPublisher {
+                connection: ManagedConnection::with_client(self.0.clone()),
+            })
+        }
+
+        fn subscribe(&self, channels: &[&str]) -> Box<dyn BusSubscriber> {
+            Box::new(RedisPubSubSubscriber::new(
+                ManagedConnection::with_client(self.0.clone()),
+                channels,
+            ))
+        }
+    }
+
+    fn received(subscriber: &mut Box<dyn BusSubscriber>) -> Vec<String> {
+        std::iter::from_fn(|| subscriber.next_message(Some(Duration::from_millis(10))))
+            .map(|msg| msg.payload)
+            .collect()
+    }
+
+    // Publish until the publisher has reconnected with backoff
+    fn publish_until_connected(publisher: &mut Publisher, payload: &str) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while publisher.publish(DATA, &payload).is_err() {
+            assert!(Instant::now() < deadline, "The publisher didn't reconnect");
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn resubscribe_after_reconnect() {
+        let bus = FakeBus(FakeRedis::new());
+        let mut subscriber = bus.subscribe(&[DATA]);
+        let mut publisher = Publisher::with_buffer(&bus, 0);
+        publisher.publish(DATA, &"before").unwrap();
+        assert_eq!(received(&mut subscriber), ["\"before\""]);
+
+        bus.0.set_down(true);
+        assert!(subscriber
+            .next_message(Some(Duration::from_millis(10)))
+            .is_none());
+        bus.0.set_down(false);
+        // Reconnects once the backoff has elapsed and subscribes again
+        assert!(subscriber.next_message(TIMEOUT).is_none());
+        assert_eq!(bus.0.connects(), 3);
+
+        publish_until_connected(&mut publisher, "after");
+        assert_eq!(received(&mut subscriber), ["\"after\""]);
+    }
+
+    #[test]
+    fn publish_fails_while_disconnected_without_buffer() {
+        let bus = FakeBus(FakeRedis::new());
+        let mut publisher = Publisher::with_buffer(&bus, 0);
+        publisher.publish(DATA, &0).unwrap();
+
+        bus.0.set_down(true);
+        for i in 1..10 {
+            assert!(matches!(
+                publisher.publish(DATA, &i),
+                Err(PublishError::Disconnected)
+            ));
+        }
+
+        bus.0.set_down(false);
+        publish_until_connected(&mut publisher, "10");
+    }
+
+    #[test]
+    fn buffered_messages_arrive_in_order_after_reconnect() {
+        let bus = FakeBus(FakeRedis::new());
+        let mut subscriber = bus.subscribe(&[DATA]);
+        let mut publisher = Publisher::with_buffer(&bus, 100);
+        publisher.publish(DATA, &0).unwrap();
+        assert_eq!(received(&mut subscriber), ["0"]);
+
+        bus.0.set_down(true);
+        for i in 1..6 {
+            publisher.publish(DATA, &i).unwrap();
+        }
+        assert!(subscriber
+            .next_message(Some(Duration::from_millis(10)))
+            .is_none());
+
+        bus.0.set_down(false);
+        assert!(subscriber.next_message(TIMEOUT).is_none());
+        // Buffered messages are flushed by the next publish once the backoff has elapsed
+        std::thread::sleep(Duration::from_secs(1));
+        publisher.publish(DATA, &6).unwrap();
+        assert_eq!(received(&mut subscriber), ["1", "2", "3", "4", "5", "6"]);
+    }
+}
@@ -0,0 +1,642 @@
+use log::*;
+use redis::{
+    self,
+    streams::{
+        StreamClaimOptions, StreamId, StreamMaxlen, StreamPendingCountReply, StreamReadOptions,
+        StreamReadReply,
+    },
+    Commands,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use super::{
+    connection::{Connect, Connection, ManagedConnection},
+    BusMessage, BusPublisher, BusSubscriber, MessageBus, PublishError,
+};
+
+const DEFAULT_MAXLEN: usize = 100000;
+const DEFAULT_CLAIM_IDLE: u64 = 60; // in seconds
+const READ_COUNT: usize = 256; // max number of messages per XREADGROUP
+const CLAIM_INTERVAL: Duration = Duration::from_secs(10); // how often to check pending entries
+const KEY_PREFIX: &str = "stream:"; // so that streams don't collide with keys named after channels
+
+/// Configuration of Redis Streams.
+#[derive(Clone, Debug)]
+pub struct StreamConfig {
+    pub default_maxlen: usize,
+    // channel -> approximate max length
+    pub maxlen: HashMap<String, usize>,
+    pub group: String,    // consumer group, one per binary
+    pub consumer: String, // consumer name within the group
+    // pending entries idle for longer than this are claimed from other consumers
+    pub claim_idle: Duration,
+}
+
+impl StreamConfig {
+    /// Read the configuration from environment variables.
+    ///
+    /// * STREAM_MAXLEN, e.g., STREAM_MAXLEN=100000,coinsignal:trade=1000000
+    /// * STREAM_GROUP, defaults to the name of the executable
+    /// * STREAM_CONSUMER, defaults to the group name
+    /// * STREAM_CLAIM_IDLE, in seconds
+    pub fn from_env() -> Self {
+        let mut default_maxlen = DEFAULT_MAXLEN;
+        let mut maxlen = HashMap::new();
+        if let Ok(text) = std::env::var("STREAM_MAXLEN") {
+            for item in text.split(',').filter(|x| !x.trim().is_empty()) {
+                let parse = |x: &str| {
+                    x.trim()
+                        .parse::<usize>()
+                        .unwrap_or_else(|_| panic!("Invalid stream max length {}", item))
+                };
+                if let Some((channel, len)) = item.split_once('=') {
+                    maxlen.insert(channel.trim().to_string(), parse(len));
+                } else {
+                    default_maxlen = parse(item);
+                }
+            }
+        } else {
+            info!(
+                "The STREAM_MAXLEN environment variable is empty, using {} by default",
+                DEFAULT_MAXLEN
+            );
+        }
+
+        let group = if let Ok(group) = std::env::var("STREAM_GROUP") {
+            group
+        } else {
+            let group = std::env::current_exe()
+                .ok()
+                .and_then(|path| path.file_stem().map(|x| x.to_string_lossy().to_string()))
+                .unwrap_or_else(|| "coinsignal".to_string());
+            info!(
+                "The STREAM_GROUP environment variable is empty, using {} by default",
+                group
+            );
+            group
+        };
+        let consumer = std::env::var("STREAM_CONSUMER").unwrap_or_else(|_| group.clone());
+        let claim_idle = std::env::var("STREAM_CLAIM_IDLE")
+            .map(|x| {
+                x.parse::<u64>()
+                    .unwrap_or_else(|_| panic!("Invalid STREAM_CLAIM_IDLE {}", x))
+            })
+            .unwrap_or(DEFAULT_CLAIM_IDLE);
+
+        StreamConfig {
+            default_maxlen,
+            maxlen,
+            group,
+            consumer,
+            claim_idle: Duration::from_secs(claim_idle),
+        }
+    }
+
+    pub fn maxlen(&self, channel: &str) -> usize {
+        self.maxlen
+            .get(channel)
+            .copied()
+            .unwrap_or(self.default_maxlen)
+    }
+}
+
+/// Redis Streams with consumer groups, each channel is a stream under the
+/// key `stream:<channel>` and each message is an entry with a payload field.
+///
+/// Delivery is at-least-once. Each binary reads through its own consumer
+/// group, so messages published while it is down are delivered after it
+/// restarts. Messages are acknowledged once the next message is requested,
+/// and entries left pending by a dead consumer are claimed after they have
+/// been idle for `claim_idle`. After a reconnect, messages delivered but not
+/// yet acknowledged are read again.
+pub struct RedisStreams {
+    redis_url: String,
+    config: StreamConfig,
+}
+
+impl RedisStreams {
+    pub fn new(redis_url: &str, config: StreamConfig) -> Self {
+        Self {
+            redis_url: redis_url.to_string(),
+            config,
+        }
+    }
+}
+
+impl MessageBus for RedisStreams {
+    fn publisher(&self) -> Box<dyn BusPublisher> {
+        Box::new(RedisStreamsPublisher {
+            connection: ManagedConnection::new(&self.redis_url),
+            config: self.config.clone(),
+        })
+    }
+
+    fn subscribe(&self, channels: &[&str]) -> Box<dyn BusSubscriber> {
+        Box::new(RedisStreamsSubscriber::new(
+            ManagedConnection::new(&self.redis_url),
+            &self.config,
+            channels,
+        ))
+    }
+}
+
+fn stream_key(channel: &str) -> String {
+    format!("{}{}", KEY_PREFIX, channel)
+}
+
+struct RedisStreamsPublisher<C: Connect> {
+    connection: ManagedConnection<C>,
+    config: StreamConfig,
+}
+
+impl<C: Connect> BusPublisher for RedisStreamsPublisher<C> {
+    fn publish(&mut self, channel: &str, payload: &str) -> Result<(), PublishError> {
+        let connection = self.connection.get().ok_or(PublishError::Disconnected)?;
+        match connection.xadd_maxlen::<String, &str, &str, &str, String>(
+            stream_key(channel),
+            StreamMaxlen::Approx(self.config.maxlen(channel)),
+            "*",
+            &[("payload", payload)],
+        ) {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                if self.connection.check(&err) {
+                    Err(PublishError::Disconnected)
+                } else {
+                    Err(PublishError::Redis(err))
+                }
+            }
+        }
+    }
+}
+
+struct RedisStreamsSubscriber<C: Connect> {
+    connection: ManagedConnection<C>,
+    ready: bool, // whether groups have been created on the current connection
+    group: String,
+    consumer: String,
+    claim_idle: Duration,
+    channels: Vec<String>,
+    keys: Vec<String>, // stream keys of channels
+    // > for new entries, otherwise the last pending entry read after a (re)connect
+    read_ids: Vec<String>,
+    // (channel index, entry ID, message)
+    buffer: VecDeque<(usize, String, BusMessage)>,
+    // IDs of delivered entries per channel, acknowledged before the next read
+    unacked: Vec<Vec<String>>,
+    // where the next scan of the pending entries list starts per channel
+    claim_starts: Vec<String>,
+    last_claim_time: Instant,
+}
+
+impl<C: Connect> RedisStreamsSubscriber<C> {
+    fn new(connection: ManagedConnection<C>, config: &StreamConfig, channels: &[&str]) -> Self {
+        let mut subscriber = RedisStreamsSubscriber {
+            connection,
+            ready: false,
+            group: config.group.clone(),
+            consumer: config.consumer.clone(),
+            claim_idle: config.claim_idle,
+            channels: channels.iter().map(|x| x.to_string()).collect(),
+            keys: channels.iter().map(|x| stream_key(x)).collect(),
+            read_ids: vec!["0".to_string(); channels.len()],
+            buffer: VecDeque::new(),
+            unacked: vec![Vec::new(); channels.len()],
+            claim_starts: vec!["-".to_string(); channels.len()],
+            last_claim_time: Instant::now(),
+        };
+        subscriber.connect();
+        subscriber
+    }
+
+    // Prepare a new connection, returns false if Redis is down
+    fn connect(&mut self) -> bool {
+        if self.ready {
+            return true;
+        }
+        if self.connection.get().is_none() {
+            return false;
+        }
+        self.create_groups();
+        if self.ready {
+            // Entries delivered to this consumer before a restart or
+            // reconnect are read first, buffered ones are among them
+            self.buffer.clear();
+            self.read_ids = vec!["0".to_string(); self.channels.len()];
+        }
+        self.ready
+    }
+
+    fn create_groups(&mut self) {
+        self.ready = true;
+        for key in self.keys.iter() {
+            let connection = match self.connection.get() {
+                Some(connection) => connection,
+                None => {
+                    self.ready = false;
+                    return;
+                }
+            };
+            // Only new entries are delivered to a new group
+            if let Err(err) =
+                connection.xgroup_create_mkstream::<&str, &str, &str, ()>(key, &self.group, "$")
+            {
+                if self.connection.check(&err) {
+                    self.ready = false;
+                    return;
+                } else if err.code() != Some("BUSYGROUP") {
+                    error!("{}", err);
+                }
+            }
+        }
+    }
+
+    fn ack(&mut self) {
+        for (key, ids) in self.keys.iter().zip(self.unacked.iter_mut()) {
+            if ids.is_empty() {
+                continue;
+            }
+            let connection = match self.connection.get() {
+                Some(connection) => connection,
+                None => return,
+            };
+            match connection.xack::<&str, &str, String, i64>(key, &self.group, ids) {
+                Ok(_) => ids.clear(),
+                Err(err) => {
+                    if self.connection.check(&err) {
+                        self.ready = false;
+                        return;
+                    }
+                    error!("{}", err);
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, index: usize, entry: StreamId) {
+        if let Some(payload) = entry.get::<String>("payload") {
+            self.buffer.push_back((
+                index,
+                entry.id,
+                BusMessage {
+                    channel: self.channels[index].clone(),
+                    payload,
+                },
+            ));
+        } else {
+            // Pending entries trimmed by MAXLEN have no fields
+            self.unacked[index].push(entry.id);
+        }
+    }
+
+    fn read(&mut self, block: Duration) {
+        let connection = match self.connection.get() {
+            Some(connection) => connection,
+            None => return,
+        };
+        let options = StreamReadOptions::default()
+            .group(&self.group, &self.consumer)
+            .block(block.as_millis().max(1) as usize)
+            .count(READ_COUNT);
+        let (keys, read_ids) = (&self.keys, &self.read_ids);
+        // The read timeout of the connection is longer than BLOCK
+        let reply = connection
+            .set_read_timeout(Some(block + Duration::from_secs(10)))
+            .and_then(|_| {
+                connection.xread_options::<String, String, Option<StreamReadReply>>(
+                    keys, read_ids, &options,
+                )
+            });
+        match reply {
+            Ok(Some(reply)) => {
+                for stream in reply.keys {
+                    let index = self.keys.iter().position(|x| *x == stream.key).unwrap();
+                    if self.read_ids[index] != ">" {
+                        // All pending entries have been read
+                        self.read_ids[index] = match stream.ids.last() {
+                            Some(entry) => entry.id.clone(),
+                            None => ">".to_string(),
+                        };
+                    }
+                    for entry in stream.ids {
+                        self.push(index, entry);
+                    }
+                }
+            }
+            Ok(None) => (), // timeout
+            Err(err) => {
+                if self.connection.check(&err) {
+                    self.ready = false;
+                } else if err.code() == Some("NOGROUP") {
+                    // The stream was deleted, e.g., by FLUSHALL
+                    warn!("{}", err);
+                    self.ready = false;
+                } else {
+                    error!("{}", err);
+                    std::thread::sleep(Duration::from_secs(1));
+                }
+            }
+        }
+    }
+
+    // Claim entries left pending by other consumers for longer than claim_idle, scanning one
+    // page of the pending entries list per channel, returns true if there are more pages.
+    //
+    // Claimed entries are read again from the pending entries of this consumer, where entries
+    // trimmed by MAXLEN show up without fields.
+    fn claim(&mut self) -> bool {
+        let min_idle = self.claim_idle.as_millis() as usize;
+        for index in 0..self.channels.len() {
+            let key = &self.keys[index];
+            let consumer = &self.consumer;
+            let connection = match self.connection.get() {
+                Some(connection) => connection,
+                None => return false,
+            };
+            // XPENDING with IDLE requires Redis 6.2
+            let pending = redis::cmd("XPENDING")
+                .arg(key)
+                .arg(&self.group)
+                .arg("IDLE")
+                .arg(min_idle)
+                .arg(&self.claim_starts[index])
+                .arg("+")
+                .arg(READ_COUNT)
+                .query::<StreamPendingCountReply>(connection);
+            let ids: Vec<String> = match pending {
+                Ok(pending) => {
+                    // Continue after the last entry of a full page
+                    self.claim_starts[index] = match pending.ids.last() {
+                        Some(last) if pending.ids.len() >= READ_COUNT => format!("({}", last.id),
+                        _ => "-".to_string(),
+                    };
+                    pending
+                        .ids
+                        .into_iter()
+                        .filter(|x| x.consumer != *consumer)
+                        .map(|x| x.id)
+                        .collect()
+                }
+                Err(err) => {
+                    if self.connection.check(&err) {
+                        self.ready = false;
+                        return false;
+                    }
+                    error!("{}", err);
+                    self.claim_starts[index] = "-".to_string();
+                    continue;
+                }
+            };
+            if ids.is_empty() {
+                continue;
+            }
+            match connection.xclaim_options::<&str, &str, &str, usize, String, Vec<String>>(
+                key,
+                &self.group,
+                &self.consumer,
+                min_idle,
+                &ids,
+                StreamClaimOptions::default().with_justid(),
+            ) {
+                Ok(claimed) => {
+                    if !claimed.is_empty() {
+                        warn!(
+                            "Claimed {} pending messages of {} from dead consumers",
+                            claimed.len(),
+                            self.channels[index]
+                        );
+                        self.read_ids[index] = "0".to_string();
+                    }
+                }
+                Err(err) => {
+                    if self.connection.check(&err) {
+                        self.ready = false;
+                        return false;
+                    }
+                    error!("{}", err);
+                }
+            }
+        }
+        self.claim_starts.iter().any(|x| x != "-")
+    }
+}
+
+impl<C: Connect> BusSubscriber for RedisStreamsSubscriber<C> {
+    fn next_message(&mut self, timeout: Option<Duration>) -> Option<BusMessage> {
+        let deadline = timeout.map(|x| Instant::now() + x);
+        loop {
+            if let Some((index, id, msg)) = self.buffer.pop_front() {
+                self.unacked[index].push(id);
+                return Some(msg);
+            }
+
+            if !self.connect() {
+                // Redis is down
+                if self.connection.wait(deadline) {
+                    continue;
+                } else {
+                    return None;
+                }
+            }
+            // All messages returned so far have been processed
+            self.ack();
+
+            if self.ready && self.last_claim_time.elapsed() >= CLAIM_INTERVAL {
+                // The remaining pages are scanned after claimed entries have been read
+                if !self.claim() {
+                    self.last_claim_time = Instant::now();
+                }
+            }
+
+            // Wake up periodically to claim pending entries
+            let block = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    (deadline - now).min(CLAIM_INTERVAL)
+                }
+                None => CLAIM_INTERVAL,
+            };
+            if self.ready {
+                self.read(block);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pubsub::fake_redis::FakeRedis;
+
+    const CHANNEL: &str = "coinsignal:test";
+    const KEY: &str = "stream:coinsignal:test";
+    const GROUP: &str = "test";
+    const TIMEOUT: Option<Duration> = Some(Duration::from_secs(2));
+
+    fn subscribe(
+        redis: &FakeRedis,
+        consumer: &str,
+        claim_idle: Duration,
+    ) -> RedisStreamsSubscriber<FakeRedis> {
+        let config = StreamConfig {
+            default_maxlen: 100000,
+            maxlen: HashMap::new(),
+            group: GROUP.to_string(),
+            consumer: consumer.to_string(),
+            claim_idle,
+        };
+        RedisStreamsSubscriber::new(
+            ManagedConnection::with_client(redis.clone()),
+            &config,
+            &[CHANNEL],
+        )
+    }
+
+    fn publish(redis: &FakeRedis, payloads: impl Iterator<Item = String>) {
+        let mut publisher = RedisStreamsPublisher {
+            connection: ManagedConnection::with_client(redis.clone()),
+            config: StreamConfig {
+                default_maxlen: 100000,
+                maxlen: HashMap::new(),
+                group: GROUP.to_string(),
+                consumer: "publisher".to_string(),
+                claim_idle: Duration::from_secs(60),
+            },
+        };
+        for payload in payloads {
+            publisher.publish(CHANNEL, &payload).unwrap();
+        }
+    }
+
+    fn receive(subscriber: &mut RedisStreamsSubscriber<FakeRedis>, count: usize) -> Vec<String> {
+        (0..count)
+            .map(|_| {
+                let msg = subscriber.next_message(TIMEOUT).unwrap();
+                assert_eq!(msg.channel, CHANNEL);
+                msg.payload
+            })
+            .collect()
+    }
+
+    fn is_empty(subscriber: &mut RedisStreamsSubscriber<FakeRedis>) -> bool {
+        subscriber
+            .next_message(Some(Duration::from_millis(10)))
+            .is_none()
+    }
+
+    #[test]
+    fn publish_to_prefixed_key() {
+        let redis = FakeRedis::new();
+        publish(&redis, (0..3).map(|i| i.to_string()));
+        assert_eq!(redis.len(KEY), 3);
+        assert_eq!(redis.len(CHANNEL), 0);
+    }
+
+    #[test]
+    fn processed_messages_are_acked() {
+        let redis = FakeRedis::new();
+        let mut subscriber = subscribe(&redis, "c1", Duration::from_secs(60));
+        publish(&redis, (0..3).map(|i| i.to_string()));
+
+        assert_eq!(receive(&mut subscriber, 3), ["0", "1", "2"]);
+        // Messages are acked once all buffered ones have been processed
+        assert_eq!(redis.pending(KEY, GROUP).len(), 3);
+        assert!(is_empty(&mut subscriber));
+        assert!(redis.pending(KEY, GROUP).is_empty());
+
+        // Nothing is delivered again after a restart
+        let mut subscriber = subscribe(&redis, "c1", Duration::from_secs(60));
+        assert!(is_empty(&mut subscriber));
+    }
+
+    #[test]
+    fn unacked_messages_are_redelivered_after_restart() {
+        let redis = FakeRedis::new();
+        let mut subscriber = subscribe(&redis, "c1", Duration::from_secs(60));
+        publish(&redis, (0..3).map(|i| i.to_string()));
+        assert_eq!(receive(&mut subscriber, 3), ["0", "1", "2"]);
+        assert!(is_empty(&mut subscriber));
+
+        publish(&redis, (3..5).map(|i| i.to_string()));
+        // "3" is being processed and "4" is buffered when the subscriber dies
+        assert_eq!(receive(&mut subscriber, 1), ["3"]);
+        drop(subscriber);
+
+        // Published while the subscriber is down
+        publish(&redis, std::iter::once("5".to_string()));
+        let mut subscriber = subscribe(&redis, "c1", Duration::from_secs(60));
+        assert_eq!(receive(&mut subscriber, 3), ["3", "4", "5"]);
+        assert!(is_empty(&mut subscriber));
+        assert!(redis.pending(KEY, GROUP).is_empty());
+    }
+
+    #[test]
+    fn processed_messages_are_acked_after_reconnect() {
+        let redis = FakeRedis::new();
+        let mut subscriber = subscribe(&redis, "c1", Duration::from_secs(60));
+        publish(&redis, (0..3).map(|i| i.to_string()));
+        assert_eq!(receive(&mut subscriber, 3), ["0", "1", "2"]);
+
+        // Acks fail while Redis is down, and are sent again after reconnecting
+        redis.set_down(true);
+        assert!(is_empty(&mut subscriber));
+        assert_eq!(redis.pending(KEY, GROUP).len(), 3);
+        redis.set_down(false);
+        assert!(subscriber.next_message(TIMEOUT).is_none());
+        assert!(redis.pending(KEY, GROUP).is_empty());
+
+        publish(&redis, std::iter::once("3".to_string()));
+        assert_eq!(receive(&mut subscriber, 1), ["3"]);
+        assert!(is_empty(&mut subscriber));
+        assert!(redis.pending(KEY, GROUP).is_empty());
+    }
+
+    #[test]
+    fn pending_entries_trimmed_by_maxlen_are_acked() {
+        let redis = FakeRedis::new();
+        let mut subscriber = subscribe(&redis, "c1", Duration::from_secs(60));
+        publish(&redis, (0..3).map(|i| i.to_string()));
+        assert_eq!(receive(&mut subscriber, 1), ["0"]);
+        drop(subscriber);
+        assert_eq!(redis.pending(KEY, GROUP).len(), 3);
+
+        // All pending entries are trimmed while the subscriber is down
+        redis.trim(KEY);
+        publish(&redis, std::iter::once("3".to_string()));
+
+        let mut subscriber = subscribe(&redis, "c1", Duration::from_secs(60));
+        assert_eq!(receive(&mut subscriber, 1), ["3"]);
+        assert!(is_empty(&mut subscriber));
+        assert!(redis.pending(KEY, GROUP).is_empty());
+    }
+
+    #[test]
+    fn claim_pages_of_idle_entries_of_dead_consumers() {
+        let redis = FakeRedis::new();
+        let mut subscriber = subscribe(&redis, "c1", Duration::from_millis(0));
+
+        // More than a page of XPENDING is left pending by a dead consumer
+        publish(&redis, (0..300).map(|i| i.to_string()));
+        let options = StreamReadOptions::default().group(GROUP, "dead").count(300);
+        let reply = redis
+            .connect()
+            .unwrap()
+            .xread_options::<&str, &str, StreamReadReply>(&[KEY], &[">"], &options)
+            .unwrap();
+        assert_eq!(reply.keys[0].ids.len(), 300);
+
+        // Pending entries are checked now rather than in CLAIM_INTERVAL
+        subscriber.last_claim_time = Instant::now() - CLAIM_INTERVAL;
+        let expected: Vec<String> = (0..300).map(|i| i.to_string()).collect();
+        assert_eq!(receive(&mut subscriber, 300), expected);
+        assert!(subscriber.claim_starts.iter().all(|x| x == "-"));
+        assert!(is_empty(&mut subscriber));
+        assert!(redis.pending(KEY, GROUP).is_empty());
+    }
+}
@@ -0,0 +1,295 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use super::{BusMessage, BusPublisher, BusSubscriber, MessageBus, PublishError};
+
+// How often threads of MergedSubscriber check whether it has been dropped
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone)]
+struct Routes {
+    default_bus: usize,
+    // channel -> index of the bus
+    routes: HashMap<String, usize>,
+}
+
+impl Routes {
+    fn route(&self, channel: &str) -> usize {
+        self.routes
+            .get(channel)
+            .copied()
+            .unwrap_or(self.default_bus)
+    }
+}
+
+/// Routes each channel to one of several buses.
+pub(super) struct RoutedBus {
+    buses: Vec<Box<dyn MessageBus>>,
+    routes: Routes,
+}
+
+impl RoutedBus {
+    pub(super) fn new(
+        buses: Vec<Box<dyn MessageBus>>,
+        default_bus: usize,
+        routes: HashMap<String, usize>,
+    ) -> Self {
+        Self {
+            buses,
+            routes: Routes {
+                default_bus,
+                routes,
+            },
+        }
+    }
+}
+
+impl MessageBus for RoutedBus {
+    fn publisher(&self) -> Box<dyn BusPublisher> {
+        Box::new(RoutedPublisher {
+            publishers: self.buses.iter().map(|bus| bus.publisher()).collect(),
+            routes: self.routes.clone(),
+        })
+    }
+
+    fn subscribe(&self, channels: &[&str]) -> Box<dyn BusSubscriber> {
+        let mut groups: Vec<Vec<&str>> = vec![Vec::new(); self.buses.len()];
+        for channel in channels {
+            groups[self.routes.route(channel)].push(channel);
+        }
+        let subscribers: Vec<Box<dyn BusSubscriber>> = groups
+            .iter()
+            .enumerate()
+            .filter(|(_, channels)| !channels.is_empty())
+            .map(|(index, channels)| self.buses[index].subscribe(channels))
+            .collect();
+        if subscribers.len() == 1 {
+            subscribers.into_iter().next().unwrap()
+        } else {
+            Box::new(MergedSubscriber::new(subscribers))
+        }
+    }
+}
+
+struct RoutedPublisher {
+    publishers: Vec<Box<dyn BusPublisher>>,
+    routes: Routes,
+}
+
+impl BusPublisher for RoutedPublisher {
+    fn publish(&mut self, channel: &str, payload: &str) -> Result<(), PublishError> {
+        self.publishers[self.routes.route(channel)].publish(channel, payload)
+    }
+}
+
+/// Reads subscribers of different buses in background threads.
+///
+/// A thread doesn't read the next message until the message it forwarded has
+/// been processed, i.e., next_message() is called again, so that the
+/// acknowledgement semantics of each bus are kept. Threads are stopped when
+/// the subscriber is dropped.
+struct MergedSubscriber {
+    rx: Receiver<(usize, BusMessage)>,
+    // wakes up the thread which forwarded the last message
+    done_txs: Vec<Sender<()>>,
+    last: Option<usize>,
+    stop: Arc<AtomicBool>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl MergedSubscriber {
+    fn new(subscribers: Vec<Box<dyn BusSubscriber>>) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut done_txs = Vec::new();
+        let mut handles = Vec::new();
+        for (index, mut subscriber) in subscribers.into_iter().enumerate() {
+            let tx = tx.clone();
+            let (done_tx, done_rx) = mpsc::channel::<()>();
+            let stop = stop.clone();
+            done_txs.push(done_tx);
+            handles.push(std::thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    if let Some(msg) = subscriber.next_message(Some(POLL_INTERVAL)) {
+                        if tx.send((index, msg)).is_err() || done_rx.recv().is_err() {
+                            break;
+                        }
+                    }
+                }
+            }));
+        }
+        Self {
+            rx,
+            done_txs,
+            last: None,
+            stop,
+            handles,
+        }
+    }
+}
+
+impl BusSubscriber for MergedSubscriber {
+    fn next_message(&mut self, timeout: Option<Duration>) -> Option<BusMessage> {
+        if let Some(index) = self.last.take() {
+            let _ = self.done_txs[index].send(());
+        }
+        let (index, msg) = if let Some(timeout) = timeout {
+            self.rx.recv_timeout(timeout).ok()?
+        } else {
+            self.rx.recv().ok()?
+        };
+        self.last = Some(index);
+        Some(msg)
+    }
+}
+
+impl Drop for MergedSubscriber {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // Threads waiting for their forwarded messages to be processed exit once done_txs are dropped
+        self.done_txs.clear();
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pubsub::InProcessBus;
+
+    const TIMEOUT: Option<Duration> = Some(Duration::from_secs(1));
+
+    // coinsignal:b goes to the second bus, other channels to the first one
+    fn routed_bus() -> (RoutedBus, InProcessBus, InProcessBus) {
+        let first = InProcessBus::new();
+        let second = InProcessBus::new();
+        let routes = HashMap::from([("coinsignal:b".to_string(), 1)]);
+        let bus = RoutedBus::new(
+            vec![Box::new(first.clone()), Box::new(second.clone())],
+            0,
+            routes,
+        );
+        (bus, first, second)
+    }
+
+    #[test]
+    fn channels_are_published_to_their_routes() {
+        let (bus, first, second) = routed_bus();
+        let mut first_subscriber = first.subscribe(&["coinsignal:a", "coinsignal:b"]);
+        let mut second_subscriber = second.subscribe(&["coinsignal:a", "coinsignal:b"]);
+
+        let mut publisher = bus.publisher();
+        publisher.publish("coinsignal:a", "1").unwrap();
+        publisher.publish("coinsignal:b", "2").unwrap();
+
+        let msg = first_subscriber.next_message(TIMEOUT).unwrap();
+        assert_eq!(
+            (msg.channel.as_str(), msg.payload.as_str()),
+            ("coinsignal:a", "1")
+        );
+        let msg = second_subscriber.next_message(TIMEOUT).unwrap();
+        assert_eq!(
+            (msg.channel.as_str(), msg.payload.as_str()),
+            ("coinsignal:b", "2")
+        );
+        let timeout = Some(Duration::from_millis(10));
+        assert!(first_subscriber.next_message(timeout).is_none());
+        assert!(second_subscriber.next_message(timeout).is_none());
+    }
+
+    #[test]
+    fn merged_subscriber_reads_all_buses() {
+        let (bus, _, _) = routed_bus();
+        let mut subscriber = bus.subscribe(&["coinsignal:a", "coinsignal:b"]);
+        let mut publisher = bus.publisher();
+        for i in 0..3 {
+            publisher.publish("coinsignal:a", &i.to_string()).unwrap();
+            publisher.publish("coinsignal:b", &i.to_string()).unwrap();
+        }
+
+        let mut received: HashMap<String, Vec<String>> = HashMap::new();
+        for _ in 0..6 {
+            let msg = subscriber.next_message(TIMEOUT).unwrap();
+            received.entry(msg.channel).or_default().push(msg.payload);
+        }
+        // Messages of a channel stay in order, channels of different buses interleave
+        for channel in ["coinsignal:a", "coinsignal:b"] {
+            assert_eq!(received[channel], ["0", "1", "2"]);
+        }
+        assert!(subscriber
+            .next_message(Some(Duration::from_millis(10)))
+            .is_none());
+    }
+
+    #[test]
+    fn merged_subscriber_waits_until_messages_are_processed() {
+        let first = InProcessBus::new();
+        let second = InProcessBus::new();
+        let mut subscriber = MergedSubscriber::new(vec![
+            first.subscribe(&["coinsignal:a"]),
+            second.subscribe(&["coinsignal:b"]),
+        ]);
+        let mut publisher = first.publisher();
+        publisher.publish("coinsignal:a", "1").unwrap();
+        publisher.publish("coinsignal:a", "2").unwrap();
+
+        // The second message is not forwarded until the first one has been processed
+        let msg = subscriber.next_message(TIMEOUT).unwrap();
+        assert_eq!(msg.payload, "1");
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(subscriber.rx.try_recv().is_err());
+
+        let msg = subscriber.next_message(TIMEOUT).unwrap();
+        assert_eq!(msg.payload, "2");
+    }
+
+    // Sets a flag when the inner subscriber is dropped
+    struct DropFlag(Box<dyn BusSubscriber>, Arc<AtomicBool>);
+
+    impl BusSubscriber for DropFlag {
+        fn next_message(&mut self, timeout: Option<Duration>) -> Option<BusMessage> {
+            self.0.next_message(timeout)
+        }
+    }
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.1.store(true, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn merged_subscriber_stops_threads_when_dropped() {
+        let bus = InProcessBus::new();
+        let dropped = [
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicBool::new(false)),
+        ];
+        let subscriber = MergedSubscriber::new(vec![
+            Box::new(DropFlag(
+                bus.subscribe(&["coinsignal:a"]),
+                dropped[0].clone(),
+            )),
+            Box::new(DropFlag(
+                bus.subscribe(&["coinsignal:b"]),
+                dropped[1].clone(),
+            )),
+        ]);
+        // One thread waits for its message to be processed, the other one for the next message
+        bus.publisher().publish("coinsignal:a", "1").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+
+        drop(subscriber);
+        assert!(dropped.iter().all(|x| x.load(Ordering::Relaxed)));
+    }
+}
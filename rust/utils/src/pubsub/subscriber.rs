@@ -1,28 +1,23 @@
+use super::{BusSubscriber, MessageBus};
+
 pub struct Subscriber {
-    redis_url: String,
-    channel: String,
+    inner: Box<dyn BusSubscriber>,
     on_msg: Box<dyn FnMut(String)>,
 }
 
 impl Subscriber {
-    pub fn new(redis_url: &str, channel: &str, on_msg: Box<dyn FnMut(String)>) -> Self {
+    pub fn new(bus: &dyn MessageBus, channel: &str, on_msg: Box<dyn FnMut(String)>) -> Self {
         Self {
-            redis_url: redis_url.to_string(),
-            channel: channel.to_string(),
+            inner: bus.subscribe(&[channel]),
             on_msg,
         }
     }
 
     pub fn run(&mut self) {
-        let client = redis::Client::open(self.redis_url.as_str()).unwrap();
-        let mut connection = client.get_connection().unwrap();
-        let mut pubsub = connection.as_pubsub();
-        pubsub.subscribe(self.channel.as_str()).unwrap();
-
         loop {
-            let msg = pubsub.get_message().unwrap();
-            let payload: String = msg.get_payload().unwrap();
-            (self.on_msg)(payload);
+            if let Some(msg) = self.inner.next_message(None) {
+                (self.on_msg)(msg.payload);
+            }
         }
     }
 }
@@ -0,0 +1,113 @@
+mod common;
+
+use common::RedisServer;
+use redis::Commands;
+use std::time::{Duration, Instant};
+use utils::pubsub::{BusSubscriber, MessageBus, PublishError, Publisher, RedisPubSub};
+
+const DATA: &str = "coinsignal:data";
+const READY: &str = "coinsignal:ready";
+
+// Publish to READY until the subscriber receives it, i.e., it has (re)subscribed
+fn wait_subscribed(server: &RedisServer, subscriber: &mut Box<dyn BusSubscriber>) {
+    let deadline = Instant::now() + Duration::from_secs(30);
+    loop {
+        assert!(
+            Instant::now() < deadline,
+            "The subscriber didn't resubscribe"
+        );
+        if let Ok(mut conn) = redis::Client::open(server.url()).unwrap().get_connection() {
+            let _ = conn.publish::<&str, &str, i64>(READY, "ready");
+        }
+        if let Some(msg) = subscriber.next_message(Some(Duration::from_millis(200))) {
+            if msg.channel == READY {
+                return;
+            }
+        }
+    }
+}
+
+// Payloads of the next messages of DATA, late messages of READY are skipped
+fn receive(subscriber: &mut Box<dyn BusSubscriber>, count: usize) -> Vec<String> {
+    let mut payloads = Vec::new();
+    while payloads.len() < count {
+        let msg = subscriber
+            .next_message(Some(Duration::from_secs(5)))
+            .unwrap();
+        if msg.channel == DATA {
+            payloads.push(msg.payload);
+        }
+    }
+    payloads
+}
+
+// Publish until Redis is back, as the publisher reconnects with backoff
+fn publish_until_connected(publisher: &mut Publisher, payload: &str) {
+    let deadline = Instant::now() + Duration::from_secs(60);
+    while publisher.publish(DATA, &payload).is_err() {
+        assert!(Instant::now() < deadline, "The publisher didn't reconnect");
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+#[ignore]
+fn subscriber_resubscribes_after_restart() {
+    let mut server = RedisServer::start();
+    let bus = RedisPubSub::new(&server.url());
+    let mut subscriber = bus.subscribe(&[DATA, READY]);
+    let mut publisher = Publisher::with_buffer(&bus, 0);
+    wait_subscribed(&server, &mut subscriber);
+    publisher.publish(DATA, &"before").unwrap();
+    assert_eq!(receive(&mut subscriber, 1), ["\"before\""]);
+
+    server.restart();
+    wait_subscribed(&server, &mut subscriber);
+    publish_until_connected(&mut publisher, "after");
+    assert_eq!(receive(&mut subscriber, 1), ["\"after\""]);
+}
+
+#[test]
+#[ignore]
+fn publish_fails_while_disconnected_without_buffer() {
+    let mut server = RedisServer::start();
+    let bus = RedisPubSub::new(&server.url());
+    let mut publisher = Publisher::with_buffer(&bus, 0);
+    publisher.publish(DATA, &0).unwrap();
+
+    server.stop();
+    for i in 1..10 {
+        assert!(matches!(
+            publisher.publish(DATA, &i),
+            Err(PublishError::Disconnected)
+        ));
+    }
+
+    server.restart();
+    publish_until_connected(&mut publisher, "10");
+}
+
+#[test]
+#[ignore]
+fn buffered_messages_arrive_in_order_after_restart() {
+    let mut server = RedisServer::start();
+    let bus = RedisPubSub::new(&server.url());
+    let mut subscriber = bus.subscribe(&[DATA, READY]);
+    let mut publisher = Publisher::with_buffer(&bus, 100);
+    wait_subscribed(&server, &mut subscriber);
+    publisher.publish(DATA, &0).unwrap();
+    assert_eq!(receive(&mut subscriber, 1), ["0"]);
+
+    server.stop();
+    for i in 1..6 {
+        publisher.publish(DATA, &i).unwrap();
+    }
+
+    server.restart();
+    // Messages published to Redis pub/sub before resubscribing are lost
+    wait_subscribed(&server, &mut subscriber);
+    // Buffered messages are flushed by the next publish once the backoff has elapsed
+    std::thread::sleep(Duration::from_secs(2));
+    publisher.publish(DATA, &6).unwrap();
+    assert_eq!(receive(&mut subscriber, 6), ["1", "2", "3", "4", "5", "6"]);
+}
@@ -0,0 +1,192 @@
+mod common;
+
+use common::RedisServer;
+use redis::{
+    streams::{StreamPendingReply, StreamReadOptions, StreamReadReply},
+    Commands,
+};
+use std::{collections::HashMap, time::Duration};
+use utils::pubsub::{BusSubscriber, MessageBus, RedisStreams, StreamConfig};
+
+const CHANNEL: &str = "coinsignal:test";
+const KEY: &str = "stream:coinsignal:test";
+const GROUP: &str = "test";
+const TIMEOUT: Option<Duration> = Some(Duration::from_secs(1));
+
+fn streams(server: &RedisServer, consumer: &str, claim_idle: Duration) -> RedisStreams {
+    RedisStreams::new(
+        &server.url(),
+        StreamConfig {
+            default_maxlen: 100000,
+            maxlen: HashMap::new(),
+            group: GROUP.to_string(),
+            consumer: consumer.to_string(),
+            claim_idle,
+        },
+    )
+}
+
+fn publish(streams: &RedisStreams, payloads: impl Iterator<Item = String>) {
+    let mut publisher = streams.publisher();
+    for payload in payloads {
+        publisher.publish(CHANNEL, &payload).unwrap();
+    }
+}
+
+fn receive(
+    subscriber: &mut Box<dyn BusSubscriber>,
+    count: usize,
+    timeout: Duration,
+) -> Vec<String> {
+    (0..count)
+        .map(|_| subscriber.next_message(Some(timeout)).unwrap().payload)
+        .collect()
+}
+
+fn pending_count(server: &RedisServer) -> usize {
+    let mut conn = server.connection();
+    match conn
+        .xpending::<&str, &str, StreamPendingReply>(KEY, GROUP)
+        .unwrap()
+    {
+        StreamPendingReply::Data(data) => data.count,
+        StreamPendingReply::Empty => 0,
+    }
+}
+
+#[test]
+#[ignore]
+fn processed_messages_are_acked() {
+    let server = RedisServer::start();
+    let streams = streams(&server, "c1", Duration::from_secs(60));
+    let mut subscriber = streams.subscribe(&[CHANNEL]);
+    publish(&streams, (0..3).map(|i| i.to_string()));
+
+    assert_eq!(
+        receive(&mut subscriber, 3, Duration::from_secs(1)),
+        ["0", "1", "2"]
+    );
+    // Messages are acked once all buffered ones have been processed
+    assert_eq!(pending_count(&server), 3);
+    assert!(subscriber
+        .next_message(Some(Duration::from_millis(100)))
+        .is_none());
+    assert_eq!(pending_count(&server), 0);
+
+    // Nothing is delivered again after a restart
+    drop(subscriber);
+    let mut subscriber = streams.subscribe(&[CHANNEL]);
+    assert!(subscriber
+        .next_message(Some(Duration::from_millis(100)))
+        .is_none());
+}
+
+#[test]
+#[ignore]
+fn unacked_messages_are_redelivered_after_restart() {
+    let server = RedisServer::start();
+    let streams = streams(&server, "c1", Duration::from_secs(60));
+    let mut subscriber = streams.subscribe(&[CHANNEL]);
+    publish(&streams, (0..3).map(|i| i.to_string()));
+    assert_eq!(
+        receive(&mut subscriber, 3, Duration::from_secs(1)),
+        ["0", "1", "2"]
+    );
+    assert!(subscriber
+        .next_message(Some(Duration::from_millis(100)))
+        .is_none());
+
+    publish(&streams, (3..5).map(|i| i.to_string()));
+    // "3" is being processed and "4" is buffered when the subscriber dies
+    assert_eq!(subscriber.next_message(TIMEOUT).unwrap().payload, "3");
+    drop(subscriber);
+
+    // Published while the subscriber is down
+    publish(&streams, std::iter::once("5".to_string()));
+    let mut subscriber = streams.subscribe(&[CHANNEL]);
+    assert_eq!(
+        receive(&mut subscriber, 3, Duration::from_secs(1)),
+        ["3", "4", "5"]
+    );
+    assert!(subscriber
+        .next_message(Some(Duration::from_millis(100)))
+        .is_none());
+    assert_eq!(pending_count(&server), 0);
+}
+
+#[test]
+#[ignore]
+fn pending_entries_trimmed_by_maxlen_are_acked() {
+    let server = RedisServer::start();
+    let streams = streams(&server, "c1", Duration::from_secs(60));
+    let mut subscriber = streams.subscribe(&[CHANNEL]);
+    publish(&streams, (0..3).map(|i| i.to_string()));
+    assert_eq!(subscriber.next_message(TIMEOUT).unwrap().payload, "0");
+    drop(subscriber);
+    assert_eq!(pending_count(&server), 3);
+
+    // All pending entries are trimmed while the subscriber is down
+    let mut conn = server.connection();
+    redis::cmd("XTRIM")
+        .arg(KEY)
+        .arg("MAXLEN")
+        .arg(0)
+        .query::<usize>(&mut conn)
+        .unwrap();
+    publish(&streams, std::iter::once("3".to_string()));
+
+    let mut subscriber = streams.subscribe(&[CHANNEL]);
+    assert_eq!(subscriber.next_message(TIMEOUT).unwrap().payload, "3");
+    assert!(subscriber
+        .next_message(Some(Duration::from_millis(100)))
+        .is_none());
+    assert_eq!(pending_count(&server), 0);
+}
+
+#[test]
+#[ignore]
+fn idle_entries_of_dead_consumers_are_claimed() {
+    let server = RedisServer::start();
+    let streams_c2 = streams(&server, "c2", Duration::from_millis(0));
+    let mut subscriber = streams_c2.subscribe(&[CHANNEL]);
+
+    // More than a page of XPENDING is left pending by a dead consumer
+    publish(&streams_c2, (0..300).map(|i| i.to_string()));
+    let mut conn = server.connection();
+    let options = StreamReadOptions::default().group(GROUP, "dead").count(300);
+    let reply = conn
+        .xread_options::<&str, &str, StreamReadReply>(&[KEY], &[">"], &options)
+        .unwrap();
+    assert_eq!(reply.keys[0].ids.len(), 300);
+
+    // Pending entries are checked every 10 seconds
+    let payloads = receive(&mut subscriber, 300, Duration::from_secs(20));
+    let expected: Vec<String> = (0..300).map(|i| i.to_string()).collect();
+    assert_eq!(payloads, expected);
+    assert!(subscriber
+        .next_message(Some(Duration::from_millis(100)))
+        .is_none());
+    assert_eq!(pending_count(&server), 0);
+}
+
+#[test]
+#[ignore]
+fn streams_do_not_collide_with_keys_named_after_channels() {
+    let server = RedisServer::start();
+    let mut conn = server.connection();
+    conn.set::<&str, &str, ()>(CHANNEL, "snapshot").unwrap();
+
+    let streams = streams(&server, "c1", Duration::from_secs(60));
+    let mut subscriber = streams.subscribe(&[CHANNEL]);
+    publish(&streams, (0..3).map(|i| i.to_string()));
+    let msg = subscriber.next_message(TIMEOUT).unwrap();
+    assert_eq!(msg.channel, CHANNEL);
+    assert_eq!(msg.payload, "0");
+    assert_eq!(
+        receive(&mut subscriber, 2, Duration::from_secs(1)),
+        ["1", "2"]
+    );
+
+    assert_eq!(conn.get::<&str, String>(CHANNEL).unwrap(), "snapshot");
+    assert_eq!(conn.xlen::<&str, usize>(KEY).unwrap(), 3);
+}